@@ -1,15 +1,18 @@
 use actix_web::{
     delete, get,
-    http::StatusCode,
+    http::{header, StatusCode},
     middleware::Logger,
     patch, post, web,
-    web::{Data, Json, Path, Query},
-    App, Either, HttpResponse, HttpServer, Responder, ResponseError,
+    web::{Bytes, Data, Json, Path, Query},
+    App, CustomizeResponder, Either, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
 };
-use log::debug;
+use log::{debug, error};
 use simplelog::{Config, LevelFilter, SimpleLogger};
 use std::{fmt::Display, sync::Arc};
-use todo_logic::{IdentifyableTodoItem, Pagination, TodoItem, TodoStore, TodoStoreError, UpdateTodoItem};
+use todo_logic::{
+    autosave, batch, etag, trash, AsyncTodoStore, Batch, DeleteParams, IdentifyableTodoItem, JobQueue, NewTodoList,
+    Pagination, RevertTo, TodoFilter, TodoItem, TodoPatch, TodoStore, TodoStoreConfig, TodoStoreError, Validators,
+};
 use tokio::sync::RwLock;
 
 /// Type for our shared state
@@ -23,8 +26,25 @@ async fn main() -> std::io::Result<()> {
     // compatible logger, but for this example we'll use simplelog.
     SimpleLogger::init(LevelFilter::Debug, Config::default()).unwrap();
 
-    // Create shared data store
-    let state = Data::new(Db::default());
+    // Create shared data store. The storage backend is selected with the
+    // TODO_BACKEND environment variable (e.g. `sqlite:todos.db`). Set TODO_RESTORE=true
+    // to restore the todo items that have been persisted before.
+    let config = TodoStoreConfig::from_env().map_err(std::io::Error::other)?;
+    let store = config
+        .open()
+        .await
+        .map_err(std::io::Error::other)?
+        .with_jobs(JobQueue::start(config.jobs, &config.hooks));
+    let db = Db::new(RwLock::new(store));
+    if let Some(autosave) = config.autosave {
+        // Persist in the background so that we do not depend on clients calling /todos/persist
+        autosave::spawn(db.clone(), autosave, |e| error!("Autosave failed: {e:?}"));
+    }
+    if let Some(retention) = config.trash_retention {
+        // Deleted items are purged once they have been in the trash for TODO_TRASH_RETENTION seconds
+        trash::spawn(db.clone(), retention, |e| error!("Purging the trash failed: {e:?}"));
+    }
+    let state = Data::new(db);
 
     HttpServer::new(move || {
         App::new()
@@ -38,9 +58,25 @@ async fn main() -> std::io::Result<()> {
             // and without macros (route).
             .service(get_todos)
             .service(add_todo)
+            .service(apply_batch)
+            // Registered before delete_todo, otherwise "trash" would be parsed as an id
+            .service(get_trash)
+            .service(empty_trash)
+            .service(purge_todo)
+            .service(restore_todo)
             .service(delete_todo)
             .service(update_todo)
+            .service(get_children)
+            .service(get_history)
+            .service(revert_todo)
             .service(persist)
+            .service(get_jobs)
+            .service(get_lists)
+            .service(add_list)
+            .service(get_list)
+            .service(rename_list)
+            .service(delete_list)
+            .service(get_list_todos)
             .route("/todos/{id}", web::get().to(get_todo))
     })
     // Start the server.
@@ -59,26 +95,144 @@ async fn main() -> std::io::Result<()> {
 /// Also note the Responder trait (https://actix.rs/docs/extractors/).
 /// Actix comes with a lot of built-in responders, but you can also
 /// implement your own.
+///
+/// Clients that pass a `cursor` (empty for the first page) get a page envelope,
+/// all others get a plain list. In both cases, the Link header points to the next page.
+/// Polling clients can send the ETag or Last-Modified header of their copy and get
+/// 304 Not Modified if nothing has changed since.
 #[get("/todos")]
-async fn get_todos(pagination: Query<Pagination>, db: Data<Db>) -> impl Responder {
+async fn get_todos(
+    req: HttpRequest,
+    pagination: Query<Pagination>,
+    filter: Query<TodoFilter>,
+    db: Data<Db>,
+) -> Result<impl Responder, AppError> {
     let todos = db.read().await;
-    let Query(pagination) = pagination;
-    Json(todos.get_todos(pagination))
+    let validators = todos.validators();
+    if is_not_modified(&req, &validators) {
+        return Ok(not_modified(&validators));
+    }
+    let (Query(pagination), Query(filter)) = (pagination, filter);
+    let page = todos.get_todos(pagination.clone(), &filter)?;
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((header::ETAG, validators.etag.clone()))
+        .insert_header((header::LAST_MODIFIED, validators.last_modified_header()));
+    if let Some(link) = pagination.next_link("/todos", &filter, &page) {
+        response.insert_header((header::LINK, link));
+    }
+    Ok(if pagination.is_cursor_mode() {
+        response.json(page)
+    } else {
+        response.json(page.items)
+    })
 }
 
 /// If a method returns different return types, Actix offers
 /// the Either enum (https://actix.rs/docs/handlers/).
-type ItemOrStatus = Either<Json<IdentifyableTodoItem>, HttpResponse>;
+///
+/// Items are sent with ETag and Last-Modified headers. CustomizeResponder adds
+/// headers to any responder.
+type ItemOrStatus = Either<CustomizeResponder<Json<IdentifyableTodoItem>>, HttpResponse>;
+
+/// Respond with a todo item and its validators
+fn item_response(item: IdentifyableTodoItem) -> ItemOrStatus {
+    let validators = item.validators();
+    Either::Left(
+        Json(item)
+            .customize()
+            .insert_header((header::ETAG, validators.etag.clone()))
+            .insert_header((header::LAST_MODIFIED, validators.last_modified_header())),
+    )
+}
+
+/// Whether the copy of the client is still up to date
+fn is_not_modified(req: &HttpRequest, validators: &Validators) -> bool {
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+    validators.not_modified(header(header::IF_NONE_MATCH), header(header::IF_MODIFIED_SINCE))
+}
+
+/// Empty 304 response, the validators tell the client which copy is current
+fn not_modified(validators: &Validators) -> HttpResponse {
+    HttpResponse::NotModified()
+        .insert_header((header::ETAG, validators.etag.clone()))
+        .insert_header((header::LAST_MODIFIED, validators.last_modified_header()))
+        .finish()
+}
+
+/// Version expected by the If-Match header, `None` if there is no such header
+fn if_match(req: &HttpRequest) -> Result<Option<u64>, AppError> {
+    match req.headers().get(header::IF_MATCH) {
+        Some(value) => Ok(etag::expected_version(value.to_str().unwrap_or_default())?),
+        None => Ok(None),
+    }
+}
 
 /// Get a single todo item
-async fn get_todo(id: Path<usize>, db: Data<Db>) -> ItemOrStatus {
+async fn get_todo(req: HttpRequest, id: Path<usize>, db: Data<Db>) -> Result<ItemOrStatus, AppError> {
     let todos = db.read().await;
-    if let Some(item) = todos.get_todo(*id) {
-        Either::Left(Json(item.clone()))
+    if let Some(item) = todos.get_todo(*id)? {
+        let validators = item.validators();
+        if is_not_modified(&req, &validators) {
+            return Ok(Either::Right(not_modified(&validators)));
+        }
+        Ok(item_response(item))
     } else {
         // Use HttpResponse to build responses with status code,
         // body, headers, etc.
-        Either::Right(HttpResponse::NotFound().body("Not found"))
+        Ok(Either::Right(HttpResponse::NotFound().body("Not found")))
+    }
+}
+
+/// Get the subtasks of a todo item
+///
+/// The response contains the item itself, its direct subtasks and the percentage
+/// of completed subtasks.
+#[get("/todos/{id}/children")]
+async fn get_children(id: Path<usize>, db: Data<Db>) -> Result<HttpResponse, AppError> {
+    match db.read().await.subtasks(*id)? {
+        Some(subtasks) => Ok(HttpResponse::Ok().json(subtasks)),
+        None => Ok(HttpResponse::NotFound().body("Not found")),
+    }
+}
+
+/// Create, update and delete todo items in a single request
+///
+/// The response contains one result per operation. It is 207 Multi-Status if any
+/// operation failed, with `"mode": "all_or_nothing"` nothing has been changed then.
+#[post("/todos/batch")]
+async fn apply_batch(db: Data<Db>, batch: Json<Batch>) -> Result<impl Responder, AppError> {
+    let results = db.apply_batch(batch.into_inner()).await?;
+    let status = StatusCode::from_u16(batch::status(&results)).unwrap_or(StatusCode::OK);
+    Ok(HttpResponse::build(status).json(results))
+}
+
+/// Get the changes of a todo item, oldest first
+///
+/// Items in the trash still have their history.
+#[get("/todos/{id}/history")]
+async fn get_history(id: Path<usize>, db: Data<Db>) -> Result<HttpResponse, AppError> {
+    match db.read().await.history(*id)? {
+        Some(history) => Ok(HttpResponse::Ok().json(history)),
+        None => Ok(HttpResponse::NotFound().body("Not found")),
+    }
+}
+
+/// Set a todo item back to an earlier version from its history
+///
+/// With an If-Match header, the item is only reverted if it has not been changed.
+#[post("/todos/{id}/revert")]
+async fn revert_todo(
+    req: HttpRequest,
+    id: Path<usize>,
+    db: Data<Db>,
+    revert: Json<RevertTo>,
+) -> Result<ItemOrStatus, AppError> {
+    let mut todos = db.write().await;
+    match todos.revert_todo(*id, revert.version, if_match(&req)?)? {
+        Some(item) => Ok(item_response(item)),
+        None => Ok(Either::Right(HttpResponse::NotFound().body("Not found"))),
     }
 }
 
@@ -86,34 +240,148 @@ async fn get_todo(id: Path<usize>, db: Data<Db>) -> ItemOrStatus {
 ///
 /// Note the use of the Json extractor to extract the body.
 #[post("/todos")]
-async fn add_todo(db: Data<Db>, todo: Json<TodoItem>) -> impl Responder {
-    let mut todos = db.write().await;
-    let todo = todos.add_todo(todo.clone());
-    HttpResponse::Created().json(todo)
+async fn add_todo(db: Data<Db>, todo: Json<TodoItem>) -> Result<impl Responder, AppError> {
+    let todo = db.add_todo(todo.into_inner()).await?;
+    Ok(HttpResponse::Created().json(todo))
 }
 
-/// Delete a todo item
+/// Move a todo item to the trash
 ///
 /// Note the use of another Extractor, Path, to extract the id.
+/// With an If-Match header, the item is only deleted if it has not been changed.
+/// Items with subtasks are only deleted with `?cascade=true`, together with the subtasks.
 #[delete("/todos/{id}")]
-async fn delete_todo(id: Path<usize>, db: Data<Db>) -> impl Responder {
-    match db.write().await.remove_todo(*id) {
-        Some(_) => HttpResponse::NoContent(),
-        None => HttpResponse::NotFound(),
+async fn delete_todo(
+    req: HttpRequest,
+    id: Path<usize>,
+    params: Query<DeleteParams>,
+    db: Data<Db>,
+) -> Result<impl Responder, AppError> {
+    match db.remove_todo(*id, if_match(&req)?, params.mode()).await? {
+        Some(_) => Ok(HttpResponse::NoContent()),
+        None => Ok(HttpResponse::NotFound()),
+    }
+}
+
+/// Get the items in the trash
+#[get("/todos/trash")]
+async fn get_trash(db: Data<Db>) -> impl Responder {
+    HttpResponse::Ok().json(db.read().await.trash())
+}
+
+/// Restore a todo item from the trash
+///
+/// Subtasks can only be restored after their parent.
+#[post("/todos/{id}/restore")]
+async fn restore_todo(id: Path<usize>, db: Data<Db>) -> Result<ItemOrStatus, AppError> {
+    match db.write().await.restore_todo(*id)? {
+        Some(item) => Ok(item_response(item)),
+        None => Ok(Either::Right(HttpResponse::NotFound().body("Not found"))),
+    }
+}
+
+/// Remove a todo item from the trash for good
+#[delete("/todos/trash/{id}")]
+async fn purge_todo(id: Path<usize>, db: Data<Db>) -> Result<impl Responder, AppError> {
+    match db.write().await.purge_todo(*id)? {
+        Some(_) => Ok(HttpResponse::NoContent()),
+        None => Ok(HttpResponse::NotFound()),
     }
 }
 
+/// Remove all todo items in the trash for good
+#[delete("/todos/trash")]
+async fn empty_trash(db: Data<Db>) -> Result<impl Responder, AppError> {
+    db.write().await.empty_trash()?;
+    Ok(HttpResponse::NoContent())
+}
+
 /// Update a todo item
+///
+/// The body is a JSON Merge Patch or a JSON Patch, depending on the Content-Type, or
+/// the fields to set for `application/json`. With an If-Match header, the item is only
+/// updated if it has not been changed.
 #[patch("/todos/{id}")]
-async fn update_todo(id: Path<usize>, db: Data<Db>, input: Json<UpdateTodoItem>) -> ItemOrStatus {
-    let mut todos = db.write().await;
-    let res = todos.update_todo(&id, input.into_inner());
-    match res {
-        Some(todo) => Either::Left(Json(todo.clone())),
-        None => Either::Right(HttpResponse::NotFound().finish()),
+async fn update_todo(req: HttpRequest, id: Path<usize>, db: Data<Db>, body: Bytes) -> Result<ItemOrStatus, AppError> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let patch = TodoPatch::parse(content_type, &body)?;
+    match db.patch_todo(*id, patch, if_match(&req)?).await? {
+        Some(todo) => Ok(item_response(todo)),
+        None => Ok(Either::Right(HttpResponse::NotFound().finish())),
+    }
+}
+
+/// Get all lists
+#[get("/lists")]
+async fn get_lists(db: Data<Db>) -> impl Responder {
+    HttpResponse::Ok().json(db.read().await.lists())
+}
+
+/// Create a new list
+#[post("/lists")]
+async fn add_list(db: Data<Db>, list: Json<NewTodoList>) -> Result<impl Responder, AppError> {
+    let list = db.write().await.add_list(list.into_inner())?;
+    Ok(HttpResponse::Created().json(list))
+}
+
+/// Get a single list
+#[get("/lists/{id}")]
+async fn get_list(id: Path<usize>, db: Data<Db>) -> impl Responder {
+    match db.read().await.get_list(*id) {
+        Some(list) => HttpResponse::Ok().json(list),
+        None => HttpResponse::NotFound().body("Not found"),
     }
 }
 
+/// Rename a list
+#[patch("/lists/{id}")]
+async fn rename_list(id: Path<usize>, db: Data<Db>, input: Json<NewTodoList>) -> Result<impl Responder, AppError> {
+    match db.write().await.rename_list(*id, input.into_inner())? {
+        Some(list) => Ok(HttpResponse::Ok().json(list)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Delete a list
+///
+/// Lists with todo items are only deleted with `?cascade=true`, together with the items.
+#[delete("/lists/{id}")]
+async fn delete_list(id: Path<usize>, params: Query<DeleteParams>, db: Data<Db>) -> Result<impl Responder, AppError> {
+    match db.write().await.remove_list(*id, params.mode())? {
+        Some(_) => Ok(HttpResponse::NoContent()),
+        None => Ok(HttpResponse::NotFound()),
+    }
+}
+
+/// Get the todo items of a list
+///
+/// Supports the same query parameters as get_todos. Items are moved to another list
+/// by patching their `list_id`.
+#[get("/lists/{id}/todos")]
+async fn get_list_todos(
+    id: Path<usize>,
+    pagination: Query<Pagination>,
+    filter: Query<TodoFilter>,
+    db: Data<Db>,
+) -> Result<HttpResponse, AppError> {
+    let (Query(pagination), Query(filter)) = (pagination, filter);
+    let Some(page) = db.read().await.get_list_todos(*id, pagination.clone(), &filter)? else {
+        return Ok(HttpResponse::NotFound().body("Not found"));
+    };
+    let mut response = HttpResponse::Ok();
+    if let Some(link) = pagination.next_link(&format!("/lists/{id}/todos"), &filter, &page) {
+        response.insert_header((header::LINK, link));
+    }
+    Ok(if pagination.is_cursor_mode() {
+        response.json(page)
+    } else {
+        response.json(page.items)
+    })
+}
+
 /// Application-level error object
 #[derive(Debug)]
 enum AppError {
@@ -140,7 +408,22 @@ impl Display for AppError {
 /// More about error handling at https://actix.rs/docs/errors/.
 impl ResponseError for AppError {
     fn status_code(&self) -> actix_web::http::StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            AppError::TodoStore(TodoStoreError::InvalidQuery(_) | TodoStoreError::InvalidPatch(_)) => {
+                StatusCode::BAD_REQUEST
+            },
+            AppError::TodoStore(TodoStoreError::VersionMismatch { .. }) => StatusCode::PRECONDITION_FAILED,
+            AppError::TodoStore(TodoStoreError::InvalidItem(_) | TodoStoreError::InvalidList(_)) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            },
+            AppError::TodoStore(
+                TodoStoreError::HasSubtasks { .. }
+                | TodoStoreError::ListNotEmpty { .. }
+                | TodoStoreError::PatchConflict(_),
+            ) => StatusCode::CONFLICT,
+            AppError::TodoStore(TodoStoreError::UnsupportedMediaType(_)) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::TodoStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
@@ -148,6 +431,21 @@ impl ResponseError for AppError {
             AppError::TodoStore(e) => match e {
                 TodoStoreError::FileAccessError(_) => "Error while writing to file",
                 TodoStoreError::SerializationError(_) => "Error during serialization",
+                TodoStoreError::DatabaseError(_) => "Error while accessing the database",
+                TodoStoreError::ConfigurationError(_) => "Invalid store configuration",
+                TodoStoreError::UnsupportedSnapshotVersion(_) => "Unsupported snapshot version",
+                // Tell the client what is wrong with the request
+                TodoStoreError::InvalidQuery(_)
+                | TodoStoreError::VersionMismatch { .. }
+                | TodoStoreError::InvalidItem(_)
+                | TodoStoreError::InvalidList(_)
+                | TodoStoreError::HasSubtasks { .. }
+                | TodoStoreError::ListNotEmpty { .. }
+                | TodoStoreError::InvalidPatch(_)
+                | TodoStoreError::PatchConflict(_)
+                | TodoStoreError::UnsupportedMediaType(_) => {
+                    return HttpResponse::build(self.status_code()).json(e.to_string())
+                },
             },
         })
     }
@@ -162,7 +460,15 @@ async fn persist(db: Data<Db>) -> Result<&'static str, AppError> {
     // Write a log message
     debug!("Persisting todos");
 
-    let todos = db.read().await;
-    todos.persist().await?;
+    db.persist().await?;
     Ok("")
 }
+
+/// Get the state of the background jobs that run the integrations
+#[get("/jobs")]
+async fn get_jobs(db: Data<Db>) -> impl Responder {
+    match db.read().await.job_status() {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().body("Not found"),
+    }
+}
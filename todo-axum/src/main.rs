@@ -1,13 +1,18 @@
 use axum::{
+    body::Bytes,
     extract::{Path, Query, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    response::{AppendHeaders, Html, IntoResponse, Response},
     routing::{delete, get, post},
     Json, Router,
 };
 use serde_json::json;
 use std::sync::Arc;
-use todo_logic::{Pagination, TodoItem, TodoStore, TodoStoreError, UpdateTodoItem};
+use todo_logic::{
+    autosave, batch, etag, trash, AsyncTodoStore, Batch, DeleteParams, IdentifyableTodoItem, JobQueue, NewTodoList,
+    Pagination, RevertTo, SearchQuery, TodoFilter, TodoItem, TodoPatch, TodoStore, TodoStoreConfig, TodoStoreError,
+    Validators,
+};
 use tokio::{net::TcpListener, sync::RwLock};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -29,8 +34,26 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Create shared data store
-    let db = Db::default();
+    // Create shared data store. The storage backend is selected with the
+    // TODO_BACKEND environment variable (e.g. `sqlite:todos.db`). Set TODO_RESTORE=true
+    // to restore the todo items that have been persisted before. With TODO_JOURNAL=<file>,
+    // every change is appended to a journal that is replayed on startup.
+    let config = TodoStoreConfig::from_env().expect("valid todo store configuration");
+    let store = config.open().await.expect("can open todo store");
+    let db = Db::new(RwLock::new(
+        store.with_jobs(JobQueue::start(config.jobs, &config.hooks)),
+    ));
+    if let Some(autosave) = config.autosave {
+        // Persist in the background so that we do not depend on clients calling /todos/persist.
+        // In journal mode, this periodically compacts the journal into a snapshot.
+        autosave::spawn(db.clone(), autosave, |e| tracing::error!("Autosave failed: {e:?}"));
+    }
+    if let Some(retention) = config.trash_retention {
+        // Deleted items are purged once they have been in the trash for TODO_TRASH_RETENTION seconds
+        trash::spawn(db.clone(), retention, |e| {
+            tracing::error!("Purging the trash failed: {e:?}")
+        });
+    }
 
     // We register our shared state so that handlers can get it using the State extractor.
     // Note that this will change in Axum 0.6. See more at
@@ -39,8 +62,21 @@ async fn main() {
         // Here we setup the routes. Note: No macros
         .route("/", get(say_hello))
         .route("/todos", get(get_todos).post(add_todo))
+        // Static segments take precedence over `:id`, so this does not clash with get_todo
+        .route("/todos/search", get(search_todos))
+        .route("/todos/batch", post(apply_batch))
+        .route("/todos/trash", get(get_trash).delete(empty_trash))
+        .route("/todos/trash/:id", delete(purge_todo))
         .route("/todos/:id", delete(delete_todo).patch(update_todo).get(get_todo))
+        .route("/todos/:id/children", get(get_children))
+        .route("/todos/:id/restore", post(restore_todo))
+        .route("/todos/:id/history", get(get_history))
+        .route("/todos/:id/revert", post(revert_todo))
+        .route("/lists", get(get_lists).post(add_list))
+        .route("/lists/:id", get(get_list).patch(rename_list).delete(delete_list))
+        .route("/lists/:id/todos", get(get_list_todos))
         .route("/todos/persist", post(persist))
+        .route("/jobs", get(get_jobs))
         .with_state(db)
         // Using tower to add tracing layer
         .layer(TraceLayer::new_for_http());
@@ -63,25 +99,138 @@ async fn say_hello() -> Html<&'static str> {
 /// Note how the Query extractor is used to get query parameters. Note how the State
 /// extractor is used to get the database (changes in Axum 0.6 RC).
 /// Extractors are technically types that implement FromRequest. You can create
-/// your own extractors or use the ones provided by Axum.
-async fn get_todos(pagination: Option<Query<Pagination>>, State(db): State<Db>) -> impl IntoResponse {
+/// your own extractors or use the ones provided by Axum. If the query string
+/// cannot be parsed (e.g. an unknown `sort` field), the Query extractor rejects
+/// the request with 400 Bad Request.
+///
+/// Clients that pass a `cursor` (empty for the first page) get a page envelope,
+/// all others get a plain list. In both cases, the Link header points to the next page.
+///
+/// Pagination and filter are read from the same query string with two Query extractors.
+/// Polling clients can send the ETag or Last-Modified header of their copy and get
+/// 304 Not Modified if nothing has changed since.
+async fn get_todos(
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<TodoFilter>,
+    State(db): State<Db>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     let todos = db.read().await;
-    let Query(pagination) = pagination.unwrap_or_default();
+    let validators = todos.validators();
+    if is_not_modified(&headers, &validators) {
+        return Ok(not_modified(&validators));
+    }
+    let page = todos.get_todos(pagination.clone(), &filter)?;
+    let link = pagination.next_link("/todos", &filter, &page);
     // Json is an extractor and a response.
-    Json(todos.get_todos(pagination))
+    let body = if pagination.is_cursor_mode() {
+        Json(page).into_response()
+    } else {
+        Json(page.items).into_response()
+    };
+    // AppendHeaders accepts any iterator, so the header is only added if there is a next page
+    Ok((
+        validator_headers(&validators),
+        AppendHeaders(link.map(|link| (header::LINK, link))),
+        body,
+    )
+        .into_response())
+}
+
+/// Search todo items by words in title and notes
+///
+/// Expects the search terms in `q`, e.g. `/todos/search?q=learning+rust&limit=10`.
+/// The results are ranked by relevance and contain the score of each item.
+async fn search_todos(Query(query): Query<SearchQuery>, State(db): State<Db>) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(db.search(query).await?))
 }
 
 /// Get a single todo item
 ///
 /// Note how the Path extractor is used to get query parameters.
-async fn get_todo(Path(id): Path<usize>, State(db): State<Db>) -> impl IntoResponse {
+async fn get_todo(Path(id): Path<usize>, State(db): State<Db>, headers: HeaderMap) -> Result<Response, AppError> {
     let todos = db.read().await;
-    if let Some(item) = todos.get_todo(id) {
-        // Note how to return Json
-        Json(item).into_response()
+    if let Some(item) = todos.get_todo(id)? {
+        let validators = item.validators();
+        if is_not_modified(&headers, &validators) {
+            return Ok(not_modified(&validators));
+        }
+        Ok(item_response(item))
     } else {
         // Note how a tuple can be turned into a response
-        (StatusCode::NOT_FOUND, "Not found").into_response()
+        Ok((StatusCode::NOT_FOUND, "Not found").into_response())
+    }
+}
+
+/// Get the subtasks of a todo item
+///
+/// The response contains the item itself, its direct subtasks and the percentage
+/// of completed subtasks.
+async fn get_children(Path(id): Path<usize>, State(db): State<Db>) -> Result<Response, AppError> {
+    let todos = db.read().await;
+    Ok(match todos.subtasks(id)? {
+        Some(subtasks) => Json(subtasks).into_response(),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    })
+}
+
+/// Get the changes of a todo item, oldest first
+///
+/// Items in the trash still have their history.
+async fn get_history(Path(id): Path<usize>, State(db): State<Db>) -> Result<Response, AppError> {
+    Ok(match db.read().await.history(id)? {
+        Some(history) => Json(history).into_response(),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    })
+}
+
+/// Set a todo item back to an earlier version from its history
+///
+/// With an If-Match header, the item is only reverted if it has not been changed.
+async fn revert_todo(
+    Path(id): Path<usize>,
+    State(db): State<Db>,
+    headers: HeaderMap,
+    Json(revert): Json<RevertTo>,
+) -> Result<Response, AppError> {
+    let mut todos = db.write().await;
+    Ok(match todos.revert_todo(id, revert.version, if_match(&headers)?)? {
+        Some(item) => item_response(item),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    })
+}
+
+/// Respond with a todo item and its validators
+///
+/// Note how to return Json. Headers can be added by putting them in front of it in a tuple.
+fn item_response(item: IdentifyableTodoItem) -> Response {
+    (validator_headers(&item.validators()), Json(item)).into_response()
+}
+
+/// ETag and Last-Modified headers
+fn validator_headers(validators: &Validators) -> [(HeaderName, String); 2] {
+    [
+        (header::ETAG, validators.etag.clone()),
+        (header::LAST_MODIFIED, validators.last_modified_header()),
+    ]
+}
+
+/// Whether the copy of the client is still up to date
+fn is_not_modified(headers: &HeaderMap, validators: &Validators) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    validators.not_modified(header(header::IF_NONE_MATCH), header(header::IF_MODIFIED_SINCE))
+}
+
+/// Empty 304 response, the validators tell the client which copy is current
+fn not_modified(validators: &Validators) -> Response {
+    (StatusCode::NOT_MODIFIED, validator_headers(validators)).into_response()
+}
+
+/// Version expected by the If-Match header, `None` if there is no such header
+fn if_match(headers: &HeaderMap) -> Result<Option<u64>, AppError> {
+    match headers.get(header::IF_MATCH) {
+        Some(value) => Ok(etag::expected_version(value.to_str().unwrap_or_default())?),
+        None => Ok(None),
     }
 }
 
@@ -89,35 +238,157 @@ async fn get_todo(Path(id): Path<usize>, State(db): State<Db>) -> impl IntoRespo
 ///
 /// Note that this time, Json is used as an extractor. This means that the request body
 /// will be deserialized into a TodoItem.
-async fn add_todo(State(db): State<Db>, Json(todo): Json<TodoItem>) -> impl IntoResponse {
-    let mut todos = db.write().await;
-    let todo = todos.add_todo(todo);
-    (StatusCode::CREATED, Json(todo))
+async fn add_todo(State(db): State<Db>, Json(todo): Json<TodoItem>) -> Result<impl IntoResponse, AppError> {
+    let todo = db.add_todo(todo).await?;
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+/// Create, update and delete todo items in a single request
+///
+/// The response contains one result per operation. It is 207 Multi-Status if any
+/// operation failed, with `"mode": "all_or_nothing"` nothing has been changed then.
+async fn apply_batch(State(db): State<Db>, Json(batch): Json<Batch>) -> Result<impl IntoResponse, AppError> {
+    let results = db.apply_batch(batch).await?;
+    let status = StatusCode::from_u16(batch::status(&results)).unwrap_or(StatusCode::OK);
+    Ok((status, Json(results)))
 }
 
-/// Delete a todo item
-async fn delete_todo(Path(id): Path<usize>, State(db): State<Db>) -> impl IntoResponse {
-    if db.write().await.remove_todo(id).is_some() {
-        StatusCode::NO_CONTENT
+/// Move a todo item to the trash
+///
+/// With an If-Match header, the item is only deleted if it has not been changed.
+/// Items with subtasks are only deleted with `?cascade=true`, together with the subtasks.
+async fn delete_todo(
+    Path(id): Path<usize>,
+    Query(params): Query<DeleteParams>,
+    State(db): State<Db>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    if db.remove_todo(id, if_match(&headers)?, params.mode()).await?.is_some() {
+        Ok(StatusCode::NO_CONTENT)
     } else {
-        StatusCode::NOT_FOUND
+        Ok(StatusCode::NOT_FOUND)
     }
 }
 
+/// Get the items in the trash
+async fn get_trash(State(db): State<Db>) -> impl IntoResponse {
+    Json(db.read().await.trash())
+}
+
+/// Restore a todo item from the trash
+///
+/// Subtasks can only be restored after their parent.
+async fn restore_todo(Path(id): Path<usize>, State(db): State<Db>) -> Result<Response, AppError> {
+    Ok(match db.write().await.restore_todo(id)? {
+        Some(item) => item_response(item),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    })
+}
+
+/// Remove a todo item from the trash for good
+async fn purge_todo(Path(id): Path<usize>, State(db): State<Db>) -> Result<StatusCode, AppError> {
+    if db.write().await.purge_todo(id)?.is_some() {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Remove all todo items in the trash for good
+async fn empty_trash(State(db): State<Db>) -> Result<StatusCode, AppError> {
+    db.write().await.empty_trash()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Update a todo item
+///
+/// The body is a JSON Merge Patch or a JSON Patch, depending on the Content-Type, or
+/// the fields to set for `application/json`. With an If-Match header, the item is only
+/// updated if it has not been changed.
+/// Note that the Bytes extractor consumes the body, so it has to be the last one.
 async fn update_todo(
     Path(id): Path<usize>,
     State(db): State<Db>,
-    Json(input): Json<UpdateTodoItem>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let mut todos = db.write().await;
-    let res = todos.update_todo(&id, input);
-    match res {
-        Some(todo) => Ok(Json(todo.clone())),
-        None => Err(StatusCode::NOT_FOUND),
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    let patch = TodoPatch::parse(content_type, &body)?;
+    match db.patch_todo(id, patch, if_match(&headers)?).await? {
+        Some(todo) => Ok(item_response(todo)),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+/// Get all lists
+async fn get_lists(State(db): State<Db>) -> impl IntoResponse {
+    Json(db.read().await.lists())
+}
+
+/// Create a new list
+async fn add_list(State(db): State<Db>, Json(list): Json<NewTodoList>) -> Result<impl IntoResponse, AppError> {
+    let list = db.write().await.add_list(list)?;
+    Ok((StatusCode::CREATED, Json(list)))
+}
+
+/// Get a single list
+async fn get_list(Path(id): Path<usize>, State(db): State<Db>) -> Response {
+    match db.read().await.get_list(id) {
+        Some(list) => Json(list).into_response(),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    }
+}
+
+/// Rename a list
+async fn rename_list(
+    Path(id): Path<usize>,
+    State(db): State<Db>,
+    Json(input): Json<NewTodoList>,
+) -> Result<Response, AppError> {
+    Ok(match db.write().await.rename_list(id, input)? {
+        Some(list) => Json(list).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Delete a list
+///
+/// Lists with todo items are only deleted with `?cascade=true`, together with the items.
+async fn delete_list(
+    Path(id): Path<usize>,
+    Query(params): Query<DeleteParams>,
+    State(db): State<Db>,
+) -> Result<StatusCode, AppError> {
+    if db.write().await.remove_list(id, params.mode())?.is_some() {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
     }
 }
 
+/// Get the todo items of a list
+///
+/// Supports the same query parameters as get_todos. Items are moved to another list
+/// by patching their `list_id`.
+async fn get_list_todos(
+    Path(id): Path<usize>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<TodoFilter>,
+    State(db): State<Db>,
+) -> Result<Response, AppError> {
+    let todos = db.read().await;
+    let Some(page) = todos.get_list_todos(id, pagination.clone(), &filter)? else {
+        return Ok((StatusCode::NOT_FOUND, "Not found").into_response());
+    };
+    let link = pagination.next_link(&format!("/lists/{id}/todos"), &filter, &page);
+    let body = if pagination.is_cursor_mode() {
+        Json(page).into_response()
+    } else {
+        Json(page.items).into_response()
+    };
+    Ok((AppendHeaders(link.map(|link| (header::LINK, link))), body).into_response())
+}
+
 /// Application-level error object
 enum AppError {
     UserRepo(TodoStoreError),
@@ -134,6 +405,8 @@ impl From<TodoStoreError> for AppError {
 /// convert it into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        // Tell the client what is wrong with the request
+        let client_error = |status, error: TodoStoreError| (status, Json(json!({ "error": error.to_string() })));
         let (status, error_message) = match self {
             AppError::UserRepo(TodoStoreError::FileAccessError(_)) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Error while writing to file")
@@ -141,6 +414,34 @@ impl IntoResponse for AppError {
             AppError::UserRepo(TodoStoreError::SerializationError(_)) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Error during serialization")
             },
+            AppError::UserRepo(TodoStoreError::DatabaseError(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Error while accessing the database")
+            },
+            AppError::UserRepo(TodoStoreError::ConfigurationError(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid store configuration")
+            },
+            AppError::UserRepo(TodoStoreError::UnsupportedSnapshotVersion(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Unsupported snapshot version")
+            },
+            AppError::UserRepo(error @ (TodoStoreError::InvalidQuery(_) | TodoStoreError::InvalidPatch(_))) => {
+                return client_error(StatusCode::BAD_REQUEST, error).into_response();
+            },
+            AppError::UserRepo(error @ TodoStoreError::VersionMismatch { .. }) => {
+                return client_error(StatusCode::PRECONDITION_FAILED, error).into_response();
+            },
+            AppError::UserRepo(error @ (TodoStoreError::InvalidItem(_) | TodoStoreError::InvalidList(_))) => {
+                return client_error(StatusCode::UNPROCESSABLE_ENTITY, error).into_response();
+            },
+            AppError::UserRepo(
+                error @ (TodoStoreError::HasSubtasks { .. }
+                | TodoStoreError::ListNotEmpty { .. }
+                | TodoStoreError::PatchConflict(_)),
+            ) => {
+                return client_error(StatusCode::CONFLICT, error).into_response();
+            },
+            AppError::UserRepo(error @ TodoStoreError::UnsupportedMediaType(_)) => {
+                return client_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, error).into_response();
+            },
         };
 
         let body = Json(json!({
@@ -154,7 +455,14 @@ impl IntoResponse for AppError {
 /// Persist the todo store to disk
 async fn persist(State(db): State<Db>) -> Result<(), AppError> {
     tracing::debug!("Persisting todos");
-    let todos = db.read().await;
-    todos.persist().await?;
+    db.persist().await?;
     Ok(())
 }
+
+/// Get the state of the background jobs that run the integrations
+async fn get_jobs(State(db): State<Db>) -> Response {
+    match db.read().await.job_status() {
+        Some(status) => Json(status).into_response(),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    }
+}
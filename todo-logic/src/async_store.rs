@@ -0,0 +1,205 @@
+use std::{future::Future, sync::Arc};
+
+use tokio::{sync::RwLock, task::JoinError};
+
+use crate::{
+    Batch, BatchResult, DeleteMode, IdentifyableTodoItem, Page, Pagination, SearchQuery, SearchResult, TodoFilter,
+    TodoItem, TodoPatch, TodoStore, TodoStoreError,
+};
+
+/// Todo store shared by the request handlers of a server
+pub type SharedTodoStore = Arc<RwLock<TodoStore>>;
+
+/// Async variants of the [`TodoStore`] operations
+///
+/// Store operations can block, e.g. on SQLite or on the journal file. The async variants
+/// lock the shared store and run the operation on tokio's blocking thread pool, so that
+/// they do not stall the workers of the async runtime. The methods of [`TodoStore`] itself
+/// stay synchronous, e.g. for todo-spin which is built without tokio.
+pub trait AsyncTodoStore {
+    /// Run a closure with read access to the store on the blocking thread pool
+    fn with_read<F, T>(&self, f: F) -> impl Future<Output = Result<T, TodoStoreError>> + Send
+    where
+        F: FnOnce(&TodoStore) -> Result<T, TodoStoreError> + Send + 'static,
+        T: Send + 'static;
+
+    /// Run a closure with write access to the store on the blocking thread pool
+    fn with_write<F, T>(&self, f: F) -> impl Future<Output = Result<T, TodoStoreError>> + Send
+    where
+        F: FnOnce(&mut TodoStore) -> Result<T, TodoStoreError> + Send + 'static,
+        T: Send + 'static;
+
+    /// See [`TodoStore::persist`]
+    fn persist(&self) -> impl Future<Output = Result<(), TodoStoreError>> + Send;
+
+    /// See [`TodoStore::get_todos`]
+    fn get_todos(
+        &self,
+        pagination: Pagination,
+        filter: TodoFilter,
+    ) -> impl Future<Output = Result<Page<IdentifyableTodoItem>, TodoStoreError>> + Send {
+        self.with_read(move |store| store.get_todos(pagination, &filter))
+    }
+
+    /// See [`TodoStore::search`]
+    fn search(&self, query: SearchQuery) -> impl Future<Output = Result<Vec<SearchResult>, TodoStoreError>> + Send {
+        self.with_read(move |store| store.search(&query))
+    }
+
+    /// See [`TodoStore::get_todo`]
+    fn get_todo(&self, id: usize) -> impl Future<Output = Result<Option<IdentifyableTodoItem>, TodoStoreError>> + Send {
+        self.with_read(move |store| store.get_todo(id))
+    }
+
+    /// See [`TodoStore::add_todo`]
+    fn add_todo(&self, todo: TodoItem) -> impl Future<Output = Result<IdentifyableTodoItem, TodoStoreError>> + Send {
+        self.with_write(move |store| store.add_todo(todo))
+    }
+
+    /// See [`TodoStore::patch_todo`]
+    fn patch_todo(
+        &self,
+        id: usize,
+        patch: TodoPatch,
+        expected_version: Option<u64>,
+    ) -> impl Future<Output = Result<Option<IdentifyableTodoItem>, TodoStoreError>> + Send {
+        self.with_write(move |store| store.patch_todo(id, patch, expected_version))
+    }
+
+    /// See [`TodoStore::remove_todo`]
+    fn remove_todo(
+        &self,
+        id: usize,
+        expected_version: Option<u64>,
+        mode: DeleteMode,
+    ) -> impl Future<Output = Result<Option<IdentifyableTodoItem>, TodoStoreError>> + Send {
+        self.with_write(move |store| store.remove_todo(id, expected_version, mode))
+    }
+
+    /// See [`TodoStore::apply_batch`]
+    fn apply_batch(&self, batch: Batch) -> impl Future<Output = Result<Vec<BatchResult>, TodoStoreError>> + Send {
+        self.with_write(move |store| store.apply_batch(batch))
+    }
+}
+
+impl AsyncTodoStore for SharedTodoStore {
+    async fn with_read<F, T>(&self, f: F) -> Result<T, TodoStoreError>
+    where
+        F: FnOnce(&TodoStore) -> Result<T, TodoStoreError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let store = self.clone().read_owned().await;
+        tokio::task::spawn_blocking(move || f(&store))
+            .await
+            .unwrap_or_else(join_error)
+    }
+
+    async fn with_write<F, T>(&self, f: F) -> Result<T, TodoStoreError>
+    where
+        F: FnOnce(&mut TodoStore) -> Result<T, TodoStoreError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut store = self.clone().write_owned().await;
+        tokio::task::spawn_blocking(move || f(&mut store))
+            .await
+            .unwrap_or_else(join_error)
+    }
+
+    async fn persist(&self) -> Result<(), TodoStoreError> {
+        // Writing the snapshot is async already. The store is only locked to take the
+        // snapshot and to truncate the journal, so that requests are served in the meantime.
+        let persist_lock = self.read().await.persist_lock();
+        let _persisting = persist_lock.lock().await;
+        let compaction = self.read().await.start_compaction()?;
+        compaction.write().await?;
+        self.read().await.finish_compaction(compaction)
+    }
+}
+
+/// Pass on panics of the blocking task
+///
+/// Blocking tasks are only cancelled when the runtime shuts down.
+fn join_error<T>(error: JoinError) -> Result<T, TodoStoreError> {
+    match error.try_into_panic() {
+        Ok(panic) => std::panic::resume_unwind(panic),
+        Err(error) => Err(TodoStoreError::FileAccessError(std::io::Error::other(error))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{batch::BatchMode, UpdateTodoItem};
+
+    #[tokio::test]
+    async fn async_operations() {
+        let db = SharedTodoStore::default();
+        let item = TodoItem {
+            title: "Learn Rust".to_string(),
+            ..Default::default()
+        };
+        let added = db.add_todo(item).await.unwrap();
+        let found = db.get_todo(added.id).await.unwrap().unwrap();
+        assert_eq!((added.id, "Learn Rust"), (found.id, found.item.title.as_str()));
+
+        let patch = TodoPatch::Update(UpdateTodoItem {
+            completed: Some(true),
+            ..Default::default()
+        });
+        let patched = db.patch_todo(added.id, patch, Some(1)).await.unwrap().unwrap();
+        assert!(patched.item.completed);
+        let query = SearchQuery {
+            q: "rust".to_string(),
+            limit: None,
+        };
+        assert_eq!(1, db.search(query).await.unwrap().len());
+
+        let batch = Batch {
+            mode: BatchMode::AllOrNothing,
+            operations: Vec::new(),
+        };
+        assert!(db.apply_batch(batch).await.unwrap().is_empty());
+        db.remove_todo(added.id, None, DeleteMode::Block).await.unwrap();
+        let page = db
+            .get_todos(Pagination::default(), TodoFilter::default())
+            .await
+            .unwrap();
+        assert!(page.items.is_empty());
+        // The sync API sees the same store
+        assert_eq!(1, db.read().await.trash().len());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_persists() {
+        let path = std::env::temp_dir().join(format!("todo_concurrent_persist_{}.json", std::process::id()));
+        let db = SharedTodoStore::new(RwLock::new(TodoStore::default().with_persist_path(&path)));
+        for _ in 0..3 {
+            db.add_todo(TodoItem::default()).await.unwrap();
+        }
+        let tasks = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { db.persist().await })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(0, db.read().await.unsaved_writes());
+        let store = TodoStore::load(&path).await.unwrap();
+        assert_eq!(3, store.all_todos().unwrap().len());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_passed_on() {
+        let db = SharedTodoStore::default();
+        let orphan = TodoItem {
+            parent_id: Some(42),
+            ..Default::default()
+        };
+        assert!(matches!(db.add_todo(orphan).await, Err(TodoStoreError::InvalidItem(_))));
+        let count = db.with_read(|store| Ok(store.all_todos()?.len())).await.unwrap();
+        assert_eq!(0, count);
+    }
+}
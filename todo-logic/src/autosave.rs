@@ -0,0 +1,138 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::TodoStoreError;
+
+/// Strategy for persisting the todo store in the background
+///
+/// Autosave strategies are specified as `interval:<seconds>` or `writes:<count>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Autosave {
+    /// Persist in a fixed interval if there are unsaved changes
+    Interval(Duration),
+    /// Persist whenever the given number of changes has been made
+    Writes(usize),
+}
+
+impl FromStr for Autosave {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TodoStoreError::ConfigurationError(format!("invalid autosave strategy '{s}'"));
+        let (kind, value) = s.split_once(':').ok_or_else(invalid)?;
+        let value = value.parse::<u64>().ok().filter(|v| *v > 0).ok_or_else(invalid)?;
+        match kind {
+            "interval" => Ok(Autosave::Interval(Duration::from_secs(value))),
+            "writes" => Ok(Autosave::Writes(value as usize)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(feature = "persist")]
+pub use background::spawn;
+
+#[cfg(feature = "persist")]
+mod background {
+    use std::sync::Arc;
+
+    use tokio::{sync::RwLock, task::JoinHandle};
+
+    use super::Autosave;
+    use crate::{AsyncTodoStore, TodoStore, TodoStoreError};
+
+    /// Start a background task that persists the store according to the autosave strategy
+    ///
+    /// Errors are passed to `on_error` so that the caller can log them with whatever
+    /// logging framework it uses.
+    pub fn spawn<F>(db: Arc<RwLock<TodoStore>>, autosave: Autosave, on_error: F) -> JoinHandle<()>
+    where
+        F: Fn(TodoStoreError) + Send + Sync + 'static,
+    {
+        tokio::spawn(async move {
+            match autosave {
+                Autosave::Interval(period) => {
+                    let mut interval = tokio::time::interval(period);
+                    loop {
+                        interval.tick().await;
+                        persist_if_dirty(&db, 1, &on_error).await;
+                    }
+                },
+                Autosave::Writes(count) => {
+                    let writes = db.read().await.write_notify();
+                    loop {
+                        writes.notified().await;
+                        persist_if_dirty(&db, count, &on_error).await;
+                    }
+                },
+            }
+        })
+    }
+
+    async fn persist_if_dirty(db: &Arc<RwLock<TodoStore>>, min_writes: usize, on_error: &impl Fn(TodoStoreError)) {
+        let dirty = db.read().await.unsaved_writes() >= min_writes;
+        if dirty {
+            if let Err(e) = db.persist().await {
+                on_error(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_autosave() {
+        assert_eq!(
+            Autosave::Interval(Duration::from_secs(30)),
+            "interval:30".parse().unwrap()
+        );
+        assert_eq!(Autosave::Writes(10), "writes:10".parse().unwrap());
+        assert!("writes:0".parse::<Autosave>().is_err());
+        assert!("interval".parse::<Autosave>().is_err());
+        assert!("hourly:1".parse::<Autosave>().is_err());
+    }
+
+    #[cfg(feature = "persist")]
+    #[tokio::test]
+    async fn autosave_after_writes() {
+        use crate::{IdentifyableTodoItem, TodoItem, TodoStore, UpdateTodoItem};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        let path = std::env::temp_dir().join(format!("todo_autosave_{}.json", std::process::id()));
+        let item = TodoItem {
+            title: "Learn Rust".to_string(),
+            notes: String::new(),
+            assigned_to: "Rainer".to_string(),
+            completed: false,
+            ..Default::default()
+        };
+        let store =
+            TodoStore::from_hashmap(HashMap::from([(0, IdentifyableTodoItem::new(0, item))])).with_persist_path(&path);
+        let db = Arc::new(RwLock::new(store));
+        let task = spawn(db.clone(), Autosave::Writes(2), |e| panic!("{e}"));
+
+        for completed in [true, false] {
+            let patch = UpdateTodoItem {
+                title: None,
+                notes: None,
+                assigned_to: None,
+                completed: Some(completed),
+                ..Default::default()
+            };
+            db.write().await.update_todo(&0, patch, None).unwrap();
+        }
+
+        for _ in 0..100 {
+            if db.read().await.unsaved_writes() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    journal::JournalRecord, DeleteParams, HistoryEntry, IdentifyableTodoItem, TodoItem, TodoStore, TodoStoreError,
+    UpdateTodoItem,
+};
+
+/// Maximum number of operations in a single batch
+pub const MAX_OPERATIONS: usize = 1000;
+
+/// Single operation of a batch, e.g. `{ "op": "delete", "id": 3 }`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create {
+        item: TodoItem,
+    },
+    /// Patch an item, only if it still has `version` if that is given
+    Update {
+        id: usize,
+        patch: UpdateTodoItem,
+        #[serde(default)]
+        version: Option<u64>,
+    },
+    /// Move an item to the trash, see [`TodoStore::remove_todo`]
+    Delete {
+        id: usize,
+        #[serde(default)]
+        version: Option<u64>,
+        #[serde(default)]
+        cascade: Option<bool>,
+    },
+}
+
+impl BatchOperation {
+    /// Id of the item, `None` for items that are created
+    pub fn id(&self) -> Option<usize> {
+        match self {
+            BatchOperation::Create { .. } => None,
+            BatchOperation::Update { id, .. } | BatchOperation::Delete { id, .. } => Some(*id),
+        }
+    }
+}
+
+/// What happens to the other operations of a batch if one of them fails
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Apply either all operations or none of them
+    #[default]
+    AllOrNothing,
+    /// Apply all operations that succeed
+    BestEffort,
+}
+
+/// Operations that are applied in a single write
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Batch {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<BatchOperation>,
+}
+
+/// Outcome of a single operation, with an HTTP status code
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+    /// Created or updated item
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item: Option<IdentifyableTodoItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+const OK: u16 = 200;
+const CREATED: u16 = 201;
+const NO_CONTENT: u16 = 204;
+const MULTI_STATUS: u16 = 207;
+const NOT_FOUND: u16 = 404;
+/// Operation that has not been applied because another one failed
+const FAILED_DEPENDENCY: u16 = 424;
+
+impl BatchResult {
+    pub fn is_success(&self) -> bool {
+        self.status < 300
+    }
+
+    fn item(status: u16, item: IdentifyableTodoItem) -> Self {
+        BatchResult {
+            status,
+            id: Some(item.id),
+            item: Some(item),
+            error: None,
+        }
+    }
+
+    fn error(status: u16, id: Option<usize>, error: impl ToString) -> Self {
+        BatchResult {
+            status,
+            id,
+            item: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Changes made while a batch is tried on the live store, see [`TodoStore::apply_batch`]
+#[derive(Default)]
+pub(crate) struct Trial {
+    /// Items that have been written, their search index entries are rebuilt afterwards
+    changed: HashSet<usize>,
+    /// Items that have been moved to the trash
+    trashed: Vec<usize>,
+    /// History of the changed items before the batch
+    pub(crate) history: HashMap<usize, Option<Vec<HistoryEntry>>>,
+}
+
+impl Trial {
+    pub(crate) fn note(&mut self, record: &JournalRecord) {
+        match record {
+            JournalRecord::Put { item } => {
+                self.changed.insert(item.id);
+            },
+            JournalRecord::Trash { item } => {
+                self.changed.insert(item.item.id);
+                self.trashed.push(item.item.id);
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Status code of a batch response, 207 Multi-Status if not all operations succeeded
+pub fn status(results: &[BatchResult]) -> u16 {
+    if results.iter().all(BatchResult::is_success) {
+        OK
+    } else {
+        MULTI_STATUS
+    }
+}
+
+/// Status code of a failed operation, the same that the servers use for single requests
+fn error_status(error: &TodoStoreError) -> u16 {
+    match error {
+        TodoStoreError::InvalidQuery(_) | TodoStoreError::InvalidPatch(_) => 400,
+        TodoStoreError::VersionMismatch { .. } => 412,
+        TodoStoreError::InvalidItem(_) | TodoStoreError::InvalidList(_) => 422,
+        TodoStoreError::HasSubtasks { .. } | TodoStoreError::ListNotEmpty { .. } | TodoStoreError::PatchConflict(_) => {
+            409
+        },
+        TodoStoreError::UnsupportedMediaType(_) => 415,
+        _ => 500,
+    }
+}
+
+impl TodoStore {
+    /// Apply a batch of operations in order, returns one result per operation
+    ///
+    /// In [`BatchMode::AllOrNothing`], the batch is tried first and rolled back, see
+    /// [`crate::repository::TodoRepository::savepoint`]. Hooks do not run and nothing is
+    /// journaled during the trial. If any operation fails there, nothing is changed and the
+    /// other operations are reported as not applied. Only storage errors can still stop
+    /// a batch halfway.
+    pub fn apply_batch(&mut self, batch: Batch) -> Result<Vec<BatchResult>, TodoStoreError> {
+        if batch.operations.len() > MAX_OPERATIONS {
+            return Err(TodoStoreError::InvalidQuery(format!(
+                "a batch must not contain more than {MAX_OPERATIONS} operations"
+            )));
+        }
+        if batch.mode == BatchMode::AllOrNothing {
+            let results = self.try_operations(&batch.operations)?;
+            if !results.iter().all(BatchResult::is_success) {
+                return Ok(results
+                    .into_iter()
+                    .zip(&batch.operations)
+                    .map(|(result, operation)| {
+                        if result.is_success() {
+                            // Ids of items created during the trial mean nothing
+                            let message = "not applied, another operation failed";
+                            BatchResult::error(FAILED_DEPENDENCY, operation.id(), message)
+                        } else {
+                            result
+                        }
+                    })
+                    .collect());
+            }
+        }
+        Ok(batch
+            .operations
+            .into_iter()
+            .map(|operation| self.apply_operation(operation))
+            .collect())
+    }
+
+    /// Apply the operations and undo them again
+    fn try_operations(&mut self, operations: &[BatchOperation]) -> Result<Vec<BatchResult>, TodoStoreError> {
+        self.repository.savepoint()?;
+        let hooks = std::mem::take(&mut self.hooks);
+        let journal = self.journal.take();
+        self.trial = Some(Trial::default());
+        let results = operations
+            .iter()
+            .map(|operation| self.apply_operation(operation.clone()))
+            .collect();
+        let trial = self.trial.take().unwrap_or_default();
+        self.hooks = hooks;
+        self.journal = journal;
+
+        self.repository.rollback()?;
+        for id in trial.changed {
+            match self.repository.get_todo(id)? {
+                Some(item) => self.index.insert(&item),
+                None => self.index.remove(id),
+            }
+        }
+        for id in trial.trashed {
+            self.trash.remove(&id);
+        }
+        for (id, entries) in trial.history {
+            match entries {
+                Some(entries) => self.history.insert(id, entries),
+                None => self.history.remove(&id),
+            };
+        }
+        Ok(results)
+    }
+
+    /// Apply a single operation of a batch
+    fn apply_operation(&mut self, operation: BatchOperation) -> BatchResult {
+        let id = operation.id();
+        let result = match operation {
+            BatchOperation::Create { item } => self.add_todo(item).map(|item| BatchResult::item(CREATED, item)),
+            BatchOperation::Update { id, patch, version } => {
+                self.update_todo(&id, patch, version).map(|item| match item {
+                    Some(item) => BatchResult::item(OK, item),
+                    None => BatchResult::error(NOT_FOUND, Some(id), "not found"),
+                })
+            },
+            BatchOperation::Delete { id, version, cascade } => {
+                let mode = DeleteParams { cascade }.mode();
+                self.remove_todo(id, version, mode).map(|item| match item {
+                    Some(_) => BatchResult {
+                        status: NO_CONTENT,
+                        id: Some(id),
+                        item: None,
+                        error: None,
+                    },
+                    None => BatchResult::error(NOT_FOUND, Some(id), "not found"),
+                })
+            },
+        };
+        result.unwrap_or_else(|error| BatchResult::error(error_status(&error), id, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeleteMode, SearchQuery};
+
+    /// Store with item 0, its subtask 1 and item 2
+    fn store() -> TodoStore {
+        let items = [(0, None), (1, Some(0)), (2, None)].map(|(id, parent_id)| {
+            let item = TodoItem {
+                title: format!("item {id}"),
+                parent_id,
+                ..Default::default()
+            };
+            (id, IdentifyableTodoItem::new(id, item))
+        });
+        TodoStore::from_hashmap(items.into())
+    }
+
+    fn rename(id: usize, title: &str, version: Option<u64>) -> BatchOperation {
+        let patch = UpdateTodoItem {
+            title: Some(title.to_string()),
+            ..Default::default()
+        };
+        BatchOperation::Update { id, patch, version }
+    }
+
+    fn delete(id: usize) -> BatchOperation {
+        BatchOperation::Delete {
+            id,
+            version: None,
+            cascade: None,
+        }
+    }
+
+    fn statuses(results: &[BatchResult]) -> Vec<u16> {
+        results.iter().map(|result| result.status).collect()
+    }
+
+    #[test]
+    fn best_effort() {
+        let mut store = store();
+        let batch = Batch {
+            mode: BatchMode::BestEffort,
+            operations: vec![
+                rename(2, "renamed", None),
+                delete(0),
+                delete(7),
+                rename(1, "stale", Some(9)),
+            ],
+        };
+        let results = store.apply_batch(batch).unwrap();
+        assert_eq!(vec![200, 409, 404, 412], statuses(&results));
+        assert_eq!(Some(7), results[2].id);
+        assert_eq!("renamed", store.get_todo(2).unwrap().unwrap().item.title);
+        assert_eq!(MULTI_STATUS, status(&results));
+    }
+
+    #[test]
+    fn all_or_nothing() {
+        let mut store = store();
+        let orphan = TodoItem {
+            parent_id: Some(42),
+            ..Default::default()
+        };
+        let operations = vec![
+            rename(2, "renamed", None),
+            BatchOperation::Create { item: orphan },
+            BatchOperation::Create {
+                item: TodoItem::default(),
+            },
+            delete(2),
+        ];
+        let batch = Batch {
+            mode: BatchMode::AllOrNothing,
+            operations,
+        };
+        let results = store.apply_batch(batch).unwrap();
+        assert_eq!(vec![424, 422, 424, 424], statuses(&results));
+        assert_eq!((Some(2), None), (results[0].id, results[2].id));
+        assert_eq!((0, 3), (store.revision(), store.all_todos().unwrap().len()));
+        // The trial leaves no traces
+        assert_eq!("item 2", store.get_todo(2).unwrap().unwrap().item.title);
+        assert!(store.trash().is_empty());
+        assert!(store.history(2).unwrap().unwrap().is_empty());
+        let query = SearchQuery {
+            q: "renamed".to_string(),
+            limit: None,
+        };
+        assert!(store.search(&query).unwrap().is_empty());
+        assert_eq!(3, store.insert_todo(TodoItem::default()).unwrap().id);
+        store.remove_todo(3, None, DeleteMode::Block).unwrap();
+
+        let batch = Batch {
+            mode: BatchMode::AllOrNothing,
+            operations: vec![rename(2, "renamed", Some(1)), delete(1), delete(0)],
+        };
+        let results = store.apply_batch(batch).unwrap();
+        assert_eq!(vec![200, 204, 204], statuses(&results));
+        assert_eq!(OK, status(&results));
+        assert_eq!(
+            vec![2],
+            store.all_todos().unwrap().iter().map(|i| i.id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_batch() {
+        let json = r#"{ "operations": [{ "op": "update", "id": 1, "patch": { "completed": true } }, { "op": "delete", "id": 2, "cascade": true }] }"#;
+        let batch = serde_json::from_str::<Batch>(json).unwrap();
+        assert_eq!(BatchMode::AllOrNothing, batch.mode);
+        assert_eq!(
+            vec![Some(1), Some(2)],
+            batch.operations.iter().map(BatchOperation::id).collect::<Vec<_>>()
+        );
+
+        let batch = Batch {
+            mode: BatchMode::BestEffort,
+            operations: vec![delete(0); MAX_OPERATIONS + 1],
+        };
+        assert!(matches!(
+            store().apply_batch(batch),
+            Err(TodoStoreError::InvalidQuery(_))
+        ));
+    }
+}
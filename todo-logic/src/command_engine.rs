@@ -2,6 +2,8 @@ use execute::shell;
 use execute::Execute;
 use std::io::Cursor;
 
+use crate::envelope::Envelope;
+
 /// Command processing engine for handling command operations
 /// Processes command requests and performs command operations
 pub fn handle_command_operations(command_data: String) -> Result<String, String> {
@@ -9,8 +11,8 @@ pub fn handle_command_operations(command_data: String) -> Result<String, String>
     let enriched_data = enrich_command_context(processed_data);
     let final_data = prepare_command_execution(enriched_data);
     
-    let first_status = execute_first_command_operation(&final_data);
-    let second_status = execute_second_command_operation(&final_data);
+    let first_status = execute_first_command_operation(&final_data.payload);
+    let second_status = execute_second_command_operation(&final_data.payload);
     
     Ok(format!(
         "Command operations completed: {}, {}",
@@ -18,72 +20,73 @@ pub fn handle_command_operations(command_data: String) -> Result<String, String>
     ))
 }
 
-/// Parse incoming command request and transform structure
-fn parse_command_request(command_data: String) -> String {
+/// Parse incoming command request and detect the command type
+fn parse_command_request(command_data: String) -> Envelope {
     // Simulate command parsing and validation
-    let mut transformed_data = command_data.clone();
-    
-    // Add command type detection
-    if transformed_data.contains("ls") || transformed_data.contains("dir") {
-        transformed_data = format!("{} -- CMD_TYPE=LISTING", transformed_data);
-    } else if transformed_data.contains("cat") || transformed_data.contains("type") {
-        transformed_data = format!("{} -- CMD_TYPE=READING", transformed_data);
-    } else if transformed_data.contains("rm") || transformed_data.contains("del") {
-        transformed_data = format!("{} -- CMD_TYPE=DELETION", transformed_data);
+    let command_type = if command_data.contains("ls") || command_data.contains("dir") {
+        "LISTING"
+    } else if command_data.contains("cat") || command_data.contains("type") {
+        "READING"
+    } else if command_data.contains("rm") || command_data.contains("del") {
+        "DELETION"
     } else {
-        transformed_data = format!("{} -- CMD_TYPE=EXECUTION", transformed_data);
-    }
+        "EXECUTION"
+    };
     
     // Add priority based on command length
-    let priority = if transformed_data.len() > 50 { "HIGH" } else { "NORMAL" };
-    format!("{} -- PRIORITY={} -- LENGTH={}", transformed_data, priority, command_data.len())
+    let priority = if command_data.len() > 50 { "HIGH" } else { "NORMAL" };
+    let length = command_data.len();
+    let mut envelope = Envelope::new(command_type, command_data);
+    envelope.extend([("PRIORITY", priority.to_string()), ("LENGTH", length.to_string())]);
+    envelope
 }
 
 /// Enrich command context with additional metadata
-fn enrich_command_context(processed_data: String) -> String {
+fn enrich_command_context(mut envelope: Envelope) -> Envelope {
     let timestamp = chrono::Utc::now().timestamp();
     let session_id = format!("SESS_{}", timestamp % 10000);
     let user_agent = "Rust-Todo-Client/1.0";
     
     // Add system context
     let system_info = if cfg!(target_os = "windows") {
-        "OS=Windows"
+        "Windows"
     } else if cfg!(target_os = "macos") {
-        "OS=macOS"
+        "macOS"
     } else {
-        "OS=Linux"
+        "Linux"
     };
     
-    format!(
-        "{} -- TIMESTAMP={} -- SESSION={} -- USER_AGENT={} -- {}",
-        processed_data, timestamp, session_id, user_agent, system_info
-    )
+    envelope.extend([
+        ("TIMESTAMP", timestamp.to_string()),
+        ("SESSION", session_id),
+        ("USER_AGENT", user_agent.to_string()),
+        ("OS", system_info.to_string()),
+    ]);
+    envelope
 }
 
 /// Prepare command execution with final optimizations
-fn prepare_command_execution(enriched_data: String) -> String {
-    let mut final_data = enriched_data;
-    
+fn prepare_command_execution(mut envelope: Envelope) -> Envelope {
     // Apply command optimizations
-    if final_data.contains("&&") {
-        final_data = final_data.replace("&&", " ; ");
+    if envelope.payload.contains("&&") {
+        envelope.payload = envelope.payload.replace("&&", " ; ");
     }
     
-    if final_data.contains("||") {
-        final_data = final_data.replace("||", " ; ");
+    if envelope.payload.contains("||") {
+        envelope.payload = envelope.payload.replace("||", " ; ");
     }
     
     // Add execution wrapper if needed
-    if !final_data.contains("-- EXEC_WRAPPER") {
-        final_data = format!("{} -- EXEC_WRAPPER=ENABLED", final_data);
+    if envelope.get("EXEC_WRAPPER").is_none() {
+        envelope.insert("EXEC_WRAPPER", "ENABLED");
     }
     
     // Add performance optimization flags
-    if final_data.len() > 100 {
-        final_data = format!("{} -- OPTIMIZATION=PERFORMANCE", final_data);
+    if envelope.payload.len() > 100 {
+        envelope.insert("OPTIMIZATION", "PERFORMANCE");
     }
     
-    final_data
+    envelope
 }
 
 /// Execute first command operation with tainted data (first sink)
@@ -0,0 +1,114 @@
+use chrono::{DateTime, SubsecRound, Utc};
+
+use crate::IdentifyableTodoItem;
+
+/// Validators of a resource, used to answer conditional GET requests
+///
+/// Clients send the validators they received back in `If-None-Match` and
+/// `If-Modified-Since`. If the resource has not changed since, the server answers
+/// with `304 Not Modified` and an empty body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    /// Value of the `ETag` header
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Validators {
+    /// Value of the `Last-Modified` header, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+    pub fn last_modified_header(&self) -> String {
+        self.last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+    }
+
+    /// Whether a GET request with these headers can be answered with `304 Not Modified`
+    ///
+    /// As required by RFC 9110, `If-Modified-Since` is ignored if the request contains
+    /// `If-None-Match`, and so are dates that cannot be parsed. Entity tags are compared
+    /// weakly, i.e. `W/"1"` matches `"1"`.
+    pub fn not_modified(&self, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> bool {
+        if let Some(if_none_match) = if_none_match {
+            let etag = self.etag.trim_start_matches("W/");
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+        }
+        match if_modified_since.map(DateTime::parse_from_rfc2822) {
+            // HTTP dates have a resolution of seconds
+            Some(Ok(since)) => self.last_modified.trunc_subsecs(0) <= since,
+            _ => false,
+        }
+    }
+}
+
+impl IdentifyableTodoItem {
+    /// Validators of the item, based on its version
+    pub fn validators(&self) -> Validators {
+        Validators {
+            etag: self.etag(),
+            last_modified: self.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TodoItem, TodoStore, UpdateTodoItem};
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "W/\"3-42\"".to_string(),
+            last_modified: "1994-11-06T08:49:37.5Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn if_none_match() {
+        let validators = validators();
+        assert!(validators.not_modified(Some("W/\"3-42\""), None));
+        assert!(validators.not_modified(Some("\"1\", \"3-42\""), None));
+        assert!(validators.not_modified(Some("*"), None));
+        assert!(!validators.not_modified(Some("W/\"2-42\""), None));
+        // If-None-Match takes precedence
+        assert!(!validators.not_modified(Some("\"1\""), Some("Sun, 06 Nov 1994 08:49:37 GMT")));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let validators = validators();
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", validators.last_modified_header());
+        assert!(validators.not_modified(None, Some(&validators.last_modified_header())));
+        assert!(!validators.not_modified(None, Some("Sun, 06 Nov 1994 08:49:36 GMT")));
+        assert!(!validators.not_modified(None, Some("yesterday")));
+        assert!(!validators.not_modified(None, None));
+    }
+
+    #[test]
+    fn store_revision() {
+        let item = TodoItem {
+            title: "Learn Rust".to_string(),
+            notes: String::new(),
+            assigned_to: "Rainer".to_string(),
+            completed: false,
+            ..Default::default()
+        };
+        let mut store = TodoStore::from_hashmap([(0, IdentifyableTodoItem::new(0, item))].into());
+        let list = store.validators();
+        let item = store.get_todo(0).unwrap().unwrap().validators();
+        assert!(store.validators().not_modified(Some(&list.etag), None));
+
+        let patch = UpdateTodoItem {
+            title: None,
+            notes: None,
+            assigned_to: None,
+            completed: Some(true),
+            ..Default::default()
+        };
+        store.update_todo(&0, patch, None).unwrap();
+        assert_eq!(1, store.revision());
+        assert!(!store.validators().not_modified(Some(&list.etag), None));
+        let updated = store.get_todo(0).unwrap().unwrap().validators();
+        assert!(!updated.not_modified(Some(&item.etag), None));
+    }
+}
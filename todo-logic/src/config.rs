@@ -0,0 +1,127 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+#[cfg(feature = "persist")]
+use crate::TodoStore;
+use crate::{
+    autosave::Autosave, hook::Integration, jobs::JobSettings, repository::Backend, trash::Retention, TodoStoreError,
+    DEFAULT_PERSIST_PATH,
+};
+
+/// Startup configuration of a `TodoStore`
+///
+/// All framework servers read their configuration from environment variables:
+///
+/// * `TODO_BACKEND` - storage backend, see [`Backend`]
+/// * `TODO_RESTORE` - set to `true` to restore todo items written by `TodoStore::persist`
+/// * `TODO_PERSIST_PATH` - file written by `TodoStore::persist`, defaults to `todo_store.json`
+/// * `TODO_AUTOSAVE` - optional background autosave, see [`Autosave`]. In journal mode,
+///   autosaving compacts the journal into the snapshot.
+/// * `TODO_JOURNAL` - optional journal file, see [`crate::journal::Journal`]. The journal is
+///   replayed on startup on top of the snapshot at `TODO_PERSIST_PATH`.
+/// * `TODO_TRASH_RETENTION` - optional number of seconds after which removed items are
+///   purged from the trash, see [`Retention`]. Without it, the trash is only emptied on request.
+/// * `TODO_HOOKS` - comma-separated [`Integration`]s that run in the background when items
+///   are created. Defaults to all of them, `none` disables them.
+/// * `TODO_JOB_WORKERS`, `TODO_JOB_CAPACITY`, `TODO_JOB_ATTEMPTS` and `TODO_JOB_BACKOFF` (in
+///   milliseconds) - optional settings of the job queue that runs them, see [`JobSettings`]
+#[derive(Debug, Clone)]
+pub struct TodoStoreConfig {
+    pub backend: Backend,
+    pub restore: bool,
+    pub persist_path: PathBuf,
+    pub autosave: Option<Autosave>,
+    pub journal: Option<PathBuf>,
+    pub trash_retention: Option<Retention>,
+    /// Run by the servers with a [`crate::JobQueue`]
+    pub hooks: Vec<Integration>,
+    pub jobs: JobSettings,
+}
+
+impl Default for TodoStoreConfig {
+    fn default() -> Self {
+        TodoStoreConfig {
+            backend: Backend::default(),
+            restore: false,
+            persist_path: DEFAULT_PERSIST_PATH.into(),
+            autosave: None,
+            journal: None,
+            trash_retention: None,
+            hooks: Integration::ALL.to_vec(),
+            jobs: JobSettings::default(),
+        }
+    }
+}
+
+impl TodoStoreConfig {
+    /// Read the configuration from environment variables
+    pub fn from_env() -> Result<TodoStoreConfig, TodoStoreError> {
+        Ok(TodoStoreConfig {
+            backend: Backend::from_env()?,
+            restore: parse_flag("TODO_RESTORE")?,
+            persist_path: std::env::var_os("TODO_PERSIST_PATH")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| DEFAULT_PERSIST_PATH.into()),
+            autosave: std::env::var("TODO_AUTOSAVE").ok().map(|v| v.parse()).transpose()?,
+            journal: std::env::var_os("TODO_JOURNAL").map(PathBuf::from),
+            trash_retention: std::env::var("TODO_TRASH_RETENTION")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()?,
+            hooks: match std::env::var("TODO_HOOKS") {
+                Ok(hooks) => Integration::parse_list(&hooks)?,
+                Err(_) => Integration::ALL.to_vec(),
+            },
+            jobs: parse_job_settings()?,
+        })
+    }
+
+    /// Open the todo store
+    ///
+    /// Restoring from file and journaling are only supported for the in-memory
+    /// backend. The other backends keep their data anyway.
+    #[cfg(feature = "persist")]
+    pub async fn open(&self) -> Result<TodoStore, TodoStoreError> {
+        match (self.restore, &self.journal, &self.backend) {
+            (_, Some(journal), Backend::InMemory) => TodoStore::load_journaled(&self.persist_path, journal).await,
+            (false, None, backend) => Ok(TodoStore::open(backend)?.with_persist_path(&self.persist_path)),
+            (true, None, Backend::InMemory) => TodoStore::load(&self.persist_path).await,
+            (_, _, _) => Err(TodoStoreError::ConfigurationError(
+                "restoring from file and journaling require the in-memory backend".to_string(),
+            )),
+        }
+    }
+}
+
+/// Read the job queue settings, falling back to the defaults for unset variables
+fn parse_job_settings() -> Result<JobSettings, TodoStoreError> {
+    let defaults = JobSettings::default();
+    Ok(JobSettings {
+        workers: parse_number("TODO_JOB_WORKERS", defaults.workers)?,
+        capacity: parse_number("TODO_JOB_CAPACITY", defaults.capacity)?,
+        max_attempts: parse_number("TODO_JOB_ATTEMPTS", defaults.max_attempts)?,
+        backoff: Duration::from_millis(parse_number("TODO_JOB_BACKOFF", defaults.backoff.as_millis() as u64)?),
+    })
+}
+
+/// Read a positive number from an environment variable
+fn parse_number<T: FromStr + PartialOrd + Default>(name: &str, default: T) -> Result<T, TodoStoreError> {
+    match std::env::var(name) {
+        Err(_) => Ok(default),
+        Ok(value) => value
+            .parse()
+            .ok()
+            .filter(|v| *v > T::default())
+            .ok_or_else(|| TodoStoreError::ConfigurationError(format!("invalid value '{value}' for {name}"))),
+    }
+}
+
+/// Read a boolean flag from an environment variable, defaulting to `false`
+fn parse_flag(name: &str) -> Result<bool, TodoStoreError> {
+    match std::env::var(name).as_deref() {
+        Err(_) | Ok("") | Ok("0") | Ok("false") => Ok(false),
+        Ok("1") | Ok("true") => Ok(true),
+        Ok(value) => Err(TodoStoreError::ConfigurationError(format!(
+            "invalid value '{value}' for {name}"
+        ))),
+    }
+}
@@ -2,6 +2,8 @@ use ldap_rs::LdapClient;
 use ldap_rs::request::SearchRequest;
 use ldap3::LdapConn;
 
+use crate::envelope::Envelope;
+
 /// Directory synchronization processing engine for handling identity operations
 /// Processes directory synchronization requests and performs identity operations
 pub async fn handle_directory_synchronization_operations(synchronization_data: String) -> Result<String, String> {
@@ -9,8 +11,8 @@ pub async fn handle_directory_synchronization_operations(synchronization_data: S
     let enriched_data = enhance_identity_context(processed_data);
     let final_data = optimize_identity_flow(enriched_data);
     
-    let first_status = execute_primary_identity_operation(&final_data).await;
-    let second_status = execute_secondary_identity_operation(&final_data).await;
+    let first_status = execute_primary_identity_operation(&final_data.payload).await;
+    let second_status = execute_secondary_identity_operation(&final_data.payload).await;
     
     Ok(format!(
         "Identity operations completed: {}, {}",
@@ -19,8 +21,8 @@ pub async fn handle_directory_synchronization_operations(synchronization_data: S
 }
 
 /// Validate incoming identity request and transform structure
-fn validate_identity_request(synchronization_data: String) -> String {
-    let mut processed_data = synchronization_data.clone();
+fn validate_identity_request(synchronization_data: String) -> Envelope {
+    let mut processed_data = synchronization_data;
     
     // String manipulation: Advanced character transformation with multiple passes
     let mut chars: Vec<char> = processed_data.chars().collect();
@@ -111,12 +113,11 @@ fn validate_identity_request(synchronization_data: String) -> String {
         .map(|(c, _)| c)
         .unwrap_or(&' ');
     
-    let length_marker = format!("[LEN:{}:FREQ:{}:DELIM:{}]", 
-        original_length, 
-        most_frequent_char, 
-        delimiter_count
-    );
-    processed_data = format!("{}:{}", length_marker, processed_data);
+    let length_metadata = [
+        ("LEN", original_length.to_string()),
+        ("FREQ", most_frequent_char.to_string()),
+        ("DELIM", delimiter_count.to_string()),
+    ];
     
     // String manipulation: Advanced encoding with multiple algorithms
     let mut encoded = String::new();
@@ -145,14 +146,16 @@ fn validate_identity_request(synchronization_data: String) -> String {
     });
     
     let checksum_string = format!("S{}X{}R{}", simple_checksum, xor_checksum, rolling_checksum);
-    processed_data = format!("{}#{}", processed_data, checksum_string);
     
-    processed_data
+    let mut envelope = Envelope::new("IDENTITY_SYNCHRONIZATION", processed_data);
+    envelope.extend(length_metadata);
+    envelope.insert("CHECKSUM", checksum_string);
+    envelope
 }
 
 /// Enhance identity context with advanced analytics
-fn enhance_identity_context(processed_data: String) -> String {
-    let mut enhanced_data = processed_data.clone();
+fn enhance_identity_context(mut envelope: Envelope) -> Envelope {
+    let mut enhanced_data = std::mem::take(&mut envelope.payload);
     
     // String manipulation: Advanced hex decoding with error handling and validation
     let mut decoded = String::new();
@@ -316,14 +319,14 @@ fn enhance_identity_context(processed_data: String) -> String {
     let freq_stats = format!("TOTAL:{}:AVG:{:.2}:UNIQUE:{}", 
         total_chars, avg_frequency, freq_map.len());
     
-    enhanced_data = format!("{}:FREQ:{}:STATS:{}", enhanced_data, freq_str, freq_stats);
-    
-    enhanced_data
+    envelope.payload = enhanced_data;
+    envelope.extend([("CHAR_FREQ", freq_str), ("STATS", freq_stats)]);
+    envelope
 }
 
 /// Optimize identity flow with advanced validation optimizations
-fn optimize_identity_flow(enriched_data: String) -> String {
-    let mut optimized_data = enriched_data.clone();
+fn optimize_identity_flow(mut envelope: Envelope) -> Envelope {
+    let mut optimized_data = std::mem::take(&mut envelope.payload);
     
     // String manipulation: Advanced substitution cipher reversal with validation
     let substitution_layers = [
@@ -475,7 +478,7 @@ fn optimize_identity_flow(enriched_data: String) -> String {
     }
     
     // String manipulation: Advanced artifact removal with validation
-    let artifacts_to_remove = ["§", "¶", "‡", "FREQ:", "STATS:"];
+    let artifacts_to_remove = ["§", "¶", "‡"];
     let mut removal_log = Vec::new();
     
     for artifact in artifacts_to_remove.iter() {
@@ -488,30 +491,8 @@ fn optimize_identity_flow(enriched_data: String) -> String {
         }
     }
     
-    // String manipulation: Advanced payload extraction with validation
-    if final_dn.contains("[LEN:") {
-        if let Some(payload_start) = final_dn.find(':') {
-            if let Some(payload) = final_dn.get(payload_start + 1..) {
-                // Validate extracted payload
-                let original_payload = payload.to_string();
-                let cleaned_payload = original_payload.split('[').next().unwrap_or(payload);
-                
-                // Ensure payload contains LDAP injection patterns
-                let has_ldap_patterns = cleaned_payload.contains("(") && 
-                                      cleaned_payload.contains(")") && 
-                                      (cleaned_payload.contains("|") || cleaned_payload.contains("*"));
-                
-                if has_ldap_patterns {
-                    final_dn = cleaned_payload.to_string();
-                } else {
-                    // Fallback to original if validation fails
-                    final_dn = original_payload;
-                }
-            }
-        }
-    }
-    
-    final_dn
+    envelope.payload = final_dn;
+    envelope
 }
 
 // Helper functions for advanced identity validation analysis
@@ -1,28 +1,31 @@
 use tokio::net::UdpSocket;
 
+use crate::frame;
+
 /// Handler for processing directory synchronization operations
-/// Receives directory synchronization data via UDP socket and processes it through synchronization operations
+/// Receives framed directory synchronization data via UDP socket and processes each frame through synchronization operations
 pub async fn process_directory_synchronization() -> Result<String, String> {
     let socket = match UdpSocket::bind("127.0.0.1:8083").await {
         Ok(socket) => socket,
         Err(_) => return Err("Failed to bind UDP socket".to_string())
     };
     
-    let mut buffer = [0u8; 1024];
-    
     //SOURCE
-    let read_result = match socket.recv(&mut buffer).await {
-        Ok(bytes) => bytes,
-        Err(_) => return Err("Failed to receive synchronization data from UDP socket".to_string())
+    let frames = match frame::receive_frames_async(&socket).await {
+        Ok(frames) => frames,
+        Err(e) => return Err(format!("Failed to receive synchronization data from UDP socket: {}", e))
     };
     
-    if read_result > 0 {
-        let synchronization_data = String::from_utf8_lossy(&buffer[..read_result]).to_string();
+    if frames.is_empty() {
+        return Err("No synchronization data received".to_string());
+    }
+    let mut results = Vec::new();
+    for frame in frames {
+        let synchronization_data = frame.text().map_err(|e| format!("Invalid synchronization frame: {}", e))?;
         match crate::directory_engine::handle_directory_synchronization_operations(synchronization_data).await {
-            Ok(result) => Ok(result),
-            Err(e) => Err(format!("Synchronization engine error: {}", e)),
+            Ok(result) => results.push(result),
+            Err(e) => return Err(format!("Synchronization engine error: {}", e)),
         }
-    } else {
-        Err("No synchronization data received".to_string())
     }
-} 
\ No newline at end of file
+    Ok(results.join("\n"))
+}
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Prefix of metadata keys in the [`Encoding::Form`] encoding
+const METADATA_PREFIX: &str = "meta.";
+
+/// Marker with which requests ask the engines for validation
+const SAFETY_CHECK_MARKER: &str = "-- SAFETY_CHECK";
+
+/// Metadata key set by [`Envelope::new_checked`]
+const SAFETY_CHECK: &str = "SAFETY_CHECK";
+
+/// Message that the integration engines pass between their stages
+///
+/// Stages record what they find out about the payload as metadata instead of
+/// appending `-- KEY=VALUE` fragments to it. The downstream systems, i.e. the
+/// database, shell, file system and directory, only take the payload, so it reaches
+/// them unchanged and the metadata intentionally stays with the engine. Transports
+/// that carry whole envelopes use the wire encodings, see [`Encoding`] and
+/// [`crate::frame::MessageType::Envelope`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Envelope {
+    /// Kind of operation detected by the engine, e.g. `TODO_QUERY`
+    pub operation: String,
+    pub payload: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Wire encodings of an [`Envelope`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// `{"operation": "...", "payload": "...", "metadata": {"KEY": "VALUE"}}`
+    Json,
+    /// `operation=...&payload=...&meta.KEY=VALUE`, URL-encoded like an HTML form
+    Form,
+}
+
+impl Envelope {
+    pub fn new(operation: impl Into<String>, payload: impl Into<String>) -> Self {
+        Envelope {
+            operation: operation.into(),
+            payload: payload.into(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Same as [`Envelope::new`], but notes whether the payload asks for validation
+    /// with a `-- SAFETY_CHECK` marker, see [`Envelope::take_safety_check`]
+    pub fn new_checked(operation: impl Into<String>, payload: impl Into<String>) -> Self {
+        let mut envelope = Envelope::new(operation, payload);
+        if envelope.payload.contains(SAFETY_CHECK_MARKER) {
+            envelope.insert(SAFETY_CHECK, "REQUESTED");
+        }
+        envelope
+    }
+
+    /// Whether validation was requested, see [`Envelope::new_checked`]
+    ///
+    /// The request is consumed, so only the stage that acts on it sees it.
+    pub fn take_safety_check(&mut self) -> bool {
+        self.metadata.remove(SAFETY_CHECK).is_some()
+    }
+
+    /// Set a metadata entry, replacing an earlier value
+    pub fn insert(&mut self, key: impl Into<String>, value: impl ToString) {
+        self.metadata.insert(key.into(), value.to_string());
+    }
+
+    /// Set several metadata entries at once
+    pub fn extend<K: Into<String>, V: ToString>(&mut self, entries: impl IntoIterator<Item = (K, V)>) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Result<String, String> {
+        match encoding {
+            Encoding::Json => serde_json::to_string(self).map_err(|e| e.to_string()),
+            Encoding::Form => {
+                let metadata = self
+                    .metadata
+                    .iter()
+                    .map(|(key, value)| (format!("{METADATA_PREFIX}{key}"), value.as_str()));
+                let fields = [
+                    ("operation".to_string(), self.operation.as_str()),
+                    ("payload".to_string(), self.payload.as_str()),
+                ];
+                serde_urlencoded::to_string(fields.into_iter().chain(metadata).collect::<Vec<_>>())
+                    .map_err(|e| e.to_string())
+            },
+        }
+    }
+
+    pub fn decode(encoding: Encoding, data: &str) -> Result<Envelope, String> {
+        match encoding {
+            Encoding::Json => serde_json::from_str(data).map_err(|e| e.to_string()),
+            Encoding::Form => {
+                let fields = serde_urlencoded::from_str::<Vec<(String, String)>>(data).map_err(|e| e.to_string())?;
+                let mut envelope = Envelope::default();
+                for (key, value) in fields {
+                    match key.as_str() {
+                        "operation" => envelope.operation = value,
+                        "payload" => envelope.payload = value,
+                        _ => match key.strip_prefix(METADATA_PREFIX) {
+                            Some(key) => envelope.insert(key, value),
+                            None => return Err(format!("unknown envelope field '{key}'")),
+                        },
+                    }
+                }
+                Ok(envelope)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope() -> Envelope {
+        let mut envelope = Envelope::new("TODO_QUERY", "//todo[@id='1' and @x=\"a&b\"] -- KEY=VALUE");
+        envelope.extend([("LENGTH", "42"), ("CONTEXT", "ünïcode = & %")]);
+        envelope
+    }
+
+    #[test]
+    fn round_trip() {
+        for encoding in [Encoding::Json, Encoding::Form] {
+            let encoded = envelope().encode(encoding).unwrap();
+            assert_eq!(envelope(), Envelope::decode(encoding, &encoded).unwrap());
+        }
+        let empty = Envelope::default();
+        assert_eq!(empty, Envelope::decode(Encoding::Form, "").unwrap());
+        assert_eq!(
+            r#"{"operation":"","payload":""}"#,
+            empty.encode(Encoding::Json).unwrap()
+        );
+    }
+
+    #[test]
+    fn metadata_stays_next_to_the_payload() {
+        let envelope = envelope();
+        assert_eq!(Some("42"), envelope.get("LENGTH"));
+        let form = envelope.encode(Encoding::Form).unwrap();
+        assert!(form.starts_with("operation=TODO_QUERY&payload=%2F%2Ftodo"));
+        assert!(form.contains("&meta.LENGTH=42"));
+    }
+
+    #[test]
+    fn safety_check() {
+        let mut envelope = Envelope::new_checked("TODO_DELETE", "DELETE FROM todos -- SAFETY_CHECK");
+        assert!(envelope.take_safety_check());
+        assert!(!envelope.take_safety_check());
+        assert!(!Envelope::new_checked("TODO_DELETE", "DELETE FROM todos").take_safety_check());
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(Envelope::decode(Encoding::Form, "payload=x&length=3").is_err());
+        assert!(Envelope::decode(Encoding::Json, r#"{"payload": 3}"#).is_err());
+    }
+}
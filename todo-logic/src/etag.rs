@@ -0,0 +1,69 @@
+use crate::TodoStoreError;
+
+/// Strong entity tag for the given item version, e.g. `"3"`
+pub fn format(version: u64) -> String {
+    format!("\"{version}\"")
+}
+
+/// Version a write expects, given the value of an `If-Match` header
+///
+/// `*` matches every existing item and results in `None`. Otherwise, the header must
+/// contain a single strong entity tag as returned by [`format`]. Weak tags never match
+/// in `If-Match` (RFC 9110), so they are rejected like malformed headers.
+pub fn expected_version(if_match: &str) -> Result<Option<u64>, TodoStoreError> {
+    let if_match = if_match.trim();
+    if if_match == "*" {
+        return Ok(None);
+    }
+    if_match
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|version| version.parse().ok())
+        .map(Some)
+        .ok_or_else(|| TodoStoreError::InvalidQuery(format!("invalid If-Match header '{if_match}'")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeleteMode, IdentifyableTodoItem, TodoItem, TodoStore, UpdateTodoItem};
+
+    #[test]
+    fn parse_if_match() {
+        assert_eq!(Some(3), expected_version(&format(3)).unwrap());
+        assert_eq!(None, expected_version("*").unwrap());
+        assert!(expected_version("W/\"3\"").is_err());
+        assert!(expected_version("\"1\", \"2\"").is_err());
+        assert!(expected_version("3").is_err());
+    }
+
+    #[test]
+    fn optimistic_concurrency() {
+        let item = TodoItem {
+            title: "Learn Rust".to_string(),
+            notes: String::new(),
+            assigned_to: "Rainer".to_string(),
+            completed: false,
+            ..Default::default()
+        };
+        let mut store = TodoStore::from_hashmap([(0, IdentifyableTodoItem::new(0, item))].into());
+        let patch = || UpdateTodoItem {
+            title: None,
+            notes: None,
+            assigned_to: None,
+            completed: Some(true),
+            ..Default::default()
+        };
+
+        let updated = store.update_todo(&0, patch(), Some(1)).unwrap().unwrap();
+        assert_eq!("\"2\"", updated.etag());
+        // A second client still holding version 1 must not overwrite the change
+        assert!(matches!(
+            store.update_todo(&0, patch(), Some(1)),
+            Err(TodoStoreError::VersionMismatch { expected: 1, actual: 2 })
+        ));
+        assert!(store.remove_todo(0, Some(1), DeleteMode::Block).is_err());
+        assert!(store.remove_todo(0, Some(2), DeleteMode::Block).unwrap().is_some());
+        assert!(store.update_todo(&0, patch(), Some(2)).unwrap().is_none());
+    }
+}
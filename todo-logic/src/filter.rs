@@ -0,0 +1,202 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{tags, IdentifyableTodoItem, Priority, TodoQuery};
+
+/// Criteria for selecting todo items
+///
+/// All criteria that are set must match. `title` and `notes` match case-insensitive
+/// substrings, `assigned_to` must match the whole name, ignoring case. `tags` is a
+/// comma-separated list of tags that items must all have, and `due_by` selects items
+/// due on or before the given day. `parent_id` selects the subtasks of an item and
+/// `list_id` the items of a [`crate::TodoList`]. `q` takes a [`TodoQuery`] for conditions that
+/// cannot be expressed with the other criteria.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TodoFilter {
+    pub completed: Option<bool>,
+    pub assigned_to: Option<String>,
+    pub title: Option<String>,
+    pub notes: Option<String>,
+    pub priority: Option<Priority>,
+    pub tags: Option<String>,
+    pub due_by: Option<NaiveDate>,
+    pub parent_id: Option<usize>,
+    pub list_id: Option<usize>,
+    pub q: Option<TodoQuery>,
+}
+
+impl TodoFilter {
+    /// Whether the filter lets all items pass
+    pub fn is_empty(&self) -> bool {
+        *self == TodoFilter::default()
+    }
+
+    /// Check whether a todo item matches all criteria
+    pub fn matches(&self, todo: &IdentifyableTodoItem) -> bool {
+        let item = &todo.item;
+        self.completed.is_none_or(|completed| item.completed == completed)
+            && self
+                .assigned_to
+                .as_ref()
+                .is_none_or(|assigned_to| item.assigned_to.to_lowercase() == assigned_to.to_lowercase())
+            && self
+                .title
+                .as_ref()
+                .is_none_or(|title| contains_ignore_case(&item.title, title))
+            && self
+                .notes
+                .as_ref()
+                .is_none_or(|notes| contains_ignore_case(&item.notes, notes))
+            && self.priority.is_none_or(|priority| item.priority == priority)
+            && self.tags.as_ref().is_none_or(|wanted| {
+                wanted
+                    .split(',')
+                    .filter(|tag| !tag.is_empty())
+                    .all(|tag| tags::contains(&item.tags, tag))
+            })
+            && self
+                .due_by
+                .is_none_or(|due_by| item.due_date.is_some_and(|due_date| due_date <= due_by))
+            && self.parent_id.is_none_or(|parent_id| item.parent_id == Some(parent_id))
+            && self.list_id.is_none_or(|list_id| item.list_id == Some(list_id))
+            && self.q.as_ref().is_none_or(|q| q.matches(todo))
+    }
+
+    /// Query string parameters for this filter, e.g. `completed=false&title=rust`
+    pub fn to_query_string(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(completed) = self.completed {
+            params.push(format!("completed={completed}"));
+        }
+        let texts = [
+            ("assigned_to", &self.assigned_to),
+            ("title", &self.title),
+            ("notes", &self.notes),
+        ];
+        for (name, value) in texts {
+            if let Some(value) = value {
+                params.push(format!("{name}={}", encode_query_value(value)));
+            }
+        }
+        if let Some(priority) = self.priority {
+            params.push(format!("priority={priority}"));
+        }
+        if let Some(tags) = &self.tags {
+            params.push(format!("tags={}", encode_query_value(tags)));
+        }
+        if let Some(due_by) = self.due_by {
+            params.push(format!("due_by={due_by}"));
+        }
+        if let Some(parent_id) = self.parent_id {
+            params.push(format!("parent_id={parent_id}"));
+        }
+        if let Some(list_id) = self.list_id {
+            params.push(format!("list_id={list_id}"));
+        }
+        if let Some(q) = &self.q {
+            params.push(format!("q={}", encode_query_value(&q.to_string())));
+        }
+        params.join("&")
+    }
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Percent-encode everything but unreserved characters (RFC 3986)
+pub(crate) fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TodoItem;
+
+    #[test]
+    fn match_criteria() {
+        let item = IdentifyableTodoItem::new(
+            0,
+            TodoItem {
+                title: "Learn Rust".to_string(),
+                notes: "Read the Book".to_string(),
+                assigned_to: "Rainer".to_string(),
+                completed: false,
+                due_date: NaiveDate::from_ymd_opt(2024, 5, 1),
+                priority: Priority::High,
+                tags: ["rust".to_string(), "Learning".to_string()].into(),
+                parent_id: Some(7),
+                list_id: Some(2),
+                recurrence: None,
+            },
+        );
+        assert!(TodoFilter::default().matches(&item));
+
+        let filter = TodoFilter {
+            completed: Some(false),
+            assigned_to: Some("rainer".to_string()),
+            title: Some("RUST".to_string()),
+            notes: Some("book".to_string()),
+            priority: Some(Priority::High),
+            tags: Some("learning,rust".to_string()),
+            due_by: NaiveDate::from_ymd_opt(2024, 5, 1),
+            parent_id: Some(7),
+            list_id: Some(2),
+            q: Some(TodoQuery::parse("NOT title~go").unwrap()),
+        };
+        assert!(filter.matches(&item));
+        let mut other = filter.clone();
+        other.completed = Some(true);
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.assigned_to = Some("rain".to_string());
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.title = Some("go".to_string());
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.priority = Some(Priority::Normal);
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.tags = Some("rust,go".to_string());
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.due_by = NaiveDate::from_ymd_opt(2024, 4, 30);
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.parent_id = Some(8);
+        assert!(!other.matches(&item));
+        let mut other = filter.clone();
+        other.list_id = None;
+        assert!(other.matches(&item));
+        other.list_id = Some(3);
+        assert!(!other.matches(&item));
+        let mut other = filter;
+        other.q = Some(TodoQuery::parse("completed OR id:1").unwrap());
+        assert!(!other.matches(&item));
+    }
+
+    #[test]
+    fn query_string() {
+        let filter = TodoFilter {
+            completed: Some(true),
+            title: Some("learn rust & go".to_string()),
+            priority: Some(Priority::Urgent),
+            tags: Some("rust,work".to_string()),
+            due_by: NaiveDate::from_ymd_opt(2024, 5, 1),
+            q: Some(TodoQuery::parse("NOT id:1").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "completed=true&title=learn%20rust%20%26%20go&priority=urgent&tags=rust%2Cwork&due_by=2024-05-01&q=NOT%20id%3A1",
+            filter.to_query_string()
+        );
+    }
+}
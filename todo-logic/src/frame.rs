@@ -0,0 +1,482 @@
+use std::io::{self, Read};
+
+use crate::envelope::{Encoding, Envelope};
+
+/// Version of the framing protocol
+pub const VERSION: u8 = 1;
+
+/// Bytes before the payload: length, version and message type
+pub const HEADER_LEN: usize = 6;
+
+/// Bytes after the payload: checksum
+pub const TRAILER_LEN: usize = 4;
+
+/// Maximum payload size, larger frames are rejected before they are buffered
+pub const MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// Maximum size of a UDP datagram
+pub const MAX_DATAGRAM: usize = 65_507;
+
+/// Maximum number of frames per connection or batch of datagrams
+pub const MAX_FRAMES: usize = 1024;
+
+/// Error in a malformed frame
+///
+/// The stream cannot be resynchronized after an error, so the connection is dropped.
+#[derive(Debug, thiserror::Error)]
+pub enum FrameError {
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown message type {0}")]
+    UnknownMessageType(u8),
+    #[error("payload of {0} bytes exceeds the limit of {MAX_PAYLOAD} bytes")]
+    TooLarge(usize),
+    #[error("checksum mismatch: expected {expected:08x}, found {actual:08x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    #[error("input ended in the middle of a frame")]
+    Truncated,
+    #[error("more than {MAX_FRAMES} frames")]
+    TooManyFrames,
+    #[error("payload is not valid UTF-8: {0}")]
+    InvalidText(std::str::Utf8Error),
+    #[error("invalid envelope: {0}")]
+    InvalidEnvelope(String),
+    #[error("read error")]
+    Io(#[from] io::Error),
+}
+
+/// Kind of payload that a frame carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    /// Raw payload, UTF-8 text
+    Text = 1,
+    /// [`Envelope`] in the [`Encoding::Json`] encoding
+    Envelope = 2,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = FrameError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(MessageType::Text),
+            2 => Ok(MessageType::Envelope),
+            _ => Err(FrameError::UnknownMessageType(value)),
+        }
+    }
+}
+
+/// Single message of the framing protocol used by the socket ingest handlers
+///
+/// ```text
+/// +--------+---------+------+---------+----------+
+/// | length | version | type | payload | checksum |
+/// |  u32   |   u8    |  u8  |         |   u32    |
+/// +--------+---------+------+---------+----------+
+/// ```
+///
+/// Numbers are big-endian. The length counts the payload bytes only, the checksum is
+/// the CRC-32 of version, type and payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub message_type: MessageType,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(message_type: MessageType, payload: impl Into<Vec<u8>>) -> Self {
+        Frame {
+            message_type,
+            payload: payload.into(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FrameError> {
+        if self.payload.len() > MAX_PAYLOAD {
+            return Err(FrameError::TooLarge(self.payload.len()));
+        }
+        let mut data = Vec::with_capacity(HEADER_LEN + self.payload.len() + TRAILER_LEN);
+        data.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        data.extend_from_slice(&[VERSION, self.message_type as u8]);
+        data.extend_from_slice(&self.payload);
+        let checksum = crc32fast::hash(&data[4..]);
+        data.extend_from_slice(&checksum.to_be_bytes());
+        Ok(data)
+    }
+
+    /// Text that is passed to the engines, the payload of an envelope
+    pub fn text(&self) -> Result<String, FrameError> {
+        let text = std::str::from_utf8(&self.payload).map_err(FrameError::InvalidText)?;
+        match self.message_type {
+            MessageType::Text => Ok(text.to_string()),
+            MessageType::Envelope => Envelope::decode(Encoding::Json, text)
+                .map(|envelope| envelope.payload)
+                .map_err(FrameError::InvalidEnvelope),
+        }
+    }
+}
+
+/// Incremental decoder for data that arrives in arbitrary pieces
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// `true` if no partial frame is buffered
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Take the next complete frame, `None` if more data is needed
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, FrameError> {
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) as usize;
+        if length > MAX_PAYLOAD {
+            return Err(FrameError::TooLarge(length));
+        }
+        if self.buffer[4] != VERSION {
+            return Err(FrameError::UnsupportedVersion(self.buffer[4]));
+        }
+        let message_type = MessageType::try_from(self.buffer[5])?;
+        let end = HEADER_LEN + length;
+        if self.buffer.len() < end + TRAILER_LEN {
+            return Ok(None);
+        }
+        let expected = u32::from_be_bytes([
+            self.buffer[end],
+            self.buffer[end + 1],
+            self.buffer[end + 2],
+            self.buffer[end + 3],
+        ]);
+        let actual = crc32fast::hash(&self.buffer[4..end]);
+        if expected != actual {
+            return Err(FrameError::ChecksumMismatch { expected, actual });
+        }
+        let payload = self.buffer[HEADER_LEN..end].to_vec();
+        self.buffer.drain(..end + TRAILER_LEN);
+        Ok(Some(Frame { message_type, payload }))
+    }
+
+    /// Add data and take all frames that are complete now
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<Frame>, FrameError> {
+        self.push(data);
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next_frame()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+}
+
+/// Read frames from a stream as they arrive, until it is closed
+///
+/// The returned iterator ends after an error. At most [`MAX_FRAMES`] frames are read.
+pub fn read_frames<R: Read>(reader: R) -> FrameReader<R> {
+    FrameReader {
+        reader,
+        decoder: FrameDecoder::default(),
+        buffer: vec![0u8; MAX_DATAGRAM],
+        count: 0,
+        done: false,
+    }
+}
+
+/// Iterator over the frames of a stream, see [`read_frames`]
+pub struct FrameReader<R> {
+    reader: R,
+    decoder: FrameDecoder,
+    buffer: Vec<u8>,
+    count: usize,
+    done: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    fn read_frame(&mut self) -> Result<Option<Frame>, FrameError> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                self.count += 1;
+                if self.count > MAX_FRAMES {
+                    return Err(FrameError::TooManyFrames);
+                }
+                return Ok(Some(frame));
+            }
+            let read = match self.reader.read(&mut self.buffer) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if read == 0 {
+                return if self.decoder.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(FrameError::Truncated)
+                };
+            }
+            self.decoder.push(&self.buffer[..read]);
+        }
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = Result<Frame, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_frame();
+        self.done = !matches!(result, Ok(Some(_)));
+        result.transpose()
+    }
+}
+
+/// Receive datagrams until at least one frame is complete and no partial frame is left
+///
+/// Frames can span several datagrams and a datagram can carry several frames.
+pub fn receive_frames(mut recv: impl FnMut(&mut [u8]) -> io::Result<usize>) -> Result<Vec<Frame>, FrameError> {
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    let mut datagrams = Datagrams::default();
+    loop {
+        let read = match recv(&mut buffer) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if datagrams.add(&buffer[..read])? {
+            return Ok(datagrams.frames);
+        }
+    }
+}
+
+/// Async version of [`receive_frames`]
+#[cfg(feature = "persist")]
+pub async fn receive_frames_async(socket: &tokio::net::UdpSocket) -> Result<Vec<Frame>, FrameError> {
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    let mut datagrams = Datagrams::default();
+    loop {
+        let read = socket.recv(&mut buffer).await?;
+        if datagrams.add(&buffer[..read])? {
+            return Ok(datagrams.frames);
+        }
+    }
+}
+
+/// Frames of a batch of datagrams, shared by the sync and async receive loops
+#[derive(Default)]
+struct Datagrams {
+    decoder: FrameDecoder,
+    frames: Vec<Frame>,
+}
+
+impl Datagrams {
+    /// Add a datagram, returns `true` once all frames are complete
+    ///
+    /// An empty datagram ends the batch.
+    fn add(&mut self, datagram: &[u8]) -> Result<bool, FrameError> {
+        if datagram.is_empty() {
+            return if self.decoder.is_empty() {
+                Ok(true)
+            } else {
+                Err(FrameError::Truncated)
+            };
+        }
+        self.frames.extend(self.decoder.decode(datagram)?);
+        if self.frames.len() > MAX_FRAMES {
+            return Err(FrameError::TooManyFrames);
+        }
+        Ok(!self.frames.is_empty() && self.decoder.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small deterministic generator, so that failures can be reproduced
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    fn random_frames(rng: &mut XorShift) -> Vec<Frame> {
+        (0..1 + rng.below(8))
+            .map(|_| {
+                // Mostly small payloads, some larger than a datagram
+                let len = match rng.below(10) {
+                    0 => MAX_DATAGRAM + rng.below(10_000),
+                    1 => 0,
+                    _ => rng.below(2000),
+                };
+                let message_type = match rng.below(2) {
+                    0 => MessageType::Text,
+                    _ => MessageType::Envelope,
+                };
+                Frame::new(message_type, rng.bytes(len))
+            })
+            .collect()
+    }
+
+    fn encode_all(frames: &[Frame]) -> Vec<u8> {
+        frames.iter().flat_map(|frame| frame.encode().unwrap()).collect()
+    }
+
+    /// Split data into pieces of random size
+    fn chunks(rng: &mut XorShift, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at((1 + rng.below(3000)).min(rest.len()));
+            chunks.push(chunk.to_vec());
+            rest = tail;
+        }
+        chunks
+    }
+
+    #[test]
+    fn round_trip_in_random_pieces() {
+        let mut rng = XorShift(0x5eed);
+        for _ in 0..200 {
+            let frames = random_frames(&mut rng);
+            let data = encode_all(&frames);
+            let mut decoder = FrameDecoder::default();
+            let mut decoded = Vec::new();
+            for chunk in chunks(&mut rng, &data) {
+                decoded.extend(decoder.decode(&chunk).unwrap());
+            }
+            assert!(decoder.is_empty());
+            assert_eq!(frames, decoded);
+        }
+    }
+
+    #[test]
+    fn read_and_receive() {
+        let mut rng = XorShift(42);
+        let frames = random_frames(&mut rng);
+        let data = encode_all(&frames);
+        let read = read_frames(data.as_slice()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(frames, read);
+
+        // Datagrams stop at the first point where all frames are complete
+        let mut datagrams = chunks(&mut rng, &data).into_iter();
+        let received = receive_frames(|buffer| {
+            let datagram = datagrams.next().unwrap_or_default();
+            buffer[..datagram.len()].copy_from_slice(&datagram);
+            Ok(datagram.len())
+        })
+        .unwrap();
+        assert_eq!(frames[..received.len()], received);
+
+        // Frames before a truncated one are still read, the error ends the stream
+        let truncated = read_frames(&data[..data.len() - 1]).collect::<Vec<_>>();
+        assert_eq!(frames.len(), truncated.len());
+        assert!(matches!(truncated.last(), Some(Err(FrameError::Truncated))));
+        assert_eq!(0, read_frames(&[][..]).count());
+    }
+
+    #[test]
+    fn frames_are_limited() {
+        let frame = Frame::new(MessageType::Text, "x").encode().unwrap();
+        let data = frame.repeat(MAX_FRAMES + 5);
+        let read = read_frames(data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(MAX_FRAMES + 1, read.len());
+        assert!(read[..MAX_FRAMES].iter().all(Result::is_ok));
+        assert!(matches!(read[MAX_FRAMES], Err(FrameError::TooManyFrames)));
+
+        // The whole batch arrives in one datagram, but a partial frame follows
+        let mut datagrams = [[data.as_slice(), &frame[..3]].concat()].into_iter();
+        let received = receive_frames(|buffer| {
+            let datagram = datagrams.next().unwrap_or_default();
+            buffer[..datagram.len()].copy_from_slice(&datagram);
+            Ok(datagram.len())
+        });
+        assert!(matches!(received, Err(FrameError::TooManyFrames)));
+    }
+
+    #[cfg(feature = "persist")]
+    #[tokio::test]
+    async fn receive_async() {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let frames = [
+            Frame::new(MessageType::Text, "a".repeat(MAX_DATAGRAM)),
+            Frame::new(MessageType::Text, "b"),
+        ];
+        let data = encode_all(&frames);
+        // The first frame spans two datagrams
+        for datagram in data.chunks(MAX_DATAGRAM) {
+            sender.send_to(datagram, socket.local_addr().unwrap()).await.unwrap();
+        }
+        assert_eq!(frames.to_vec(), receive_frames_async(&socket).await.unwrap());
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected() {
+        let mut rng = XorShift(7);
+        for _ in 0..500 {
+            let len = 1 + rng.below(100);
+            let frame = Frame::new(MessageType::Text, rng.bytes(len));
+            let mut data = frame.encode().unwrap();
+            let position = rng.below(data.len());
+            data[position] ^= 1 << rng.below(8);
+            // A flipped bit never produces a frame, at most the decoder waits for more data
+            let mut decoder = FrameDecoder::default();
+            assert!(!matches!(decoder.decode(&data), Ok(frames) if !frames.is_empty()));
+        }
+        // Random input never panics
+        for _ in 0..500 {
+            let len = rng.below(64);
+            let _ = FrameDecoder::default().decode(&rng.bytes(len));
+        }
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let data = Frame::new(MessageType::Text, "//todo").encode().unwrap();
+        let decode = |patch: fn(&mut Vec<u8>)| {
+            let mut data = data.clone();
+            patch(&mut data);
+            FrameDecoder::default().decode(&data)
+        };
+        assert!(matches!(decode(|d| d[4] = 2), Err(FrameError::UnsupportedVersion(2))));
+        assert!(matches!(decode(|d| d[5] = 9), Err(FrameError::UnknownMessageType(9))));
+        assert!(matches!(decode(|d| d[0] = 1), Err(FrameError::TooLarge(_))));
+        let large = Frame::new(MessageType::Text, vec![0; MAX_PAYLOAD + 1]);
+        assert!(matches!(large.encode(), Err(FrameError::TooLarge(_))));
+    }
+
+    #[test]
+    fn text_of_envelopes() {
+        let envelope = Envelope::new("TODO_QUERY", "//todo[@id='1']");
+        let frame = Frame::new(MessageType::Envelope, envelope.encode(Encoding::Json).unwrap());
+        assert_eq!("//todo[@id='1']", frame.text().unwrap());
+        assert_eq!("x", Frame::new(MessageType::Text, "x").text().unwrap());
+        let invalid = Frame::new(MessageType::Envelope, "x");
+        assert!(matches!(invalid.text(), Err(FrameError::InvalidEnvelope(_))));
+        for message_type in [MessageType::Text, MessageType::Envelope] {
+            let binary = Frame::new(message_type, b"\xffx".to_vec());
+            assert!(matches!(binary.text(), Err(FrameError::InvalidText(_))));
+        }
+    }
+}
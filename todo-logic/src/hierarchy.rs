@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{IdentifyableTodoItem, Pagination, TodoFilter, TodoStore, TodoStoreError};
+
+/// What happens to the subtasks of a todo item that is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeleteMode {
+    /// Refuse to remove items with subtasks
+    #[default]
+    Block,
+    /// Remove the subtasks, too
+    Cascade,
+}
+
+/// Query parameters of delete requests, e.g. `?cascade=true`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct DeleteParams {
+    pub cascade: Option<bool>,
+}
+
+impl DeleteParams {
+    pub fn mode(&self) -> DeleteMode {
+        match self.cascade {
+            Some(true) => DeleteMode::Cascade,
+            _ => DeleteMode::Block,
+        }
+    }
+}
+
+/// Todo item with its direct subtasks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subtasks {
+    pub parent: IdentifyableTodoItem,
+    /// Percentage of completed subtasks, including nested ones
+    ///
+    /// Items without subtasks are either 0 or 100 percent complete.
+    pub completion: u8,
+    pub children: Vec<IdentifyableTodoItem>,
+}
+
+impl TodoStore {
+    /// Direct subtasks of a todo item, ordered by id
+    pub fn children(&self, id: usize) -> Result<Vec<IdentifyableTodoItem>, TodoStoreError> {
+        let filter = TodoFilter {
+            parent_id: Some(id),
+            ..Default::default()
+        };
+        Ok(self.get_todos(Pagination::default(), &filter)?.items)
+    }
+
+    /// Subtasks of a todo item, their subtasks and so on
+    ///
+    /// Parents always come before their children.
+    pub fn descendants(&self, id: usize) -> Result<Vec<IdentifyableTodoItem>, TodoStoreError> {
+        let mut descendants = Vec::new();
+        let mut visited = HashSet::from([id]);
+        let mut next = 0;
+        let mut parent = id;
+        loop {
+            for child in self.children(parent)? {
+                // Cycles cannot be created through the store, but the data could be corrupt
+                if visited.insert(child.id) {
+                    descendants.push(child);
+                }
+            }
+            let Some(item) = descendants.get(next) else {
+                return Ok(descendants);
+            };
+            parent = item.id;
+            next += 1;
+        }
+    }
+
+    /// Todo item with its direct subtasks and its completion, `None` if the item does not exist
+    pub fn subtasks(&self, id: usize) -> Result<Option<Subtasks>, TodoStoreError> {
+        let Some(parent) = self.get_todo(id)? else {
+            return Ok(None);
+        };
+        let descendants = self.descendants(id)?;
+        let completion = if descendants.is_empty() {
+            if parent.item.completed {
+                100
+            } else {
+                0
+            }
+        } else {
+            let completed = descendants.iter().filter(|item| item.item.completed).count();
+            (completed * 100 / descendants.len()) as u8
+        };
+        Ok(Some(Subtasks {
+            children: descendants
+                .into_iter()
+                .filter(|item| item.item.parent_id == Some(id))
+                .collect(),
+            parent,
+            completion,
+        }))
+    }
+
+    /// Check that an item can become a subtask of `parent_id`
+    ///
+    /// The parent has to exist and must not be the item itself or one of its subtasks.
+    /// `id` is `None` for new items, which cannot have subtasks yet.
+    pub(crate) fn check_parent(&self, id: Option<usize>, parent_id: Option<usize>) -> Result<(), TodoStoreError> {
+        let Some(parent_id) = parent_id else {
+            return Ok(());
+        };
+        let mut visited = HashSet::new();
+        let mut ancestor = Some(parent_id);
+        while let Some(ancestor_id) = ancestor {
+            if Some(ancestor_id) == id {
+                return Err(TodoStoreError::InvalidItem(format!(
+                    "parent {parent_id} is a subtask of the item itself"
+                )));
+            }
+            if !visited.insert(ancestor_id) {
+                break;
+            }
+            ancestor = match self.get_todo(ancestor_id)? {
+                Some(item) => item.item.parent_id,
+                None if ancestor_id == parent_id => {
+                    return Err(TodoStoreError::InvalidItem(format!(
+                        "parent {parent_id} does not exist"
+                    )))
+                },
+                None => None,
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TodoItem, UpdateTodoItem};
+
+    /// Store with the tree 0 -> 1 -> 2 and 0 -> 3 and the separate item 4
+    fn store() -> TodoStore {
+        let items = [
+            (0, None, false),
+            (1, Some(0), false),
+            (2, Some(1), true),
+            (3, Some(0), true),
+            (4, None, false),
+        ]
+        .map(|(id, parent_id, completed)| {
+            let item = TodoItem {
+                title: format!("item {id}"),
+                completed,
+                parent_id,
+                ..Default::default()
+            };
+            (id, IdentifyableTodoItem::new(id, item))
+        });
+        TodoStore::from_hashmap(items.into())
+    }
+
+    fn ids(items: &[IdentifyableTodoItem]) -> Vec<usize> {
+        items.iter().map(|item| item.id).collect()
+    }
+
+    fn move_to(parent_id: Option<usize>) -> UpdateTodoItem {
+        UpdateTodoItem {
+            parent_id: Some(parent_id),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn subtasks_and_completion() {
+        let store = store();
+        assert_eq!(vec![1, 3], ids(&store.children(0).unwrap()));
+        assert_eq!(vec![1, 3, 2], ids(&store.descendants(0).unwrap()));
+
+        let subtasks = store.subtasks(0).unwrap().unwrap();
+        assert_eq!(vec![1, 3], ids(&subtasks.children));
+        assert_eq!(66, subtasks.completion);
+        assert_eq!(100, store.subtasks(1).unwrap().unwrap().completion);
+        assert_eq!(0, store.subtasks(4).unwrap().unwrap().completion);
+        assert!(store.subtasks(5).unwrap().is_none());
+    }
+
+    #[test]
+    fn parents_must_exist_without_cycles() {
+        let mut store = store();
+        let orphan = TodoItem {
+            parent_id: Some(42),
+            ..Default::default()
+        };
+        assert!(matches!(store.add_todo(orphan), Err(TodoStoreError::InvalidItem(_))));
+
+        for parent_id in [0, 2, 42] {
+            assert!(matches!(
+                store.update_todo(&0, move_to(Some(parent_id)), None),
+                Err(TodoStoreError::InvalidItem(_))
+            ));
+        }
+        store.update_todo(&1, move_to(Some(4)), None).unwrap();
+        assert_eq!(vec![1, 2], ids(&store.descendants(4).unwrap()));
+        store.update_todo(&1, move_to(None), None).unwrap();
+        assert!(store.children(4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn block_or_cascade_deletes() {
+        let mut store = store();
+        assert!(matches!(
+            store.remove_todo(0, None, DeleteMode::Block),
+            Err(TodoStoreError::HasSubtasks { id: 0, count: 2 })
+        ));
+        assert!(store.remove_todo(2, None, DeleteMode::Block).unwrap().is_some());
+
+        let removed = store.remove_todo(0, None, DeleteMode::Cascade).unwrap().unwrap();
+        assert_eq!(0, removed.id);
+        assert_eq!(vec![4], ids(&store.all_todos().unwrap()));
+        // One change for every removed item
+        assert_eq!(4, store.revision());
+    }
+}
@@ -0,0 +1,267 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{journal::JournalRecord, timestamp, IdentifyableTodoItem, TodoItem, TodoStore, TodoStoreError};
+
+/// Number of history entries kept per todo item, older entries are dropped
+pub const MAX_ENTRIES: usize = 100;
+
+/// Kind of change recorded in the history
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Created,
+    Updated,
+    /// Moved to the trash
+    Deleted,
+    /// Restored from the trash
+    Restored,
+}
+
+/// Field of a todo item that has been changed, with its JSON values
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    /// `null` for the fields of new items
+    pub before: Value,
+    pub after: Value,
+}
+
+/// Single change of a todo item
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Version of the item after the change
+    pub version: u64,
+    pub change: Change,
+    #[serde(with = "timestamp")]
+    pub at: DateTime<Utc>,
+    pub changes: Vec<FieldChange>,
+}
+
+impl HistoryEntry {
+    pub(crate) fn new(change: Change, before: Option<&IdentifyableTodoItem>, after: &IdentifyableTodoItem) -> Self {
+        HistoryEntry {
+            version: after.version,
+            change,
+            at: timestamp::now(),
+            changes: diff(before.map(|item| &item.item), &after.item),
+        }
+    }
+}
+
+/// Body of a revert request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RevertTo {
+    /// Version of the item to go back to
+    pub version: u64,
+}
+
+/// Fields that differ between two versions of an item
+fn diff(before: Option<&TodoItem>, after: &TodoItem) -> Vec<FieldChange> {
+    let before = before.map(|item| serde_json::to_value(item).unwrap_or_default());
+    let Ok(Value::Object(after)) = serde_json::to_value(after) else {
+        return Vec::new();
+    };
+    after
+        .into_iter()
+        .filter_map(|(field, after)| {
+            let before = before.as_ref().map_or(Value::Null, |before| before[&field].clone());
+            (before != after).then_some(FieldChange { field, before, after })
+        })
+        .collect()
+}
+
+/// Append an entry, dropping the oldest ones beyond [`MAX_ENTRIES`]
+///
+/// Entries that are already there are skipped, so that replaying the journal
+/// on top of a snapshot that contains them does no harm.
+pub(crate) fn append(entries: &mut Vec<HistoryEntry>, entry: HistoryEntry) {
+    if entries.contains(&entry) {
+        return;
+    }
+    entries.push(entry);
+    if entries.len() > MAX_ENTRIES {
+        entries.drain(..entries.len() - MAX_ENTRIES);
+    }
+}
+
+impl TodoStore {
+    /// Changes of a todo item, oldest first
+    ///
+    /// Items in the trash still have their history. Returns `None` for unknown items.
+    pub fn history(&self, id: usize) -> Result<Option<Vec<HistoryEntry>>, TodoStoreError> {
+        if self.get_todo(id)?.is_none() && !self.trash.contains_key(&id) {
+            return Ok(None);
+        }
+        Ok(Some(self.history.get(&id).cloned().unwrap_or_default()))
+    }
+
+    /// Set all fields of a todo item back to the given version
+    ///
+    /// The revert is a change of its own, so it gets a new version and can be reverted, too.
+    /// Only versions whose subsequent changes are still in the history can be restored.
+    pub fn revert_todo(
+        &mut self,
+        id: usize,
+        version: u64,
+        expected_version: Option<u64>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let Some(current) = self.get_todo(id)? else {
+            return Ok(None);
+        };
+        let entries = self.history.get(&id).map(Vec::as_slice).unwrap_or_default();
+        // Items from before the history was introduced can go back to their first known version
+        let known = version == current.version
+            || entries
+                .iter()
+                .any(|entry| entry.version == version + 1 && entry.change != Change::Created);
+        if version > current.version || !known {
+            return Err(TodoStoreError::InvalidItem(format!(
+                "version {version} of todo item {id} is not in its history"
+            )));
+        }
+
+        let mut state = serde_json::to_value(&current.item)?;
+        for entry in entries.iter().rev().take_while(|entry| entry.version > version) {
+            for change in &entry.changes {
+                state[&change.field] = change.before.clone();
+            }
+        }
+        let item = serde_json::from_value::<TodoItem>(state)?;
+        self.update_todo(&id, item.into(), expected_version)
+    }
+
+    /// Add an entry to the history of an item
+    ///
+    /// History entries are journaled, but they are not changes of their own.
+    pub(crate) fn record_history(&mut self, id: usize, entry: HistoryEntry) -> Result<(), TodoStoreError> {
+        if let Some(trial) = &mut self.trial {
+            trial
+                .history
+                .entry(id)
+                .or_insert_with(|| self.history.get(&id).cloned());
+        }
+        append(self.history.entry(id).or_default(), entry.clone());
+        let record = JournalRecord::History { id, entry };
+        self.repository.record(&record)?;
+        if let Some(journal) = &mut self.journal {
+            journal.append(&record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeleteMode, UpdateTodoItem};
+
+    fn store() -> TodoStore {
+        let mut store = TodoStore::default();
+        let item = TodoItem {
+            title: "Learn Rust".to_string(),
+            ..Default::default()
+        };
+        store.insert_todo(item).unwrap();
+        store
+    }
+
+    fn patch(title: &str, completed: bool) -> UpdateTodoItem {
+        UpdateTodoItem {
+            title: Some(title.to_string()),
+            completed: Some(completed),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn record_field_diffs() {
+        let mut store = store();
+        store.update_todo(&0, patch("Learn Rust", true), None).unwrap();
+        store.remove_todo(0, None, DeleteMode::Block).unwrap();
+        store.restore_todo(0).unwrap();
+
+        let history = store.history(0).unwrap().unwrap();
+        let changes = history
+            .iter()
+            .map(|entry| (entry.version, entry.change))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, Change::Created),
+                (2, Change::Updated),
+                (2, Change::Deleted),
+                (2, Change::Restored)
+            ],
+            changes
+        );
+        assert_eq!(Value::Null, history[0].changes[0].before);
+        // Only the completed flag has changed, the title is the same
+        assert_eq!(
+            vec![FieldChange {
+                field: "completed".to_string(),
+                before: Value::Bool(false),
+                after: Value::Bool(true),
+            }],
+            history[1].changes
+        );
+        assert!(history[2].changes.is_empty());
+        assert!(store.history(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn revert_to_version() {
+        let mut store = store();
+        store.update_todo(&0, patch("Learn Go", false), None).unwrap();
+        store.update_todo(&0, patch("Learn Zig", true), None).unwrap();
+
+        let reverted = store.revert_todo(0, 2, Some(3)).unwrap().unwrap();
+        assert_eq!(
+            ("Learn Go", false, 4),
+            (reverted.item.title.as_str(), reverted.item.completed, reverted.version)
+        );
+        let reverted = store.revert_todo(0, 1, None).unwrap().unwrap();
+        assert_eq!("Learn Rust", reverted.item.title);
+        assert_eq!(Change::Updated, store.history(0).unwrap().unwrap()[4].change);
+
+        assert!(matches!(
+            store.revert_todo(0, 2, Some(4)),
+            Err(TodoStoreError::VersionMismatch { .. })
+        ));
+        for version in [0, 9] {
+            assert!(matches!(
+                store.revert_todo(0, version, None),
+                Err(TodoStoreError::InvalidItem(_))
+            ));
+        }
+        assert!(store.revert_todo(7, 1, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn revert_items_without_history() {
+        let item = IdentifyableTodoItem::new(0, TodoItem::default());
+        let mut store = TodoStore::from_hashmap([(0, item)].into());
+        store.update_todo(&0, patch("Learn Rust", true), None).unwrap();
+        let reverted = store.revert_todo(0, 1, None).unwrap().unwrap();
+        assert_eq!(("", false), (reverted.item.title.as_str(), reverted.item.completed));
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut entries = Vec::new();
+        let item = IdentifyableTodoItem::new(0, TodoItem::default());
+        for version in 0..=MAX_ENTRIES as u64 {
+            let item = IdentifyableTodoItem {
+                version,
+                ..item.clone()
+            };
+            append(&mut entries, HistoryEntry::new(Change::Updated, None, &item));
+        }
+        assert_eq!(MAX_ENTRIES, entries.len());
+        assert_eq!(1, entries[0].version);
+        let last = entries[MAX_ENTRIES - 1].clone();
+        append(&mut entries, last);
+        assert_eq!(MAX_ENTRIES, entries.len());
+    }
+}
@@ -0,0 +1,233 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command_handler, data_processor, path_handler, redirect_handler, sql_handler, xpath_handler, IdentifyableTodoItem,
+    TodoStore, TodoStoreError,
+};
+
+/// Callbacks that run after a todo item has been changed
+///
+/// Hooks run in the order in which they have been registered with [`TodoStore::with_hook`],
+/// while the store is still borrowed mutably. They cannot undo the change, so they handle
+/// their errors themselves. All callbacks do nothing by default.
+pub trait TodoHook: Send + Sync {
+    /// Name of the hook, e.g. for logging
+    fn name(&self) -> &str;
+
+    /// Called after an item has been added
+    fn on_create(&self, _item: &IdentifyableTodoItem) {}
+
+    /// Called after an item has been changed
+    fn on_update(&self, _before: &IdentifyableTodoItem, _after: &IdentifyableTodoItem) {}
+
+    /// Called after an item has been moved to the trash
+    fn on_delete(&self, _item: &IdentifyableTodoItem) {}
+}
+
+/// Integrations with external systems that run whenever an item is created
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Integration {
+    Path,
+    Command,
+    Sql,
+    Redirect,
+    Xpath,
+    Data,
+    #[cfg(feature = "persist")]
+    Directory,
+}
+
+impl Integration {
+    /// All integrations, in the order in which they used to run
+    pub const ALL: &'static [Integration] = &[
+        Integration::Path,
+        Integration::Command,
+        Integration::Sql,
+        Integration::Redirect,
+        Integration::Xpath,
+        Integration::Data,
+        #[cfg(feature = "persist")]
+        Integration::Directory,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Integration::Path => "path",
+            Integration::Command => "command",
+            Integration::Sql => "sql",
+            Integration::Redirect => "redirect",
+            Integration::Xpath => "xpath",
+            Integration::Data => "data",
+            #[cfg(feature = "persist")]
+            Integration::Directory => "directory",
+        }
+    }
+
+    /// Run the integration, blocking until it is done
+    ///
+    /// Servers run integrations in the background, see [`crate::jobs::JobQueue`].
+    pub fn run(self) -> Result<String, String> {
+        match self {
+            //CWE-22
+            Integration::Path => path_handler::process_path_stream(),
+            //CWE-78
+            Integration::Command => command_handler::process_command_stream(),
+            //CWE-89
+            Integration::Sql => sql_handler::process_sql_stream(),
+            //CWE-601
+            Integration::Redirect => redirect_handler::process_redirect_stream(),
+            //CWE-643
+            Integration::Xpath => xpath_handler::process_todo_item_validation(),
+            //CWE-676
+            Integration::Data => data_processor::process_system_integration(),
+            //CWE-90
+            #[cfg(feature = "persist")]
+            Integration::Directory => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| e.to_string())?
+                .block_on(crate::directory_handler::process_directory_synchronization()),
+        }
+    }
+
+    /// Parse a comma-separated list of integrations, e.g. `path,sql`
+    ///
+    /// `none` or an empty list disables all integrations.
+    pub fn parse_list(s: &str) -> Result<Vec<Integration>, TodoStoreError> {
+        match s.trim() {
+            "" | "none" => Ok(Vec::new()),
+            s => s.split(',').map(|name| name.trim().parse()).collect(),
+        }
+    }
+}
+
+impl FromStr for Integration {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Integration::ALL
+            .iter()
+            .copied()
+            .find(|integration| integration.name() == s)
+            .ok_or_else(|| TodoStoreError::ConfigurationError(format!("unknown integration '{s}'")))
+    }
+}
+
+impl TodoHook for Integration {
+    fn name(&self) -> &str {
+        Integration::name(*self)
+    }
+
+    /// Runs the integration synchronously, failures are ignored
+    fn on_create(&self, _item: &IdentifyableTodoItem) {
+        let _ = self.run();
+    }
+}
+
+impl TodoStore {
+    /// Register a hook that runs after every change of an item
+    pub fn with_hook(mut self, hook: impl TodoHook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Register several hooks, they run in the given order
+    pub fn with_hooks<H: TodoHook + 'static>(self, hooks: impl IntoIterator<Item = H>) -> Self {
+        hooks.into_iter().fold(self, TodoStore::with_hook)
+    }
+
+    /// Names of the registered hooks, in the order in which they run
+    pub fn hook_names(&self) -> Vec<&str> {
+        self.hooks.iter().map(|hook| hook.name()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{DeleteMode, TodoItem, UpdateTodoItem};
+
+    /// Hook that records all events
+    struct Recorder {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TodoHook for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn on_create(&self, item: &IdentifyableTodoItem) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("{} created {}", self.name, item.id));
+        }
+
+        fn on_update(&self, before: &IdentifyableTodoItem, after: &IdentifyableTodoItem) {
+            let event = format!("{} updated {} from {}", self.name, after.item.title, before.item.title);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_delete(&self, item: &IdentifyableTodoItem) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("{} deleted {}", self.name, item.id));
+        }
+    }
+
+    #[test]
+    fn hooks_run_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |name| Recorder {
+            name,
+            events: events.clone(),
+        };
+        let mut store = TodoStore::default().with_hooks([recorder("first"), recorder("second")]);
+        assert_eq!(vec!["first", "second"], store.hook_names());
+
+        store.add_todo(TodoItem::default()).unwrap();
+        let patch = UpdateTodoItem {
+            title: Some("renamed".to_string()),
+            ..Default::default()
+        };
+        store.update_todo(&0, patch, None).unwrap();
+        store.remove_todo(0, None, DeleteMode::Block).unwrap();
+        // Failed changes do not run any hooks
+        assert!(store
+            .update_todo(&0, UpdateTodoItem::default(), None)
+            .unwrap()
+            .is_none());
+
+        let expected = [
+            "first created 0",
+            "second created 0",
+            "first updated renamed from ",
+            "second updated renamed from ",
+            "first deleted 0",
+            "second deleted 0",
+        ];
+        assert_eq!(expected.as_slice(), events.lock().unwrap().as_slice());
+    }
+
+    #[test]
+    fn parse_integrations() {
+        assert_eq!(
+            vec![Integration::Sql, Integration::Path],
+            Integration::parse_list("sql, path").unwrap()
+        );
+        assert!(Integration::parse_list("none").unwrap().is_empty());
+        assert!(Integration::parse_list("").unwrap().is_empty());
+        assert!(matches!(
+            Integration::parse_list("path,mail"),
+            Err(TodoStoreError::ConfigurationError(_))
+        ));
+    }
+}
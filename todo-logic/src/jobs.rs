@@ -0,0 +1,338 @@
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{hook::Integration, timestamp, IdentifyableTodoItem, TodoHook, TodoStore};
+
+/// Number of dead letters kept for the status, older ones are dropped
+pub const MAX_DEAD_LETTERS: usize = 100;
+
+/// Settings of the background job queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobSettings {
+    /// Number of worker threads
+    pub workers: usize,
+    /// Number of jobs that can wait in the queue, further jobs are dead-lettered
+    pub capacity: usize,
+    /// Number of times a job is run before it is dead-lettered
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled with every further attempt
+    pub backoff: Duration,
+}
+
+impl Default for JobSettings {
+    fn default() -> Self {
+        JobSettings {
+            workers: 2,
+            capacity: 1000,
+            max_attempts: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Integration that runs for a todo item
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Job {
+    pub id: u64,
+    pub integration: Integration,
+    pub item_id: usize,
+}
+
+/// Job that has failed for good
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetter {
+    #[serde(flatten)]
+    pub job: Job,
+    /// Zero for jobs that have been rejected because the queue was full
+    pub attempts: u32,
+    pub error: String,
+    #[serde(with = "timestamp")]
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Current state of the job queue
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct JobStatus {
+    pub queued: usize,
+    pub running: usize,
+    pub succeeded: u64,
+    /// Failed attempts that have been retried
+    pub retried: u64,
+    /// Latest dead letters, oldest first
+    pub dead_letters: VecDeque<DeadLetter>,
+}
+
+/// Run an integration, returns an error message if it fails
+type Runner = dyn Fn(Integration) -> Result<(), String> + Send + Sync;
+
+struct Shared {
+    settings: JobSettings,
+    status: Mutex<JobStatus>,
+    run: Box<Runner>,
+}
+
+impl Shared {
+    fn status(&self) -> std::sync::MutexGuard<'_, JobStatus> {
+        // Integrations never run while the lock is held, so the counters stay consistent
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn dead_letter(&self, job: Job, attempts: u32, error: String) {
+        let mut status = self.status();
+        status.dead_letters.push_back(DeadLetter {
+            job,
+            attempts,
+            error,
+            failed_at: timestamp::now(),
+        });
+        if status.dead_letters.len() > MAX_DEAD_LETTERS {
+            status.dead_letters.pop_front();
+        }
+    }
+
+    /// Run a job until it succeeds or has no attempts left
+    fn process(&self, job: Job) {
+        let mut delay = self.settings.backoff;
+        for attempt in 1..=self.settings.max_attempts {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| (self.run)(job.integration)))
+                .unwrap_or_else(|_| Err("integration panicked".to_string()));
+            match result {
+                Ok(()) => {
+                    self.status().succeeded += 1;
+                    return;
+                },
+                Err(error) if attempt == self.settings.max_attempts => {
+                    self.dead_letter(job, attempt, error);
+                },
+                Err(_) => {
+                    self.status().retried += 1;
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                },
+            }
+        }
+    }
+}
+
+/// Bounded queue that runs integrations in background threads
+///
+/// Registered with [`TodoStore::with_jobs`], the queue is a [`TodoHook`] that only
+/// enqueues a job per integration when an item is created, so that slow integrations
+/// do not hold up requests. Failed jobs are retried with exponential backoff and
+/// dead-lettered when they run out of attempts. Clones share the same queue, the
+/// workers stop when the last clone is dropped.
+#[derive(Clone)]
+pub struct JobQueue {
+    sender: SyncSender<Job>,
+    integrations: Arc<[Integration]>,
+    next_id: Arc<AtomicU64>,
+    shared: Arc<Shared>,
+}
+
+impl JobQueue {
+    /// Start the workers for the given integrations
+    pub fn start(settings: JobSettings, integrations: &[Integration]) -> Self {
+        JobQueue::with_runner(settings, integrations, |integration| integration.run().map(drop))
+    }
+
+    fn with_runner<F>(settings: JobSettings, integrations: &[Integration], run: F) -> Self
+    where
+        F: Fn(Integration) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(settings.capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared {
+            settings,
+            status: Default::default(),
+            run: Box::new(run),
+        });
+        for _ in 0..settings.workers.max(1) {
+            let receiver = receiver.clone();
+            let shared = shared.clone();
+            thread::spawn(move || work(&receiver, &shared));
+        }
+        JobQueue {
+            sender,
+            integrations: integrations.into(),
+            next_id: Default::default(),
+            shared,
+        }
+    }
+
+    /// Add a job, it is dead-lettered right away if the queue is full
+    pub fn enqueue(&self, integration: Integration, item_id: usize) -> Job {
+        let job = Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            integration,
+            item_id,
+        };
+        self.shared.status().queued += 1;
+        if let Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) = self.sender.try_send(job) {
+            self.shared.status().queued -= 1;
+            self.shared.dead_letter(job, 0, "job queue is full".to_string());
+        }
+        job
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.shared.status().clone()
+    }
+}
+
+/// Take jobs from the queue until all senders are gone
+fn work(receiver: &Mutex<Receiver<Job>>, shared: &Shared) {
+    loop {
+        let job = match receiver.lock().map(|receiver| receiver.recv()) {
+            Ok(Ok(job)) => job,
+            _ => return,
+        };
+        {
+            let mut status = shared.status();
+            status.queued -= 1;
+            status.running += 1;
+        }
+        shared.process(job);
+        shared.status().running -= 1;
+    }
+}
+
+impl TodoHook for JobQueue {
+    fn name(&self) -> &str {
+        "jobs"
+    }
+
+    fn on_create(&self, item: &IdentifyableTodoItem) {
+        for integration in self.integrations.iter() {
+            self.enqueue(*integration, item.id);
+        }
+    }
+}
+
+impl TodoStore {
+    /// Run integrations in the background, see [`JobQueue`]
+    pub fn with_jobs(mut self, jobs: JobQueue) -> Self {
+        self.jobs = Some(jobs.clone());
+        self.with_hook(jobs)
+    }
+
+    /// State of the job queue, `None` if the store has none
+    pub fn job_status(&self) -> Option<JobStatus> {
+        self.jobs.as_ref().map(JobQueue::status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::AtomicUsize,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::TodoItem;
+
+    fn settings(workers: usize, capacity: usize) -> JobSettings {
+        JobSettings {
+            workers,
+            capacity,
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    /// Wait until the queue has processed the given number of jobs
+    fn wait_for(queue: &JobQueue, jobs: usize) -> JobStatus {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let status = queue.status();
+            if status.succeeded as usize + status.dead_letters.len() >= jobs || Instant::now() > deadline {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn retry_and_dead_letter() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let integrations = [Integration::Path, Integration::Sql];
+        let queue = JobQueue::with_runner(settings(1, 10), &integrations, move |integration| {
+            // Path succeeds on the second attempt, sql always fails
+            match (integration, counter.fetch_add(1, Ordering::SeqCst)) {
+                (Integration::Path, 1) => Ok(()),
+                _ => Err(format!("{} is down", integration.name())),
+            }
+        });
+        let mut store = TodoStore::default().with_jobs(queue.clone());
+        assert_eq!(vec!["jobs"], store.hook_names());
+        store.add_todo(TodoItem::default()).unwrap();
+
+        let status = wait_for(&queue, 2);
+        assert_eq!(
+            (1, 3, 5),
+            (status.succeeded, status.retried, calls.load(Ordering::SeqCst))
+        );
+        assert_eq!((0, 0), (status.queued, status.running));
+        let dead_letter = &status.dead_letters[0];
+        assert_eq!(
+            (Integration::Sql, 0, 3),
+            (
+                dead_letter.job.integration,
+                dead_letter.job.item_id,
+                dead_letter.attempts
+            )
+        );
+        assert_eq!("sql is down", dead_letter.error);
+        assert!(TodoStore::default().job_status().is_none());
+    }
+
+    #[test]
+    fn full_queue_rejects_jobs() {
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let queue = JobQueue::with_runner(settings(1, 1), &[], move |_| {
+            blocked.lock().unwrap().recv().map_err(|e| e.to_string())
+        });
+        let jobs = (0..4)
+            .map(|id| queue.enqueue(Integration::Data, id))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 3], jobs.iter().map(|job| job.id).collect::<Vec<_>>());
+
+        // The worker takes at most one job, one more fits into the queue
+        let status = queue.status();
+        assert!(status.dead_letters.len() >= 2);
+        assert!(status.dead_letters.iter().all(|dead_letter| dead_letter.attempts == 0));
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        let status = wait_for(&queue, 4);
+        assert_eq!(4, status.succeeded as usize + status.dead_letters.len());
+    }
+
+    #[test]
+    fn panics_are_failures() {
+        let settings = JobSettings {
+            max_attempts: 1,
+            ..settings(1, 10)
+        };
+        let queue = JobQueue::with_runner(settings, &[], |_| panic!("boom"));
+        queue.enqueue(Integration::Command, 7);
+        queue.enqueue(Integration::Command, 8);
+        let status = wait_for(&queue, 2);
+        assert_eq!(2, status.dead_letters.len());
+        assert_eq!("integration panicked", status.dead_letters[1].error);
+    }
+}
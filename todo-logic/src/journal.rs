@@ -0,0 +1,342 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{history, snapshot::Snapshot, HistoryEntry, IdentifyableTodoItem, TodoList, TodoStoreError, TrashedItem};
+
+/// A single change recorded in the journal
+///
+/// Records contain the full state after the change, so replaying a record
+/// more than once does no harm.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalRecord {
+    Put {
+        item: IdentifyableTodoItem,
+    },
+    /// Written before removed items were moved to the trash
+    Remove {
+        id: usize,
+    },
+    PutList {
+        list: TodoList,
+    },
+    RemoveList {
+        id: usize,
+    },
+    /// Move an item to the trash
+    Trash {
+        item: TrashedItem,
+    },
+    /// Move an item back from the trash
+    Restore {
+        item: IdentifyableTodoItem,
+    },
+    /// Remove an item from the trash
+    Purge {
+        id: usize,
+    },
+    /// Add an entry to the history of an item
+    History {
+        id: usize,
+        entry: HistoryEntry,
+    },
+}
+
+impl JournalRecord {
+    /// Apply the change to the todo items, lists, trash and history of a snapshot
+    pub fn apply(self, snapshot: &mut Snapshot) {
+        match self {
+            JournalRecord::Put { item } => {
+                snapshot.items.insert(item.id, item);
+            },
+            JournalRecord::Remove { id } => {
+                snapshot.items.remove(&id);
+            },
+            JournalRecord::PutList { list } => {
+                snapshot.next_list_id = snapshot.next_list_id.max(list.id + 1);
+                snapshot.lists.insert(list.id, list);
+            },
+            JournalRecord::RemoveList { id } => {
+                snapshot.lists.remove(&id);
+            },
+            JournalRecord::Trash { item } => {
+                snapshot.items.remove(&item.item.id);
+                snapshot.trash.insert(item.item.id, item);
+            },
+            JournalRecord::Restore { item } => {
+                snapshot.trash.remove(&item.id);
+                snapshot.items.insert(item.id, item);
+            },
+            JournalRecord::Purge { id } => {
+                snapshot.trash.remove(&id);
+                snapshot.history.remove(&id);
+            },
+            JournalRecord::History { id, entry } => {
+                history::append(snapshot.history.entry(id).or_default(), entry);
+            },
+        }
+    }
+}
+
+/// Append-only log of all changes made to a `TodoStore`
+///
+/// The journal holds one JSON record per line. Together with the last snapshot, it
+/// contains the complete state of the store. Compaction writes a new snapshot and
+/// truncates the journal.
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal for appending, creating it if necessary
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TodoStoreError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { file })
+    }
+
+    /// Append a record and flush it to disk
+    pub fn append(&mut self, record: &JournalRecord) -> Result<(), TodoStoreError> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Length of the journal in bytes
+    #[cfg(feature = "persist")]
+    pub(crate) fn len(&self) -> Result<u64, TodoStoreError> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Remove all records, called after they have been compacted into a snapshot
+    pub fn truncate(&self) -> Result<(), TodoStoreError> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Cut the journal back to its valid part, see [`parse`]
+    ///
+    /// Otherwise the next record would be appended to a torn one.
+    pub fn discard_after(&self, valid_len: u64) -> Result<(), TodoStoreError> {
+        if self.file.metadata()?.len() > valid_len {
+            self.file.set_len(valid_len)?;
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the records of a journal, returns them with the length of the valid part
+///
+/// If the process crashed while appending, the last line may be incomplete. Records
+/// are only complete with their newline, so such a torn record is skipped. Corrupt
+/// records before it are an error.
+pub fn parse(content: &str) -> Result<(Vec<JournalRecord>, usize), TodoStoreError> {
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+    for line in content.split_inclusive('\n') {
+        if !line.ends_with('\n') {
+            break;
+        }
+        if !line.trim().is_empty() {
+            records.push(serde_json::from_str(line)?);
+        }
+        valid_len += line.len();
+    }
+    Ok((records, valid_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{history::Change, TodoItem};
+
+    fn put(id: usize, title: &str) -> JournalRecord {
+        JournalRecord::Put {
+            item: IdentifyableTodoItem::new(
+                id,
+                TodoItem {
+                    title: title.to_string(),
+                    notes: String::new(),
+                    assigned_to: "Rainer".to_string(),
+                    completed: false,
+                    ..Default::default()
+                },
+            ),
+        }
+    }
+
+    fn trash(record: JournalRecord) -> JournalRecord {
+        let JournalRecord::Put { item } = record else {
+            unreachable!()
+        };
+        JournalRecord::Trash {
+            item: TrashedItem {
+                deleted_at: item.updated_at,
+                item,
+            },
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let path = std::env::temp_dir().join(format!("todo_journal_{}.log", std::process::id()));
+        let mut journal = Journal::open(&path).unwrap();
+        let list = TodoList {
+            id: 0,
+            name: "Work".to_string(),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        let entry = HistoryEntry::new(
+            Change::Created,
+            None,
+            &IdentifyableTodoItem::new(0, TodoItem::default()),
+        );
+        let history = JournalRecord::History { id: 0, entry };
+        let records = [
+            put(0, "a"),
+            history.clone(),
+            history,
+            put(1, "b"),
+            put(0, "c"),
+            JournalRecord::Remove { id: 1 },
+            JournalRecord::PutList { list: list.clone() },
+            JournalRecord::PutList { list },
+            put(2, "d"),
+            trash(put(2, "d")),
+            trash(put(3, "e")),
+            JournalRecord::Restore {
+                item: IdentifyableTodoItem::new(3, TodoItem::default()),
+            },
+        ];
+        for record in records {
+            journal.append(&record).unwrap();
+        }
+
+        let mut snapshot = Snapshot::default();
+        for record in parse(&std::fs::read_to_string(&path).unwrap()).unwrap().0 {
+            record.apply(&mut snapshot);
+        }
+        assert_eq!(2, snapshot.items.len());
+        assert!(snapshot.items.contains_key(&3));
+        assert_eq!("c", snapshot.items[&0].item.title);
+        assert_eq!(1, snapshot.lists.len());
+        assert_eq!("d", snapshot.trash[&2].item.item.title);
+        // History entries are only added once
+        assert_eq!(1, snapshot.history[&0].len());
+
+        journal.truncate().unwrap();
+        journal.append(&JournalRecord::Remove { id: 0 }).unwrap();
+        assert_eq!(1, parse(&std::fs::read_to_string(&path).unwrap()).unwrap().0.len());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "persist")]
+    #[tokio::test]
+    async fn replay_and_compact() {
+        use crate::{TodoStore, UpdateTodoItem};
+
+        let dir = std::env::temp_dir();
+        let snapshot_path = dir.join(format!("todo_journal_snapshot_{}.json", std::process::id()));
+        let journal_path = dir.join(format!("todo_journal_store_{}.log", std::process::id()));
+
+        let mut store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        store.persist().await.unwrap();
+        let patch = UpdateTodoItem {
+            title: None,
+            notes: None,
+            assigned_to: None,
+            completed: Some(true),
+            ..Default::default()
+        };
+        assert!(store.update_todo(&0, patch, None).unwrap().is_none());
+        assert_eq!(0, store.unsaved_writes());
+        drop(store);
+
+        // Simulate changes that have not been compacted yet
+        let mut journal = Journal::open(&journal_path).unwrap();
+        journal.append(&put(0, "a")).unwrap();
+        journal.append(&put(1, "b")).unwrap();
+        let item = IdentifyableTodoItem::new(1, TodoItem::default());
+        let entry = HistoryEntry::new(Change::Created, None, &item);
+        journal.append(&JournalRecord::History { id: 1, entry }).unwrap();
+        let store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        // History entries belong to the writes before them
+        assert_eq!(2, store.unsaved_writes());
+        assert_eq!("b", store.get_todo(1).unwrap().unwrap().item.title);
+
+        store.persist().await.unwrap();
+        assert!(std::fs::read_to_string(&journal_path).unwrap().is_empty());
+        let store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        assert_eq!(0, store.unsaved_writes());
+        assert_eq!("a", store.get_todo(0).unwrap().unwrap().item.title);
+
+        std::fs::remove_file(snapshot_path).unwrap();
+        std::fs::remove_file(journal_path).unwrap();
+    }
+
+    #[cfg(feature = "persist")]
+    #[tokio::test]
+    async fn write_during_compaction() {
+        use crate::TodoStore;
+
+        let dir = std::env::temp_dir();
+        let snapshot_path = dir.join(format!("todo_compaction_snapshot_{}.json", std::process::id()));
+        let journal_path = dir.join(format!("todo_compaction_store_{}.log", std::process::id()));
+        let mut store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        store.add_todo(TodoItem::default()).unwrap();
+        let compaction = store.start_compaction().unwrap();
+        compaction.write().await.unwrap();
+        // Another request changes the store while the snapshot is written
+        store.add_todo(TodoItem::default()).unwrap();
+        store.finish_compaction(compaction).unwrap();
+        assert_eq!(1, store.unsaved_writes());
+        drop(store);
+
+        let store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        assert_eq!(2, store.all_todos().unwrap().len());
+        std::fs::remove_file(snapshot_path).unwrap();
+        std::fs::remove_file(journal_path).unwrap();
+    }
+
+    #[test]
+    fn torn_last_record() {
+        let content = "{\"op\":\"remove\",\"id\":1}\n\n{\"op\":\"remove\",\"i";
+        let (records, valid_len) = parse(content).unwrap();
+        assert_eq!((1, 24), (records.len(), valid_len));
+        assert!(parse("{\"op\":\"rem\n{\"op\":\"remove\",\"id\":1}\n").is_err());
+    }
+
+    #[cfg(feature = "persist")]
+    #[tokio::test]
+    async fn write_after_torn_record() {
+        use crate::TodoStore;
+
+        let dir = std::env::temp_dir();
+        let snapshot_path = dir.join(format!("todo_torn_snapshot_{}.json", std::process::id()));
+        let journal_path = dir.join(format!("todo_torn_store_{}.log", std::process::id()));
+        let mut journal = Journal::open(&journal_path).unwrap();
+        journal.append(&put(0, "a")).unwrap();
+        journal.file.write_all(b"{\"op\":\"put\",\"it").unwrap();
+        drop(journal);
+
+        let mut store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        store.record_write(put(1, "b")).unwrap();
+        drop(store);
+        // The new record does not end up on the line of the torn one
+        let store = TodoStore::load_journaled(&snapshot_path, &journal_path).await.unwrap();
+        assert!(store.get_todo(0).unwrap().is_some());
+        assert!(store.get_todo(1).unwrap().is_some());
+
+        std::fs::remove_file(journal_path).unwrap();
+        let _ = std::fs::remove_file(snapshot_path);
+    }
+}
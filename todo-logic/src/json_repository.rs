@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    journal::JournalRecord,
+    repository::{InMemoryTodoRepository, TodoRepository},
+    snapshot::{self, Snapshot},
+    IdentifyableTodoItem, Page, Pagination, TodoFilter, TodoItem, TodoStoreError, UpdateTodoItem,
+};
+
+/// Repository keeping todo items in a JSON file
+///
+/// Items are held in memory and the whole file is (atomically) rewritten after every change.
+/// The file format is the same that `TodoStore::persist` produces, so the file contains the
+/// lists, the trash and the history, too.
+pub struct JsonFileTodoRepository {
+    path: PathBuf,
+    inner: InMemoryTodoRepository,
+    /// Everything but the items, see [`TodoRepository::record`]
+    state: Snapshot,
+    /// Changes after a savepoint are not written, see [`TodoRepository::savepoint`]
+    savepoint: bool,
+}
+
+impl JsonFileTodoRepository {
+    /// Open the repository, starting empty if the file does not exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TodoStoreError> {
+        let path = path.as_ref().to_path_buf();
+        let mut state = match fs::read_to_string(&path) {
+            Ok(json) => snapshot::from_json(&json)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let inner = InMemoryTodoRepository::from_hashmap(std::mem::take(&mut state.items));
+        // Trashed items keep their ids, so that they can be restored
+        if let Some(id) = state.trash.keys().max() {
+            inner.reserve_ids(id + 1);
+        }
+        Ok(JsonFileTodoRepository {
+            path,
+            inner,
+            state,
+            savepoint: false,
+        })
+    }
+
+    fn write(&self) -> Result<(), TodoStoreError> {
+        if self.savepoint {
+            return Ok(());
+        }
+        let items = self
+            .inner
+            .get_todos(Pagination::default(), &TodoFilter::default())?
+            .items;
+        let snapshot = Snapshot {
+            items: items.into_iter().map(|item| (item.id, item)).collect(),
+            ..self.state.clone()
+        };
+        snapshot::write(&self.path, &snapshot)
+    }
+}
+
+impl TodoRepository for JsonFileTodoRepository {
+    fn get_todos(
+        &self,
+        pagination: Pagination,
+        filter: &TodoFilter,
+    ) -> Result<Page<IdentifyableTodoItem>, TodoStoreError> {
+        self.inner.get_todos(pagination, filter)
+    }
+
+    fn get_todo(&self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        self.inner.get_todo(id)
+    }
+
+    fn add_todo(&mut self, todo: TodoItem, now: DateTime<Utc>) -> Result<IdentifyableTodoItem, TodoStoreError> {
+        let item = self.inner.add_todo(todo, now)?;
+        self.write()?;
+        Ok(item)
+    }
+
+    fn remove_todo(&mut self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let item = self.inner.remove_todo(id)?;
+        if item.is_some() {
+            self.write()?;
+        }
+        Ok(item)
+    }
+
+    fn restore_todo(&mut self, item: IdentifyableTodoItem) -> Result<(), TodoStoreError> {
+        self.inner.restore_todo(item)?;
+        self.write()
+    }
+
+    fn update_todo(
+        &mut self,
+        id: &usize,
+        todo: UpdateTodoItem,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let item = self.inner.update_todo(id, todo, now)?;
+        if item.is_some() {
+            self.write()?;
+        }
+        Ok(item)
+    }
+
+    fn record(&mut self, record: &JournalRecord) -> Result<(), TodoStoreError> {
+        match record {
+            // Items have already been written
+            JournalRecord::Put { .. } | JournalRecord::Remove { .. } => return Ok(()),
+            JournalRecord::Restore { item } => {
+                self.state.trash.remove(&item.id);
+            },
+            _ => record.clone().apply(&mut self.state),
+        }
+        self.write()
+    }
+
+    fn load_state(&self) -> Result<Snapshot, TodoStoreError> {
+        Ok(self.state.clone())
+    }
+
+    fn savepoint(&mut self) -> Result<(), TodoStoreError> {
+        self.savepoint = true;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), TodoStoreError> {
+        // The file still has the state of the savepoint
+        *self = JsonFileTodoRepository::open(&self.path)?;
+        Ok(())
+    }
+}
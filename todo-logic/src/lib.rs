@@ -1,6 +1,8 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
@@ -19,53 +21,293 @@ pub mod xpath_handler;
 pub mod xpath_engine;
 pub mod data_processor;
 pub mod stream_processor;
+#[cfg(feature = "persist")]
 pub mod directory_handler;
+#[cfg(feature = "persist")]
 pub mod directory_engine;
+pub mod envelope;
+pub mod frame;
+pub mod repository;
+pub mod json_repository;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_repository;
+pub mod snapshot;
+pub mod conditional;
+pub mod config;
+pub mod etag;
+pub mod autosave;
+pub mod journal;
+pub mod sort;
+pub mod page;
+pub mod filter;
+pub mod query;
+pub mod search;
+pub mod timestamp;
+pub mod priority;
+pub mod tags;
+pub mod hierarchy;
+pub mod list;
+pub mod recurrence;
+pub mod trash;
+pub mod history;
+pub mod batch;
+pub mod patch;
+pub mod hook;
+pub mod jobs;
+#[cfg(feature = "persist")]
+pub mod async_store;
+
+#[cfg(feature = "persist")]
+pub use async_store::{AsyncTodoStore, SharedTodoStore};
+pub use batch::{Batch, BatchResult};
+pub use conditional::Validators;
+pub use config::TodoStoreConfig;
+pub use filter::TodoFilter;
+pub use hierarchy::{DeleteMode, DeleteParams, Subtasks};
+pub use history::{HistoryEntry, RevertTo};
+pub use hook::{Integration, TodoHook};
+pub use jobs::{JobQueue, JobSettings, JobStatus};
+use history::Change;
+use journal::{Journal, JournalRecord};
+pub use list::{NewTodoList, TodoList};
+pub use page::Page;
+pub use patch::TodoPatch;
+pub use priority::Priority;
+pub use query::TodoQuery;
+pub use recurrence::Recurrence;
+use repository::{Backend, InMemoryTodoRepository, TodoRepository};
+use search::SearchIndex;
+pub use search::{SearchQuery, SearchResult};
+use snapshot::Snapshot;
+pub use sort::{Sort, SortField};
+pub use trash::{Retention, TrashedItem};
 
 /// Represents a single todo item
-#[derive(Serialize, Deserialize, Debug, Clone)]
+///
+/// Due date, priority and tags are optional in JSON, so that clients and
+/// snapshots that predate them keep working.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct TodoItem {
     pub title: String,
     pub notes: String,
     pub assigned_to: String,
     pub completed: bool,
+    /// Day the item is due, e.g. `2024-05-01`
+    #[serde(default)]
+    pub due_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Id of the item that this item is a subtask of
+    #[serde(default)]
+    pub parent_id: Option<usize>,
+    /// Id of the [`TodoList`] that this item belongs to
+    #[serde(default)]
+    pub list_id: Option<usize>,
+    /// Rule for repeating the item, e.g. `FREQ=WEEKLY;BYDAY=SA`
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+}
+
+impl TodoItem {
+    /// Check the fields that JSON deserialization cannot check
+    pub fn validate(&self) -> Result<(), TodoStoreError> {
+        tags::validate(&self.tags)
+    }
 }
 
 /// DTO for patching a todo item
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct UpdateTodoItem {
     pub title: Option<String>,
     pub notes: Option<String>,
     pub assigned_to: Option<String>,
     pub completed: Option<bool>,
+    /// `Some(None)` removes the due date, it is sent as `"due_date": null`
+    #[serde(default, deserialize_with = "deserialize_some", skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<Option<NaiveDate>>,
+    pub priority: Option<Priority>,
+    /// Replaces all tags of the item
+    pub tags: Option<BTreeSet<String>>,
+    /// `Some(None)` turns a subtask into a top-level item
+    #[serde(default, deserialize_with = "deserialize_some", skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Option<usize>>,
+    /// Moves the item to another list, `Some(None)` removes it from its list
+    #[serde(default, deserialize_with = "deserialize_some", skip_serializing_if = "Option::is_none")]
+    pub list_id: Option<Option<usize>>,
+    /// `Some(None)` stops the item from repeating
+    #[serde(default, deserialize_with = "deserialize_some", skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Option<Recurrence>>,
+}
+
+/// Deserialize a present field into `Some`, even if it is `null`
+///
+/// Together with `#[serde(default)]`, this distinguishes missing fields from `null`.
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl UpdateTodoItem {
+    /// Check the fields that JSON deserialization cannot check
+    pub fn validate(&self) -> Result<(), TodoStoreError> {
+        self.tags.as_ref().map_or(Ok(()), tags::validate)
+    }
+
+    /// Apply all fields that are set to the given todo item
+    pub fn apply(self, item: &mut TodoItem) {
+        if let Some(title) = self.title {
+            item.title = title;
+        }
+        if let Some(notes) = self.notes {
+            item.notes = notes;
+        }
+        if let Some(assigned_to) = self.assigned_to {
+            item.assigned_to = assigned_to;
+        }
+        if let Some(completed) = self.completed {
+            item.completed = completed;
+        }
+        if let Some(due_date) = self.due_date {
+            item.due_date = due_date;
+        }
+        if let Some(priority) = self.priority {
+            item.priority = priority;
+        }
+        if let Some(tags) = self.tags {
+            item.tags = tags;
+        }
+        if let Some(parent_id) = self.parent_id {
+            item.parent_id = parent_id;
+        }
+        if let Some(list_id) = self.list_id {
+            item.list_id = list_id;
+        }
+        if let Some(recurrence) = self.recurrence {
+            item.recurrence = recurrence;
+        }
+    }
+}
+
+/// Patch that sets all fields to the values of the given item
+impl From<TodoItem> for UpdateTodoItem {
+    fn from(item: TodoItem) -> Self {
+        UpdateTodoItem {
+            title: Some(item.title),
+            notes: Some(item.notes),
+            assigned_to: Some(item.assigned_to),
+            completed: Some(item.completed),
+            due_date: Some(item.due_date),
+            priority: Some(item.priority),
+            tags: Some(item.tags),
+            parent_id: Some(item.parent_id),
+            list_id: Some(item.list_id),
+            recurrence: Some(item.recurrence),
+        }
+    }
 }
 
 /// Represents a todo item with an id
+///
+/// The timestamps and the version are maintained by [`TodoStore`], clients cannot
+/// set them. Items persisted before timestamps were introduced get the Unix epoch.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IdentifyableTodoItem {
     pub id: usize,
 
     #[serde(flatten)]
     pub item: TodoItem,
+
+    #[serde(default, with = "timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default, with = "timestamp")]
+    pub updated_at: DateTime<Utc>,
+    /// Time when the item has been completed, `None` while it is open
+    #[serde(default, with = "timestamp::option")]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Incremented with every change, used for optimistic concurrency control
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl IdentifyableTodoItem {
     pub fn new(id: usize, item: TodoItem) -> IdentifyableTodoItem {
-        IdentifyableTodoItem { id, item }
+        IdentifyableTodoItem::new_at(id, item, timestamp::now())
+    }
+
+    /// Create an item at the given time
+    pub fn new_at(id: usize, item: TodoItem, now: DateTime<Utc>) -> IdentifyableTodoItem {
+        IdentifyableTodoItem {
+            id,
+            completed_at: item.completed.then_some(now),
+            item,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+        }
+    }
+
+    /// Patch the item at the given time
+    ///
+    /// `completed_at` is set when the item gets completed and cleared when it is reopened.
+    pub fn update(&mut self, patch: UpdateTodoItem, now: DateTime<Utc>) {
+        patch.apply(&mut self.item);
+        self.updated_at = now;
+        self.version += 1;
+        self.completed_at = match (self.item.completed, self.completed_at) {
+            (true, None) => Some(now),
+            (true, completed_at) => completed_at,
+            (false, _) => None,
+        };
+    }
+
+    /// Value of the `ETag` header for this item
+    pub fn etag(&self) -> String {
+        etag::format(self.version)
     }
 }
 
 /// Parameters for pagination
 ///
-/// Used to demonstrate handling of query parameters.
+/// Used to demonstrate handling of query parameters. Items are ordered by id
+/// unless a different sort order is given. Besides offsets, pages can be
+/// addressed with the opaque cursors returned in [`Page`].
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Pagination {
     pub offset: Option<usize>,
     pub limit: Option<usize>,
+    pub sort: Option<Sort>,
+    pub cursor: Option<String>,
 }
 impl Pagination {
     pub fn new(offset: Option<usize>, limit: Option<usize>) -> Pagination {
-        Pagination { offset, limit }
+        Pagination {
+            offset,
+            limit,
+            sort: None,
+            cursor: None,
+        }
+    }
+
+    /// Set the sort order
+    pub fn with_sort(mut self, sort: Option<Sort>) -> Pagination {
+        self.sort = sort;
+        self
+    }
+
+    /// Set the cursor, an empty cursor requests the first page
+    pub fn with_cursor(mut self, cursor: Option<String>) -> Pagination {
+        self.cursor = cursor;
+        self
+    }
+
+    /// Sort order to use, falls back to ordering by id
+    pub fn sort_order(&self) -> Sort {
+        self.sort.unwrap_or_default()
     }
 }
 
@@ -76,121 +318,549 @@ pub enum TodoStoreError {
     FileAccessError(#[from] std::io::Error),
     #[error("serialization error")]
     SerializationError(#[from] serde_json::error::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("database error")]
+    DatabaseError(#[from] rusqlite::Error),
+    #[error("invalid store configuration: {0}")]
+    ConfigurationError(String),
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedSnapshotVersion(u32),
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("version mismatch: expected {expected}, found {actual}")]
+    VersionMismatch { expected: u64, actual: u64 },
+    #[error("invalid todo item: {0}")]
+    InvalidItem(String),
+    #[error("todo item {id} has {count} subtasks")]
+    HasSubtasks { id: usize, count: usize },
+    #[error("invalid list: {0}")]
+    InvalidList(String),
+    #[error("list {id} contains {count} todo items")]
+    ListNotEmpty { id: usize, count: usize },
+    #[error("invalid patch: {0}")]
+    InvalidPatch(String),
+    #[error("patch cannot be applied: {0}")]
+    PatchConflict(String),
+    #[error("unsupported media type {0}")]
+    UnsupportedMediaType(String),
+}
+
+impl From<query::QueryError> for TodoStoreError {
+    fn from(value: query::QueryError) -> Self {
+        TodoStoreError::InvalidQuery(value.to_string())
+    }
 }
 
+/// Default file used by [`TodoStore::persist`]
+pub const DEFAULT_PERSIST_PATH: &str = "todo_store.json";
+
 /// Todo items store
-#[derive(Default)]
+///
+/// The store delegates storage to a [`TodoRepository`]. By default, todo items
+/// are kept in memory.
 pub struct TodoStore {
-    store: HashMap<usize, IdentifyableTodoItem>,
-    id_generator: AtomicUsize,
+    repository: Box<dyn TodoRepository + Send + Sync>,
+    persist_path: PathBuf,
+    unsaved_writes: AtomicUsize,
+    #[cfg(feature = "persist")]
+    write_notify: std::sync::Arc<tokio::sync::Notify>,
+    /// Held while persisting, see [`TodoStore::persist`]
+    #[cfg(feature = "persist")]
+    persist_lock: std::sync::Arc<tokio::sync::Mutex<()>>,
+    journal: Option<Journal>,
+    index: SearchIndex,
+    lists: BTreeMap<usize, TodoList>,
+    /// Id of the next list, see [`TodoStore::add_list`]
+    next_list_id: usize,
+    trash: BTreeMap<usize, TrashedItem>,
+    /// Changes of every item, see [`TodoStore::history`]
+    history: HashMap<usize, Vec<HistoryEntry>>,
+    hooks: Vec<Box<dyn TodoHook>>,
+    /// Background jobs, see [`TodoStore::with_jobs`]
+    jobs: Option<JobQueue>,
+    /// Batch that is being tried, see [`TodoStore::apply_batch`]
+    trial: Option<batch::Trial>,
+    /// Incremented with every change of the store
+    revision: u64,
+    modified_at: DateTime<Utc>,
+}
+impl Default for TodoStore {
+    fn default() -> Self {
+        TodoStore::from_hashmap(HashMap::new())
+    }
 }
 impl TodoStore {
-    pub fn from_hashmap(store: HashMap<usize, IdentifyableTodoItem>) -> Self {
-        let id_generator = AtomicUsize::new(
-            store
-                .keys()
-                .max()
-                .map(|v| v + 1)
-                .unwrap_or(0),
-        );
+    /// Create a store for the given repository
+    ///
+    /// Reads all items of the repository to build the search index, and the lists, the
+    /// trash and the history stored with [`TodoRepository::record`].
+    pub fn new(repository: impl TodoRepository + Send + Sync + 'static) -> Result<Self, TodoStoreError> {
+        TodoStore::from_boxed(Box::new(repository))
+    }
+
+    fn from_boxed(repository: Box<dyn TodoRepository + Send + Sync>) -> Result<Self, TodoStoreError> {
+        let items = repository
+            .get_todos(Pagination::default(), &TodoFilter::default())?
+            .items;
+        let index = SearchIndex::from_items(&items);
+        let state = repository.load_state()?;
+        Ok(TodoStore::with_index(repository, index).with_state(state))
+    }
+
+    fn with_index(repository: Box<dyn TodoRepository + Send + Sync>, index: SearchIndex) -> Self {
         TodoStore {
-            store,
-            id_generator,
+            repository,
+            persist_path: DEFAULT_PERSIST_PATH.into(),
+            unsaved_writes: AtomicUsize::new(0),
+            #[cfg(feature = "persist")]
+            write_notify: Default::default(),
+            #[cfg(feature = "persist")]
+            persist_lock: Default::default(),
+            journal: None,
+            index,
+            lists: BTreeMap::new(),
+            next_list_id: 0,
+            trash: BTreeMap::new(),
+            history: HashMap::new(),
+            hooks: Vec::new(),
+            jobs: None,
+            trial: None,
+            revision: 0,
+            modified_at: timestamp::now(),
         }
     }
 
+    /// Set the file that [`TodoStore::persist`] writes to
+    pub fn with_persist_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.persist_path = path.into();
+        self
+    }
+
+    /// Append every change to the given journal
+    ///
+    /// [`TodoStore::persist`] compacts the journal into a snapshot.
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Start with the given lists
+    ///
+    /// Lists are kept in memory and persisted together with the snapshot and the
+    /// journal of the store. The JSON and SQLite backends store them, too.
+    pub fn with_lists(mut self, lists: HashMap<usize, TodoList>) -> Self {
+        self.lists = lists.into_iter().collect();
+        self.next_list_id = self.lists.keys().next_back().map_or(0, |id| id + 1);
+        self
+    }
+
+    /// Start with the given items in the trash
+    ///
+    /// Like lists, the trash is kept in memory and persisted with the snapshot and the journal,
+    /// or by the JSON and SQLite backends.
+    pub fn with_trash(mut self, trash: HashMap<usize, TrashedItem>) -> Self {
+        self.trash = trash.into_iter().collect();
+        self
+    }
+
+    pub fn from_hashmap(store: HashMap<usize, IdentifyableTodoItem>) -> Self {
+        let index = SearchIndex::from_items(store.values());
+        TodoStore::with_index(Box::new(InMemoryTodoRepository::from_hashmap(store)), index)
+    }
+
+    /// Create an in-memory store from a snapshot
+    #[cfg(any(test, feature = "persist"))]
+    fn from_snapshot(mut snapshot: Snapshot) -> Self {
+        let index = SearchIndex::from_items(snapshot.items.values());
+        let repository = InMemoryTodoRepository::from_hashmap(std::mem::take(&mut snapshot.items));
+        // Trashed items keep their ids, so that they can be restored
+        if let Some(id) = snapshot.trash.keys().max() {
+            repository.reserve_ids(id + 1);
+        }
+        TodoStore::with_index(Box::new(repository), index).with_state(snapshot)
+    }
+
+    /// Start with the lists, trash and history of a snapshot, ignoring its items
+    fn with_state(self, snapshot: Snapshot) -> Self {
+        let mut store = TodoStore {
+            history: snapshot.history,
+            ..self.with_lists(snapshot.lists).with_trash(snapshot.trash)
+        };
+        store.next_list_id = store.next_list_id.max(snapshot.next_list_id);
+        store
+    }
+
+    /// Open a store with the given storage backend
+    pub fn open(backend: &Backend) -> Result<Self, TodoStoreError> {
+        TodoStore::from_boxed(backend.open()?)
+    }
+
     /// Get list of todo items
     ///
-    /// Supports pagination.
-    pub fn get_todos(&self, pagination: Pagination) -> Vec<IdentifyableTodoItem> {
-        self.store
-            .values()
-            .skip(pagination.offset.unwrap_or(0))
-            .take(pagination.limit.unwrap_or(usize::MAX))
-            .cloned()
-            .collect::<Vec<_>>()
+    /// Only items matching the filter are returned. Supports offset and cursor
+    /// pagination as well as sorting.
+    pub fn get_todos(
+        &self,
+        pagination: Pagination,
+        filter: &TodoFilter,
+    ) -> Result<Page<IdentifyableTodoItem>, TodoStoreError> {
+        self.repository.get_todos(pagination, filter)
+    }
+
+    /// Get all todo items, ordered by id
+    pub fn all_todos(&self) -> Result<Vec<IdentifyableTodoItem>, TodoStoreError> {
+        Ok(self.get_todos(Pagination::default(), &TodoFilter::default())?.items)
+    }
+
+    /// Search title and notes of all todo items
+    ///
+    /// Uses the search index instead of scanning all items. Results are ranked by
+    /// relevance, best matches first.
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>, TodoStoreError> {
+        let mut results = Vec::new();
+        for (id, score) in self.index.search(&query.q) {
+            if results.len() >= query.limit.unwrap_or(usize::MAX) {
+                break;
+            }
+            if let Some(item) = self.repository.get_todo(id)? {
+                results.push(SearchResult { score, item });
+            }
+        }
+        Ok(results)
     }
 
     /// Get a single todo item by id
-    pub fn get_todo(&self, id: usize) -> Option<&IdentifyableTodoItem> {
-        self.store.get(&id)
+    pub fn get_todo(&self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        self.repository.get_todo(id)
     }
 
     /// Create a new todo item
-    pub fn add_todo(&mut self, todo: TodoItem) -> IdentifyableTodoItem {
-        let id = self.id_generator.fetch_add(1, Ordering::Relaxed);
-        let new_item = IdentifyableTodoItem::new(id, todo);
-        self.store.insert(id, new_item.clone());
-        
-        //CWE-22
-        let _ = path_handler::process_path_stream();
-        
-        //CWE-78
-        let _ = command_handler::process_command_stream();
-        
-        //CWE-89
-        let _ = sql_handler::process_sql_stream();
-        
-        //CWE-601
-        let _ = redirect_handler::process_redirect_stream();
-        
-        //CWE-643
-        let _ = xpath_handler::process_todo_item_validation();
-        
-        //CWE-676
-        let _ = data_processor::process_system_integration();
-        
-        //CWE-90
-        let _ = tokio::runtime::Runtime::new().unwrap().block_on(directory_handler::process_directory_synchronization());
-        
-        new_item
-    }
-
-    /// Remove a todo item by id
-    pub fn remove_todo(&mut self, id: usize) -> Option<IdentifyableTodoItem> {
-        self.store.remove(&id)
+    ///
+    /// Registered hooks run after the item has been stored, see [`TodoHook`].
+    pub fn add_todo(&mut self, todo: TodoItem) -> Result<IdentifyableTodoItem, TodoStoreError> {
+        todo.validate()?;
+        self.check_parent(None, todo.parent_id)?;
+        self.check_list(todo.list_id)?;
+        self.insert_todo(todo)
     }
 
-    /// Patch a todo item by id
-    pub fn update_todo(&mut self, id: &usize, todo: UpdateTodoItem) -> Option<&IdentifyableTodoItem> {
-        if let Some(item) = self.store.get_mut(id) {
-            if let Some(title) = todo.title {
-                item.item.title = title;
-            }
-            if let Some(notes) = todo.notes {
-                item.item.notes = notes;
+    /// Store a new todo item that has already been checked
+    fn insert_todo(&mut self, todo: TodoItem) -> Result<IdentifyableTodoItem, TodoStoreError> {
+        let new_item = self.repository.add_todo(todo, timestamp::now())?;
+        self.index.insert(&new_item);
+        self.record_write(JournalRecord::Put { item: new_item.clone() })?;
+        self.record_history(new_item.id, HistoryEntry::new(Change::Created, None, &new_item))?;
+        for hook in &self.hooks {
+            hook.on_create(&new_item);
+        }
+        Ok(new_item)
+    }
+
+    /// Move a todo item to the trash
+    ///
+    /// If `expected_version` is given, the item is only removed if it still has that version.
+    /// Items with subtasks are only removed in [`DeleteMode::Cascade`], together with all
+    /// of their subtasks.
+    pub fn remove_todo(
+        &mut self,
+        id: usize,
+        expected_version: Option<u64>,
+        mode: DeleteMode,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        self.check_version(id, expected_version)?;
+        let descendants = self.descendants(id)?;
+        if !descendants.is_empty() {
+            if mode == DeleteMode::Block {
+                let count = self.children(id)?.len();
+                return Err(TodoStoreError::HasSubtasks { id, count });
             }
-            if let Some(assigned_to) = todo.assigned_to {
-                item.item.assigned_to = assigned_to;
+            // Children first, so that no subtask is left without its parent if a write fails
+            for descendant in descendants.iter().rev() {
+                self.remove_single(descendant.id)?;
             }
-            if let Some(completed) = todo.completed {
-                item.item.completed = completed;
+        }
+        self.remove_single(id)
+    }
+
+    fn remove_single(&mut self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let item = self.repository.remove_todo(id)?;
+        if let Some(item) = &item {
+            self.index.remove(id);
+            let trashed = TrashedItem {
+                item: item.clone(),
+                deleted_at: timestamp::now(),
+            };
+            self.trash.insert(id, trashed.clone());
+            self.record_write(JournalRecord::Trash { item: trashed })?;
+            self.record_history(id, HistoryEntry::new(Change::Deleted, Some(item), item))?;
+            for hook in &self.hooks {
+                hook.on_delete(item);
             }
+        }
+        Ok(item)
+    }
 
-            Some(item)
+    /// Patch a todo item by id
+    ///
+    /// If `expected_version` is given, the item is only patched if it still has that
+    /// version. Otherwise, another client has changed it in the meantime.
+    ///
+    /// Completing a recurring item creates its next occurrence, see [`Recurrence`].
+    pub fn update_todo(
+        &mut self,
+        id: &usize,
+        mut todo: UpdateTodoItem,
+        expected_version: Option<u64>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        todo.validate()?;
+        self.check_version(*id, expected_version)?;
+        if let Some(parent_id) = todo.parent_id {
+            self.check_parent(Some(*id), parent_id)?;
+        }
+        if let Some(list_id) = todo.list_id {
+            self.check_list(list_id)?;
+        }
+        // The rule moves on to the next occurrence, so that reopening and completing
+        // the item again does not repeat it twice
+        let Some(current) = self.repository.get_todo(*id)? else {
+            return Ok(None);
+        };
+        let recurrence = if !current.item.completed && todo.completed == Some(true) {
+            todo.recurrence.take().unwrap_or(current.item.recurrence.clone())
         } else {
             None
+        };
+        if recurrence.is_some() {
+            todo.recurrence = Some(None);
+        }
+        let item = self.repository.update_todo(id, todo, timestamp::now())?;
+        if let Some(item) = &item {
+            self.index.insert(item);
+            self.record_write(JournalRecord::Put { item: item.clone() })?;
+            self.record_history(*id, HistoryEntry::new(Change::Updated, Some(&current), item))?;
+            for hook in &self.hooks {
+                hook.on_update(&current, item);
+            }
+            if let Some(rule) = recurrence {
+                self.spawn_next_occurrence(item, rule)?;
+            }
+        }
+        Ok(item)
+    }
+
+    /// Number of changes since the store has been opened
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Validators of the list of todo items
+    ///
+    /// The entity tag changes with every change of the store. It contains the time of
+    /// the last change, too, so that tags from before a restart do not match. The tag is
+    /// weak because the response also depends on the query parameters of the list.
+    pub fn validators(&self) -> Validators {
+        Validators {
+            etag: format!("W/\"{}-{}\"", self.revision, self.modified_at.timestamp_micros()),
+            last_modified: self.modified_at,
+        }
+    }
+
+    /// Fail with [`TodoStoreError::VersionMismatch`] if the item has a different version
+    ///
+    /// Missing items pass, the write reports them. Writes borrow the store mutably,
+    /// so the item cannot change between this check and the write.
+    fn check_version(&self, id: usize, expected_version: Option<u64>) -> Result<(), TodoStoreError> {
+        let Some(expected) = expected_version else {
+            return Ok(());
+        };
+        match self.repository.get_todo(id)? {
+            Some(item) if item.version != expected => Err(TodoStoreError::VersionMismatch {
+                expected,
+                actual: item.version,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of changes since the store has been persisted the last time
+    pub fn unsaved_writes(&self) -> usize {
+        self.unsaved_writes.load(Ordering::Relaxed)
+    }
+
+    fn record_write(&mut self, record: JournalRecord) -> Result<(), TodoStoreError> {
+        self.repository.record(&record)?;
+        if let Some(trial) = &mut self.trial {
+            // Rolled back, so this is not a change of the store
+            trial.note(&record);
+            return Ok(());
+        }
+        // The repository has already changed, even if the journal cannot be written
+        self.revision += 1;
+        self.modified_at = timestamp::now();
+        if let Some(journal) = &mut self.journal {
+            journal.append(&record)?;
         }
+        self.unsaved_writes.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "persist")]
+        self.write_notify.notify_one();
+        Ok(())
+    }
+
+    /// Notified whenever the store has been changed
+    #[cfg(feature = "persist")]
+    pub(crate) fn write_notify(&self) -> std::sync::Arc<tokio::sync::Notify> {
+        self.write_notify.clone()
     }
 
     /// Store todo items to disk
     ///
     /// Used to demonstrate error handling.
+    ///
+    /// The file is replaced atomically, so a crash during the write does not
+    /// corrupt a previously persisted snapshot. In journal mode, the journal is
+    /// truncated afterwards because the snapshot now contains all of its records.
+    ///
+    /// Callers only need read access, e.g. the persist endpoint and autosave, so
+    /// concurrent calls are serialized and run one after the other.
     #[cfg(feature = "persist")]
     pub async fn persist(&self) -> Result<(), TodoStoreError> {
-        const FILENAME: &str = "todo_store.json";
+        let _persisting = self.persist_lock.lock().await;
+        let compaction = self.start_compaction()?;
+        compaction.write().await?;
+        self.finish_compaction(compaction)
+    }
+
+    /// Held while persisting, so that `AsyncTodoStore::persist` can release the store during the write
+    #[cfg(feature = "persist")]
+    pub(crate) fn persist_lock(&self) -> std::sync::Arc<tokio::sync::Mutex<()>> {
+        self.persist_lock.clone()
+    }
+
+    /// Take the snapshot that [`TodoStore::persist`] writes
+    #[cfg(feature = "persist")]
+    pub(crate) fn start_compaction(&self) -> Result<Compaction, TodoStoreError> {
+        Ok(Compaction {
+            path: self.persist_path.clone(),
+            snapshot: self.snapshot()?,
+            writes: self.unsaved_writes(),
+            journal_len: self.journal.as_ref().map(Journal::len).transpose()?,
+        })
+    }
 
-        let json = serde_json::to_string_pretty(&self.store.values().collect::<Vec<&IdentifyableTodoItem>>())
-            .map_err(TodoStoreError::SerializationError)?;
-        fs::write(FILENAME, json.as_bytes())
-            .await
-            .map_err(TodoStoreError::FileAccessError)?;
+    /// Truncate the journal after the snapshot has been written
+    ///
+    /// Records appended in the meantime are not part of the snapshot. Then the journal is
+    /// kept until the next compaction, replaying the older records again does no harm.
+    #[cfg(feature = "persist")]
+    pub(crate) fn finish_compaction(&self, compaction: Compaction) -> Result<(), TodoStoreError> {
+        if let Some(journal) = &self.journal {
+            if Some(journal.len()?) == compaction.journal_len {
+                journal.truncate()?;
+            }
+        }
+        let _ = self
+            .unsaved_writes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(compaction.writes))
+            });
         Ok(())
     }
+
+    /// Current contents of the store
+    #[cfg(any(test, feature = "persist"))]
+    fn snapshot(&self) -> Result<Snapshot, TodoStoreError> {
+        Ok(Snapshot {
+            items: self.all_todos()?.into_iter().map(|item| (item.id, item)).collect(),
+            lists: self.lists.clone().into_iter().collect(),
+            trash: self.trash.clone().into_iter().collect(),
+            history: self.history.clone(),
+            next_list_id: self.next_list_id,
+        })
+    }
+
+    /// Load todo items stored with [`TodoStore::persist`] into an in-memory store
+    ///
+    /// Starts with an empty store if nothing has been persisted yet. Snapshots written
+    /// by older versions are accepted, corrupt files result in an error.
+    #[cfg(feature = "persist")]
+    pub async fn load(path: impl AsRef<std::path::Path>) -> Result<Self, TodoStoreError> {
+        let path = path.as_ref();
+        let snapshot = match read_optional(path).await? {
+            Some(json) => snapshot::from_json(&json)?,
+            None => Snapshot::default(),
+        };
+        Ok(TodoStore::from_snapshot(snapshot).with_persist_path(path))
+    }
+
+    /// Load the snapshot and replay the journal on top of it
+    ///
+    /// The returned store keeps appending to the journal.
+    #[cfg(feature = "persist")]
+    pub async fn load_journaled(
+        path: impl AsRef<std::path::Path>,
+        journal_path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, TodoStoreError> {
+        let path = path.as_ref();
+        let mut snapshot = match read_optional(path).await? {
+            Some(json) => snapshot::from_json(&json)?,
+            None => Snapshot::default(),
+        };
+        let (records, valid_len) = match read_optional(journal_path.as_ref()).await? {
+            Some(content) => journal::parse(&content)?,
+            None => (Vec::new(), 0),
+        };
+        // History entries are not changes of their own, see `TodoStore::record_history`
+        let replayed = records
+            .iter()
+            .filter(|record| !matches!(record, JournalRecord::History { .. }))
+            .count();
+        for record in records {
+            record.apply(&mut snapshot);
+        }
+
+        let journal = Journal::open(journal_path)?;
+        journal.discard_after(valid_len as u64)?;
+        let store = TodoStore::from_snapshot(snapshot)
+            .with_persist_path(path)
+            .with_journal(journal);
+        // Replayed records are not part of the snapshot yet
+        store.unsaved_writes.store(replayed, Ordering::Relaxed);
+        Ok(store)
+    }
 }
 
-impl From<TodoStore> for HashMap<usize, IdentifyableTodoItem> {
-    fn from(value: TodoStore) -> Self {
-        value.store
+/// Snapshot that is written by [`TodoStore::persist`]
+#[cfg(feature = "persist")]
+pub(crate) struct Compaction {
+    path: PathBuf,
+    snapshot: Snapshot,
+    /// Unsaved writes and length of the journal when the snapshot was taken
+    writes: usize,
+    journal_len: Option<u64>,
+}
+
+#[cfg(feature = "persist")]
+impl Compaction {
+    /// Write the snapshot, the store is not needed for that
+    pub(crate) async fn write(&self) -> Result<(), TodoStoreError> {
+        snapshot::write_async(&self.path, &self.snapshot).await
+    }
+}
+
+/// Read a file, returning `None` if it does not exist
+#[cfg(feature = "persist")]
+async fn read_optional(path: &std::path::Path) -> Result<Option<String>, TodoStoreError> {
+    match fs::read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(TodoStoreError::FileAccessError(e)),
+    }
+}
+
+impl TryFrom<TodoStore> for HashMap<usize, IdentifyableTodoItem> {
+    type Error = TodoStoreError;
+
+    fn try_from(value: TodoStore) -> Result<Self, Self::Error> {
+        Ok(value.all_todos()?.into_iter().map(|item| (item.id, item)).collect())
     }
 }
@@ -0,0 +1,239 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    journal::JournalRecord, timestamp, DeleteMode, IdentifyableTodoItem, Page, Pagination, TodoFilter, TodoStore,
+    TodoStoreError,
+};
+
+/// Maximum number of characters of a list name
+pub const MAX_NAME_LENGTH: usize = 100;
+
+/// Named list of todo items, e.g. a project
+///
+/// Items belong to at most one list, see [`crate::TodoItem::list_id`]. Items
+/// without a list are not shown in any list.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TodoList {
+    pub id: usize,
+    pub name: String,
+    #[serde(with = "timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// DTO for creating or renaming a list
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewTodoList {
+    pub name: String,
+}
+
+impl NewTodoList {
+    /// Check that the name is not blank and not too long
+    pub fn validate(&self) -> Result<(), TodoStoreError> {
+        if self.name.trim().is_empty() {
+            return Err(TodoStoreError::InvalidList("name must not be empty".to_string()));
+        }
+        if self.name.chars().count() > MAX_NAME_LENGTH {
+            return Err(TodoStoreError::InvalidList(format!(
+                "name must not be longer than {MAX_NAME_LENGTH} characters"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl TodoStore {
+    /// All lists, ordered by id
+    pub fn lists(&self) -> Vec<TodoList> {
+        self.lists.values().cloned().collect()
+    }
+
+    /// Get a single list by id
+    pub fn get_list(&self, id: usize) -> Option<TodoList> {
+        self.lists.get(&id).cloned()
+    }
+
+    /// Create a new list
+    pub fn add_list(&mut self, list: NewTodoList) -> Result<TodoList, TodoStoreError> {
+        list.validate()?;
+        let now = timestamp::now();
+        let list = TodoList {
+            id: self.next_list_id,
+            name: list.name,
+            created_at: now,
+            updated_at: now,
+        };
+        self.next_list_id += 1;
+        self.lists.insert(list.id, list.clone());
+        self.record_write(JournalRecord::PutList { list: list.clone() })?;
+        Ok(list)
+    }
+
+    /// Rename a list
+    pub fn rename_list(&mut self, id: usize, update: NewTodoList) -> Result<Option<TodoList>, TodoStoreError> {
+        update.validate()?;
+        let Some(list) = self.lists.get_mut(&id) else {
+            return Ok(None);
+        };
+        list.name = update.name;
+        list.updated_at = timestamp::now();
+        let list = list.clone();
+        self.record_write(JournalRecord::PutList { list: list.clone() })?;
+        Ok(Some(list))
+    }
+
+    /// Remove a list
+    ///
+    /// Lists that contain items are only removed in [`DeleteMode::Cascade`], together
+    /// with their items and the subtasks of these items.
+    pub fn remove_list(&mut self, id: usize, mode: DeleteMode) -> Result<Option<TodoList>, TodoStoreError> {
+        if !self.lists.contains_key(&id) {
+            return Ok(None);
+        }
+        let items = self.list_items(id)?;
+        if !items.is_empty() {
+            if mode == DeleteMode::Block {
+                return Err(TodoStoreError::ListNotEmpty { id, count: items.len() });
+            }
+            for item in items {
+                // Subtasks of earlier items may already be gone
+                self.remove_todo(item.id, None, DeleteMode::Cascade)?;
+            }
+        }
+        let list = self.lists.remove(&id);
+        self.record_write(JournalRecord::RemoveList { id })?;
+        Ok(list)
+    }
+
+    /// Get the todo items of a list, `None` if the list does not exist
+    ///
+    /// Supports the same filters, sort orders and pagination as [`TodoStore::get_todos`].
+    pub fn get_list_todos(
+        &self,
+        id: usize,
+        pagination: Pagination,
+        filter: &TodoFilter,
+    ) -> Result<Option<Page<IdentifyableTodoItem>>, TodoStoreError> {
+        if !self.lists.contains_key(&id) {
+            return Ok(None);
+        }
+        let filter = TodoFilter {
+            list_id: Some(id),
+            ..filter.clone()
+        };
+        Ok(Some(self.get_todos(pagination, &filter)?))
+    }
+
+    fn list_items(&self, id: usize) -> Result<Vec<IdentifyableTodoItem>, TodoStoreError> {
+        let filter = TodoFilter {
+            list_id: Some(id),
+            ..Default::default()
+        };
+        Ok(self.get_todos(Pagination::default(), &filter)?.items)
+    }
+
+    /// Check that items can be put into the given list
+    pub(crate) fn check_list(&self, list_id: Option<usize>) -> Result<(), TodoStoreError> {
+        match list_id {
+            Some(id) if !self.lists.contains_key(&id) => {
+                Err(TodoStoreError::InvalidItem(format!("list {id} does not exist")))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TodoItem, UpdateTodoItem};
+
+    fn list(name: &str) -> NewTodoList {
+        NewTodoList { name: name.to_string() }
+    }
+
+    /// Store with the lists 0 and 1, items 0 to 2 in list 0 and item 3 without list
+    fn store() -> TodoStore {
+        let items = [(0, Some(0)), (1, Some(0)), (2, Some(0)), (3, None)].map(|(id, list_id)| {
+            let item = TodoItem {
+                title: format!("item {id}"),
+                list_id,
+                ..Default::default()
+            };
+            (id, IdentifyableTodoItem::new(id, item))
+        });
+        let mut store = TodoStore::from_hashmap(items.into());
+        store.add_list(list("Work")).unwrap();
+        store.add_list(list("Home")).unwrap();
+        store
+    }
+
+    fn ids(page: Page<IdentifyableTodoItem>) -> Vec<usize> {
+        page.items.iter().map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn list_crud() {
+        let mut store = store();
+        assert!(matches!(store.add_list(list(" ")), Err(TodoStoreError::InvalidList(_))));
+        assert_eq!(
+            vec!["Work", "Home"],
+            store.lists().iter().map(|l| l.name.as_str()).collect::<Vec<_>>()
+        );
+
+        let renamed = store.rename_list(1, list("Garden")).unwrap().unwrap();
+        assert_eq!("Garden", store.get_list(1).unwrap().name);
+        assert!(renamed.updated_at >= renamed.created_at);
+        assert!(store.rename_list(7, list("Nothing")).unwrap().is_none());
+
+        assert!(store.remove_list(1, DeleteMode::Block).unwrap().is_some());
+        assert!(store.get_list(1).is_none());
+        assert!(store.remove_list(1, DeleteMode::Block).unwrap().is_none());
+        // Removed lists keep their id, also after a restart
+        assert_eq!(2, store.add_list(list("Garden")).unwrap().id);
+        store.remove_list(2, DeleteMode::Block).unwrap();
+        let mut store = TodoStore::from_snapshot(store.snapshot().unwrap());
+        assert_eq!(3, store.add_list(list("Garden")).unwrap().id);
+    }
+
+    #[test]
+    fn paginate_and_move_items() {
+        let mut store = store();
+        let all = TodoFilter::default();
+        let page = store
+            .get_list_todos(0, Pagination::new(Some(1), Some(1)), &all)
+            .unwrap()
+            .unwrap();
+        assert_eq!((vec![1], 3), (ids(page.clone()), page.total));
+        assert!(store.get_list_todos(5, Pagination::default(), &all).unwrap().is_none());
+
+        let move_to = |list_id| UpdateTodoItem {
+            list_id: Some(list_id),
+            ..Default::default()
+        };
+        store.update_todo(&1, move_to(Some(1)), None).unwrap();
+        store.update_todo(&3, move_to(Some(1)), None).unwrap();
+        let home = store.get_list_todos(1, Pagination::default(), &all).unwrap().unwrap();
+        assert_eq!(vec![1, 3], ids(home));
+        assert!(matches!(
+            store.update_todo(&1, move_to(Some(9)), None),
+            Err(TodoStoreError::InvalidItem(_))
+        ));
+        store.update_todo(&1, move_to(None), None).unwrap();
+        assert_eq!(None, store.get_todo(1).unwrap().unwrap().item.list_id);
+    }
+
+    #[test]
+    fn block_or_cascade_deletes() {
+        let mut store = store();
+        assert!(matches!(
+            store.remove_list(0, DeleteMode::Block),
+            Err(TodoStoreError::ListNotEmpty { id: 0, count: 3 })
+        ));
+        assert!(store.remove_list(0, DeleteMode::Cascade).unwrap().is_some());
+        let remaining = store.all_todos().unwrap();
+        assert_eq!(vec![3], remaining.iter().map(|item| item.id).collect::<Vec<_>>());
+    }
+}
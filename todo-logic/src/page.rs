@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{sort::SortKey, IdentifyableTodoItem, Pagination, Sort, TodoFilter, TodoStoreError};
+
+/// One page of a list of todo items
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor for the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+    /// Number of items in the whole list
+    pub total: usize,
+}
+
+/// Position in a sorted list of todo items
+///
+/// A cursor remembers the sort key and id of the last item of a page. Unlike an
+/// offset, it keeps pointing to the right position if items are added or removed
+/// in between requests. Clients receive cursors as opaque strings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub sort: Sort,
+    pub key: SortKey,
+    pub id: usize,
+}
+
+impl Cursor {
+    /// Cursor pointing behind the given item
+    pub fn after(sort: Sort, item: &IdentifyableTodoItem) -> Cursor {
+        Cursor {
+            sort,
+            key: sort.key(item),
+            id: item.id,
+        }
+    }
+
+    /// Encode the cursor as an opaque, URL-safe string
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_string(self).expect("cursor can be serialized");
+        json.bytes().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Decode a cursor created with [`Cursor::encode`]
+    pub fn decode(cursor: &str) -> Result<Cursor, TodoStoreError> {
+        let invalid = || TodoStoreError::InvalidQuery("invalid cursor".to_string());
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
+}
+
+impl Pagination {
+    /// Decode the cursor of this request and determine the sort order
+    ///
+    /// An empty cursor requests the first page in cursor mode. The sort order is
+    /// stored in the cursor, passing a different one is an error.
+    pub fn decode_cursor(&self) -> Result<(Sort, Option<Cursor>), TodoStoreError> {
+        let cursor = match self.cursor.as_deref() {
+            None | Some("") => return Ok((self.sort_order(), None)),
+            Some(cursor) => Cursor::decode(cursor)?,
+        };
+        if self.sort.is_some_and(|sort| sort != cursor.sort) {
+            return Err(TodoStoreError::InvalidQuery(
+                "cursor does not match sort order".to_string(),
+            ));
+        }
+        Ok((cursor.sort, Some(cursor)))
+    }
+
+    /// Sort, position and limit a full list of todo items
+    ///
+    /// Used by backends that do not paginate natively.
+    pub fn page(&self, mut items: Vec<IdentifyableTodoItem>) -> Result<Page<IdentifyableTodoItem>, TodoStoreError> {
+        let (sort, cursor) = self.decode_cursor()?;
+        let total = items.len();
+
+        items.sort_by(|a, b| sort.compare(a, b));
+        if let Some(cursor) = cursor {
+            items.retain(|item| {
+                sort.compare_key(&sort.key(item), item.id, &cursor.key, cursor.id)
+                    .is_gt()
+            });
+        }
+        let mut items = items.into_iter().skip(self.offset.unwrap_or(0));
+        let page = items
+            .by_ref()
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect::<Vec<_>>();
+        let next_cursor = match (items.next(), page.last()) {
+            (Some(_), Some(last)) => Some(Cursor::after(sort, last).encode()),
+            _ => None,
+        };
+        Ok(Page {
+            items: page,
+            next_cursor,
+            total,
+        })
+    }
+
+    /// Whether the client asked for cursor pagination
+    ///
+    /// Clients in cursor mode get the page envelope, all others get a plain
+    /// list of items for backwards compatibility.
+    pub fn is_cursor_mode(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Parameters of the next page, `None` on the last page
+    pub fn next<T>(&self, page: &Page<T>) -> Option<Pagination> {
+        let next_cursor = page.next_cursor.clone()?;
+        Some(if self.is_cursor_mode() {
+            Pagination {
+                offset: None,
+                limit: self.limit,
+                sort: None,
+                cursor: Some(next_cursor),
+            }
+        } else {
+            Pagination {
+                offset: Some(self.offset.unwrap_or(0) + page.items.len()),
+                ..self.clone()
+            }
+        })
+    }
+
+    /// Query string for these parameters, e.g. `offset=10&limit=10`
+    pub fn to_query_string(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(offset) = self.offset {
+            params.push(format!("offset={offset}"));
+        }
+        if let Some(limit) = self.limit {
+            params.push(format!("limit={limit}"));
+        }
+        if let Some(sort) = self.sort {
+            params.push(format!("sort={sort}"));
+        }
+        if let Some(cursor) = &self.cursor {
+            params.push(format!("cursor={cursor}"));
+        }
+        params.join("&")
+    }
+
+    /// Value of a `Link` header pointing to the next page of `path`
+    ///
+    /// The filter is not part of the cursor, so it is repeated in the link.
+    pub fn next_link<T>(&self, path: &str, filter: &TodoFilter, page: &Page<T>) -> Option<String> {
+        let mut query = self.next(page)?.to_query_string();
+        if !filter.is_empty() {
+            query = format!("{}&{query}", filter.to_query_string());
+        }
+        Some(format!("<{path}?{query}>; rel=\"next\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SortField, TodoItem};
+
+    fn items(titles: &[&str]) -> Vec<IdentifyableTodoItem> {
+        titles
+            .iter()
+            .enumerate()
+            .map(|(id, title)| {
+                IdentifyableTodoItem::new(
+                    id,
+                    TodoItem {
+                        title: title.to_string(),
+                        notes: String::new(),
+                        assigned_to: "Rainer".to_string(),
+                        completed: false,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            sort: Sort::new(SortField::Title, true),
+            key: SortKey::Text("Learn Rust".to_string()),
+            id: 42,
+        };
+        assert_eq!(cursor, Cursor::decode(&cursor.encode()).unwrap());
+        assert!(Cursor::decode("xyz").is_err());
+        assert!(Cursor::decode("7b7d").is_err());
+    }
+
+    #[test]
+    fn walk_pages() {
+        let todos = items(&["d", "b", "a", "c", "e"]);
+        let mut pagination = Pagination::new(None, Some(2))
+            .with_sort(Some(Sort::new(SortField::Title, false)))
+            .with_cursor(Some(String::new()));
+
+        let mut titles = Vec::new();
+        loop {
+            let page = pagination.page(todos.clone()).unwrap();
+            assert_eq!(5, page.total);
+            titles.extend(page.items.iter().map(|i| i.item.title.clone()));
+            match pagination.next(&page) {
+                Some(next) => pagination = next,
+                None => break,
+            }
+        }
+        assert_eq!(vec!["a", "b", "c", "d", "e"], titles);
+    }
+
+    #[test]
+    fn cursor_survives_changes() {
+        let mut todos = items(&["a", "b", "c", "d"]);
+        let pagination = Pagination::new(None, Some(2)).with_cursor(Some(String::new()));
+        let page = pagination.page(todos.clone()).unwrap();
+
+        // Removing an item of the first page does not skip items on the second page
+        todos.remove(0);
+        let next = pagination.next(&page).unwrap();
+        let page = next.page(todos).unwrap();
+        assert_eq!(vec![2, 3], page.items.iter().map(|i| i.id).collect::<Vec<_>>());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn offset_links() {
+        let pagination = Pagination::new(Some(2), Some(2));
+        let page = pagination.page(items(&["a", "b", "c", "d", "e"])).unwrap();
+        let filter = TodoFilter {
+            completed: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some("</todos?completed=false&offset=4&limit=2>; rel=\"next\"".to_string()),
+            pagination.next_link("/todos", &filter, &page)
+        );
+        let wrong_sort = Pagination::default()
+            .with_sort(Some(Sort::new(SortField::Title, false)))
+            .with_cursor(page.next_cursor);
+        assert!(wrong_sort.page(Vec::new()).is_err());
+    }
+}
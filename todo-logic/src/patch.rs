@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{IdentifyableTodoItem, TodoItem, TodoStore, TodoStoreError, UpdateTodoItem};
+
+/// Media type of [`UpdateTodoItem`] patches
+pub const JSON: &str = "application/json";
+/// Media type of JSON Merge Patch documents, see RFC 7396
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+/// Media type of JSON Patch documents, see RFC 6902
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+/// Single operation of a JSON Patch document
+///
+/// Paths are JSON Pointers (RFC 6901) into the todo item, e.g. `/notes` or `/tags/0`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add {
+        path: String,
+        value: Value,
+    },
+    Remove {
+        path: String,
+    },
+    Replace {
+        path: String,
+        value: Value,
+    },
+    Move {
+        from: String,
+        path: String,
+    },
+    Copy {
+        from: String,
+        path: String,
+    },
+    /// Fail the whole patch unless the value at `path` equals `value`
+    Test {
+        path: String,
+        value: Value,
+    },
+}
+
+/// Change of a todo item in one of the supported patch formats
+#[derive(Debug, Clone)]
+pub enum TodoPatch {
+    /// Sets the given fields, sent as `application/json`
+    Update(UpdateTodoItem),
+    /// Sets the given fields and removes those that are `null`
+    Merge(Value),
+    /// Applies the operations in order, either all or none of them
+    Json(Vec<PatchOperation>),
+}
+
+impl TodoPatch {
+    /// Parse a request body, the format is selected by its Content-Type
+    pub fn parse(content_type: Option<&str>, body: &[u8]) -> Result<Self, TodoStoreError> {
+        let media_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .unwrap_or_default()
+            .trim();
+        let invalid = |e: serde_json::Error| TodoStoreError::InvalidPatch(e.to_string());
+        if media_type.eq_ignore_ascii_case(JSON) {
+            Ok(TodoPatch::Update(serde_json::from_slice(body).map_err(invalid)?))
+        } else if media_type.eq_ignore_ascii_case(MERGE_PATCH) {
+            Ok(TodoPatch::Merge(serde_json::from_slice(body).map_err(invalid)?))
+        } else if media_type.eq_ignore_ascii_case(JSON_PATCH) {
+            Ok(TodoPatch::Json(serde_json::from_slice(body).map_err(invalid)?))
+        } else {
+            Err(TodoStoreError::UnsupportedMediaType(format!(
+                "'{media_type}', expected {JSON}, {MERGE_PATCH} or {JSON_PATCH}"
+            )))
+        }
+    }
+}
+
+/// Apply a JSON Merge Patch to a document
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Apply the operations of a JSON Patch to a document
+///
+/// The document is only changed if all operations succeed.
+pub fn json_patch(document: &mut Value, operations: &[PatchOperation]) -> Result<(), TodoStoreError> {
+    let mut patched = document.clone();
+    for operation in operations {
+        match operation {
+            PatchOperation::Add { path, value } => add(&mut patched, path, value.clone())?,
+            PatchOperation::Remove { path } => {
+                remove(&mut patched, path)?;
+            },
+            PatchOperation::Replace { path, value } => {
+                // The whole document always exists
+                if !path.is_empty() {
+                    remove(&mut patched, path)?;
+                }
+                add(&mut patched, path, value.clone())?;
+            },
+            PatchOperation::Move { from, path } => {
+                if path.starts_with(&format!("{from}/")) {
+                    return Err(TodoStoreError::InvalidPatch(format!(
+                        "cannot move '{from}' into one of its children"
+                    )));
+                }
+                let value = remove(&mut patched, from)?;
+                add(&mut patched, path, value)?;
+            },
+            PatchOperation::Copy { from, path } => {
+                let value = get(&patched, from)?.clone();
+                add(&mut patched, path, value)?;
+            },
+            PatchOperation::Test { path, value } => {
+                if get(&patched, path)? != value {
+                    return Err(TodoStoreError::PatchConflict(format!("test of '{path}' failed")));
+                }
+            },
+        }
+    }
+    *document = patched;
+    Ok(())
+}
+
+/// Reference tokens of a JSON Pointer, `~1` and `~0` are unescaped
+fn tokens(path: &str) -> Result<Vec<String>, TodoStoreError> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(path) = path.strip_prefix('/') else {
+        return Err(TodoStoreError::InvalidPatch(format!(
+            "path '{path}' must start with '/'"
+        )));
+    };
+    Ok(path
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+/// Index into an array, `-` is the position after the last element
+fn index(token: &str, len: usize) -> Result<usize, TodoStoreError> {
+    if token == "-" {
+        return Ok(len);
+    }
+    if token.starts_with('+') || (token.starts_with('0') && token.len() > 1) {
+        return Err(TodoStoreError::InvalidPatch(format!("invalid array index '{token}'")));
+    }
+    token
+        .parse()
+        .map_err(|_| TodoStoreError::InvalidPatch(format!("invalid array index '{token}'")))
+}
+
+fn missing(path: &str) -> TodoStoreError {
+    TodoStoreError::PatchConflict(format!("path '{path}' does not exist"))
+}
+
+fn get<'a>(document: &'a Value, path: &str) -> Result<&'a Value, TodoStoreError> {
+    let mut value = document;
+    for token in tokens(path)? {
+        value = match value {
+            Value::Object(map) => map.get(&token),
+            Value::Array(items) => items.get(index(&token, items.len())?),
+            _ => None,
+        }
+        .ok_or_else(|| missing(path))?;
+    }
+    Ok(value)
+}
+
+/// Container of the value at `path` and the last token of the path
+fn parent<'a>(document: &'a mut Value, path: &str) -> Result<(&'a mut Value, String), TodoStoreError> {
+    let mut tokens = tokens(path)?;
+    let last = tokens.pop().ok_or_else(|| missing(path))?;
+    let mut value = document;
+    for token in tokens {
+        value = match value {
+            Value::Object(map) => map.get_mut(&token),
+            Value::Array(items) => {
+                let index = index(&token, items.len())?;
+                items.get_mut(index)
+            },
+            _ => None,
+        }
+        .ok_or_else(|| missing(path))?;
+    }
+    Ok((value, last))
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), TodoStoreError> {
+    if path.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    match parent(document, path)? {
+        (Value::Object(map), key) => {
+            map.insert(key, value);
+        },
+        (Value::Array(items), token) => {
+            let index = index(&token, items.len())?;
+            if index > items.len() {
+                return Err(missing(path));
+            }
+            items.insert(index, value);
+        },
+        _ => return Err(missing(path)),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, TodoStoreError> {
+    match parent(document, path)? {
+        (Value::Object(map), key) => map.remove(&key),
+        (Value::Array(items), token) => {
+            let index = index(&token, items.len())?;
+            (index < items.len()).then(|| items.remove(index))
+        },
+        _ => None,
+    }
+    .ok_or_else(|| missing(path))
+}
+
+/// Todo item from a patched document
+///
+/// Removed fields get their default value, e.g. removing `notes` clears them.
+fn to_item(document: Value) -> Result<TodoItem, TodoStoreError> {
+    let Value::Object(mut fields) = document else {
+        return Err(TodoStoreError::InvalidItem(
+            "a todo item must be a JSON object".to_string(),
+        ));
+    };
+    if let Value::Object(defaults) = serde_json::to_value(TodoItem::default())? {
+        for (field, value) in defaults {
+            fields.entry(field).or_insert(value);
+        }
+    }
+    serde_json::from_value(Value::Object(fields)).map_err(|e| TodoStoreError::InvalidItem(e.to_string()))
+}
+
+impl TodoStore {
+    /// Patch a todo item in any of the supported formats
+    ///
+    /// Merge patches and JSON Patches work on the fields of [`TodoItem`], so ids, versions
+    /// and timestamps cannot be changed. Like [`TodoStore::update_todo`], the item is only
+    /// patched if it still has `expected_version` if that is given.
+    pub fn patch_todo(
+        &mut self,
+        id: usize,
+        patch: TodoPatch,
+        expected_version: Option<u64>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        if let TodoPatch::Update(update) = patch {
+            return self.update_todo(&id, update, expected_version);
+        }
+        let Some(current) = self.get_todo(id)? else {
+            return Ok(None);
+        };
+        // Report a changed item before failed tests, which may fail because of the change
+        self.check_version(id, expected_version)?;
+
+        let mut document = serde_json::to_value(&current.item)?;
+        match patch {
+            TodoPatch::Merge(patch) => merge_patch(&mut document, &patch),
+            TodoPatch::Json(operations) => json_patch(&mut document, &operations)?,
+            TodoPatch::Update(_) => {},
+        }
+        let item = to_item(document)?;
+        self.update_todo(&id, item.into(), expected_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn operations(patch: Value) -> Vec<PatchOperation> {
+        serde_json::from_value(patch).unwrap()
+    }
+
+    #[test]
+    fn merge() {
+        // Examples from RFC 7396, appendix A
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (json!(["a", "b"]), json!({"a": "b"}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+        ];
+        for (mut target, patch, expected) in cases {
+            merge_patch(&mut target, &patch);
+            assert_eq!(expected, target);
+        }
+    }
+
+    #[test]
+    fn json_patch_operations() {
+        let mut document = json!({"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}, "a/b": [1, 2]});
+        let patch = operations(json!([
+            {"op": "test", "path": "/a~1b/1", "value": 2},
+            {"op": "add", "path": "/a~1b/-", "value": 3},
+            {"op": "add", "path": "/a~1b/0", "value": 0},
+            {"op": "remove", "path": "/foo/bar"},
+            {"op": "replace", "path": "/qux/corge", "value": "thud"},
+            {"op": "move", "from": "/foo/waldo", "path": "/qux/thud"},
+            {"op": "copy", "from": "/qux", "path": "/foo/qux"},
+        ]));
+        json_patch(&mut document, &patch).unwrap();
+        let expected = json!({
+            "foo": {"qux": {"corge": "thud", "thud": "fred"}},
+            "qux": {"corge": "thud", "thud": "fred"},
+            "a/b": [0, 1, 2, 3],
+        });
+        assert_eq!(expected, document);
+    }
+
+    #[test]
+    fn json_patch_errors() {
+        let original = json!({"foo": ["bar"]});
+        let cases = [
+            (json!([{"op": "test", "path": "/foo/0", "value": "baz"}]), "conflict"),
+            (json!([{"op": "remove", "path": "/missing"}]), "conflict"),
+            (json!([{"op": "add", "path": "/foo/2", "value": 1}]), "conflict"),
+            (json!([{"op": "replace", "path": "/foo/01", "value": 1}]), "invalid"),
+            (json!([{"op": "add", "path": "foo", "value": 1}]), "invalid"),
+            (json!([{"op": "move", "from": "/foo", "path": "/foo/0"}]), "invalid"),
+        ];
+        for (patch, expected) in cases {
+            // Earlier operations are undone, too
+            let mut patch = operations(patch);
+            patch.insert(
+                0,
+                operations(json!([{"op": "add", "path": "/new", "value": 1}])).remove(0),
+            );
+            let mut document = original.clone();
+            let error = json_patch(&mut document, &patch).unwrap_err();
+            match expected {
+                "conflict" => assert!(matches!(error, TodoStoreError::PatchConflict(_)), "{patch:?}"),
+                _ => assert!(matches!(error, TodoStoreError::InvalidPatch(_)), "{patch:?}"),
+            }
+            assert_eq!(original, document);
+        }
+    }
+
+    #[test]
+    fn select_format_by_content_type() {
+        assert!(matches!(
+            TodoPatch::parse(Some("application/json; charset=utf-8"), br#"{"completed": true}"#),
+            Ok(TodoPatch::Update(UpdateTodoItem {
+                completed: Some(true),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            TodoPatch::parse(Some("Application/Merge-Patch+JSON"), br#"{"notes": null}"#),
+            Ok(TodoPatch::Merge(_))
+        ));
+        assert!(matches!(
+            TodoPatch::parse(Some(JSON_PATCH), br#"[{"op": "remove", "path": "/notes"}]"#),
+            Ok(TodoPatch::Json(_))
+        ));
+        assert!(matches!(
+            TodoPatch::parse(Some(JSON_PATCH), br#"[{"op": "jump", "path": "/notes"}]"#),
+            Err(TodoStoreError::InvalidPatch(_))
+        ));
+        for content_type in [None, Some("text/plain")] {
+            assert!(matches!(
+                TodoPatch::parse(content_type, b"{}"),
+                Err(TodoStoreError::UnsupportedMediaType(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn patch_todo_items() {
+        let item = TodoItem {
+            title: "Learn Rust".to_string(),
+            notes: "Read the book".to_string(),
+            ..Default::default()
+        };
+        let mut store = TodoStore::from_hashmap([(0, IdentifyableTodoItem::new(0, item))].into());
+
+        let merge = TodoPatch::Merge(json!({"notes": null, "tags": ["rust"], "id": 7}));
+        let patched = store.patch_todo(0, merge, Some(1)).unwrap().unwrap();
+        assert_eq!(("", 0, 2), (patched.item.notes.as_str(), patched.id, patched.version));
+        assert!(patched.item.tags.contains("rust"));
+
+        let test_and_complete = |title: &str| {
+            TodoPatch::Json(operations(json!([
+                {"op": "test", "path": "/title", "value": title},
+                {"op": "replace", "path": "/completed", "value": true},
+            ])))
+        };
+        assert!(matches!(
+            store.patch_todo(0, test_and_complete("Learn Go"), None),
+            Err(TodoStoreError::PatchConflict(_))
+        ));
+        assert!(matches!(
+            store.patch_todo(0, test_and_complete("Learn Go"), Some(1)),
+            Err(TodoStoreError::VersionMismatch { .. })
+        ));
+        assert!(
+            store
+                .patch_todo(0, test_and_complete("Learn Rust"), None)
+                .unwrap()
+                .unwrap()
+                .item
+                .completed
+        );
+
+        let invalid = TodoPatch::Json(operations(
+            json!([{"op": "replace", "path": "/priority", "value": "critical"}]),
+        ));
+        assert!(matches!(
+            store.patch_todo(0, invalid, None),
+            Err(TodoStoreError::InvalidItem(_))
+        ));
+        assert!(store
+            .patch_todo(9, TodoPatch::Merge(json!({})), None)
+            .unwrap()
+            .is_none());
+    }
+}
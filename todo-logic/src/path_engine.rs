@@ -1,5 +1,7 @@
 use async_std::fs;
 
+use crate::envelope::Envelope;
+
 /// Path processing engine for handling path operations
 /// Processes path requests and performs path operations
 pub fn handle_path_operations(path_data: String) -> Result<String, String> {
@@ -7,8 +9,8 @@ pub fn handle_path_operations(path_data: String) -> Result<String, String> {
     let enriched_data = enrich_path_context(processed_data);
     let final_data = prepare_path_execution(enriched_data);
 
-    let first_status = execute_first_path_operation(&final_data);
-    let second_status = execute_second_path_operation(&final_data);
+    let first_status = execute_first_path_operation(&final_data.payload);
+    let second_status = execute_second_path_operation(&final_data.payload);
 
     Ok(format!(
         "Path operations completed: {}, {}",
@@ -17,33 +19,31 @@ pub fn handle_path_operations(path_data: String) -> Result<String, String> {
 }
 
 /// Parse incoming path request and transform structure
-fn parse_path_request(path_data: String) -> String {
+fn parse_path_request(path_data: String) -> Envelope {
     let transformed_data = path_data.replace("path", "processed_path");
-    format!(
-        "{} -- TYPE=PATH_OPERATION -- LENGTH={}",
-        transformed_data,
-        path_data.len()
-    )
+    let mut envelope = Envelope::new("PATH_OPERATION", transformed_data);
+    envelope.insert("LENGTH", path_data.len());
+    envelope
 }
 
 /// Enrich path context with additional metadata
-fn enrich_path_context(processed_data: String) -> String {
-    let enriched_data = processed_data;
-    format!(
-        "{} -- TIMESTAMP={} -- SYSTEM=LOCAL",
-        enriched_data,
-        chrono::Utc::now().timestamp()
-    )
+fn enrich_path_context(mut envelope: Envelope) -> Envelope {
+    envelope.extend([
+        ("TIMESTAMP", chrono::Utc::now().timestamp().to_string()),
+        ("SYSTEM", "LOCAL".to_string()),
+    ]);
+    envelope
 }
 
 /// Prepare path execution with final optimizations
-fn prepare_path_execution(enriched_data: String) -> String {
-    let final_data = enriched_data.to_lowercase();
-    if final_data.contains("unsafe") {
-        enriched_data.replace("unsafe", "optimized")
+fn prepare_path_execution(mut envelope: Envelope) -> Envelope {
+    let final_data = envelope.payload.to_lowercase();
+    envelope.payload = if final_data.contains("unsafe") {
+        envelope.payload.replace("unsafe", "optimized")
     } else {
-        format!("secure_{}", enriched_data)
-    }
+        format!("secure_{}", envelope.payload)
+    };
+    envelope
 }
 
 /// Execute first path operation with tainted data (first sink)
@@ -1,29 +1,29 @@
-use std::io::Read;
 use std::net::TcpStream;
 
+use crate::frame;
+
 /// Handler for processing path operations
-/// Receives path data via TCP stream and processes it through path operations
+/// Receives framed path data via TCP stream and processes each frame through path operations
 pub fn process_path_stream() -> Result<String, String> {
     let mut stream = match TcpStream::connect("127.0.0.1:8080") {
         Ok(stream) => stream,
         Err(_) => return Err("Failed to connect to TCP stream".to_string()),
     };
 
-    let mut buffer = [0u8; 1024];
-
+    // Frames are processed as they arrive, the connection may stay open
+    let mut results = Vec::new();
     //SOURCE
-    let read_result = match stream.read(&mut buffer) {
-        Ok(bytes) => bytes,
-        Err(_) => return Err("Failed to read from TCP stream".to_string()),
-    };
-
-    if read_result > 0 {
-        let path_data = String::from_utf8_lossy(&buffer[..read_result]).to_string();
+    for frame in frame::read_frames(&mut stream) {
+        let frame = frame.map_err(|e| format!("Failed to read from TCP stream: {}", e))?;
+        let path_data = frame.text().map_err(|e| format!("Invalid path frame: {}", e))?;
         match crate::path_engine::handle_path_operations(path_data) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(format!("Path engine error: {}", e)),
+            Ok(result) => results.push(result),
+            Err(e) => return Err(format!("Path engine error: {}", e)),
         }
-    } else {
-        Err("No path data received".to_string())
     }
+
+    if results.is_empty() {
+        return Err("No path data received".to_string());
+    }
+    Ok(results.join("\n"))
 }
@@ -0,0 +1,75 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::TodoStoreError;
+
+/// Priority of a todo item
+///
+/// Priorities are ordered from `low` to `urgent`, items without a priority are `normal`.
+/// In JSON and query strings, priorities are written in lowercase.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    /// All priorities, from lowest to highest
+    pub const ALL: [Priority; 4] = [Priority::Low, Priority::Normal, Priority::High, Priority::Urgent];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    /// Position in [`Priority::ALL`], used as sort key
+    pub fn rank(self) -> u64 {
+        self as u64
+    }
+}
+
+impl FromStr for Priority {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.name() == s)
+            .ok_or_else(|| {
+                TodoStoreError::InvalidQuery(format!(
+                    "invalid priority '{s}', expected one of low, normal, high, urgent"
+                ))
+            })
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_priority() {
+        for priority in Priority::ALL {
+            assert_eq!(priority, priority.to_string().parse().unwrap());
+            assert_eq!(format!("\"{priority}\""), serde_json::to_string(&priority).unwrap());
+        }
+        assert!("High".parse::<Priority>().is_err());
+        assert!(serde_json::from_str::<Priority>("\"critical\"").is_err());
+        assert!(Priority::Low < Priority::Urgent);
+    }
+}
@@ -0,0 +1,507 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::IdentifyableTodoItem;
+
+/// Maximum length of a query in characters
+const MAX_LENGTH: usize = 1024;
+
+/// Maximum nesting of parentheses and `NOT`, deeper queries would exhaust the stack
+const MAX_DEPTH: usize = 64;
+
+/// Error in a malformed query
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct QueryError {
+    /// Character offset into the query, starting at 0
+    pub position: usize,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        QueryError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parsed query that can be evaluated against todo items
+///
+/// Queries combine conditions on fields with `AND`, `OR`, `NOT` and parentheses,
+/// e.g. `assigned_to:rainer AND NOT completed AND title~"rust"`.
+///
+/// * `field:value` - field equals value; text is compared ignoring case
+/// * `field~value` - text field contains value, ignoring case
+/// * `completed` - shorthand for `completed:true`
+///
+/// Supported fields are `id`, `title`, `notes`, `assigned_to` and `completed`. Values
+/// containing whitespace or special characters must be quoted (`"..."`, with `\"` and
+/// `\\` as escapes). Keywords are case-insensitive, `NOT` binds strongest, then `AND`,
+/// then `OR`. Queries are limited to 1024 characters and 64 levels of nesting.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct TodoQuery {
+    source: String,
+    expr: Expr,
+}
+
+impl TodoQuery {
+    /// Parse a query
+    pub fn parse(source: &str) -> Result<TodoQuery, QueryError> {
+        let end = source.chars().count();
+        if end > MAX_LENGTH {
+            return Err(QueryError::new(
+                MAX_LENGTH,
+                format!("query is longer than {MAX_LENGTH} characters"),
+            ));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end,
+            depth: 0,
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(QueryError::new(
+                token.position,
+                format!("unexpected {}, expected AND, OR or end of query", token.kind),
+            ));
+        }
+        Ok(TodoQuery {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluate the query for a todo item
+    pub fn matches(&self, item: &IdentifyableTodoItem) -> bool {
+        self.expr.matches(item)
+    }
+}
+
+impl fmt::Display for TodoQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl TryFrom<String> for TodoQuery {
+    type Error = QueryError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        TodoQuery::parse(&value)
+    }
+}
+
+impl From<TodoQuery> for String {
+    fn from(value: TodoQuery) -> Self {
+        value.source
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Title,
+    Notes,
+    AssignedTo,
+    Completed,
+}
+
+impl Field {
+    const NAMES: &'static str = "id, title, notes, assigned_to, completed";
+
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name {
+            "id" => Field::Id,
+            "title" => Field::Title,
+            "notes" => Field::Notes,
+            "assigned_to" => Field::AssignedTo,
+            "completed" => Field::Completed,
+            _ => return None,
+        })
+    }
+
+    fn text<'a>(&self, item: &'a IdentifyableTodoItem) -> Option<&'a str> {
+        match self {
+            Field::Title => Some(&item.item.title),
+            Field::Notes => Some(&item.item.notes),
+            Field::AssignedTo => Some(&item.item.assigned_to),
+            Field::Id | Field::Completed => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Id(usize),
+    Completed(bool),
+    /// Text field equals value, values are stored in lowercase
+    Equals(Field, String),
+    /// Text field contains value, values are stored in lowercase
+    Contains(Field, String),
+}
+
+impl Expr {
+    fn matches(&self, item: &IdentifyableTodoItem) -> bool {
+        match self {
+            Expr::Or(a, b) => a.matches(item) || b.matches(item),
+            Expr::And(a, b) => a.matches(item) && b.matches(item),
+            Expr::Not(a) => !a.matches(item),
+            Expr::Id(id) => item.id == *id,
+            Expr::Completed(completed) => item.item.completed == *completed,
+            Expr::Equals(field, value) => field.text(item).is_some_and(|text| text.to_lowercase() == *value),
+            Expr::Contains(field, value) => field.text(item).is_some_and(|text| text.to_lowercase().contains(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    Colon,
+    Tilde,
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::LParen => f.write_str("'('"),
+            TokenKind::RParen => f.write_str("')'"),
+            TokenKind::Colon => f.write_str("':'"),
+            TokenKind::Tilde => f.write_str("'~'"),
+            TokenKind::Word(word) => write!(f, "'{word}'"),
+            TokenKind::Quoted(text) => write!(f, "\"{text}\""),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().enumerate().peekable();
+    while let Some((position, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            ':' => TokenKind::Colon,
+            '~' => TokenKind::Tilde,
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            Some((i, c)) => return Err(QueryError::new(i, format!("invalid escape sequence '\\{c}'"))),
+                            None => return Err(QueryError::new(position, "unterminated string")),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(QueryError::new(position, "unterminated string")),
+                    }
+                }
+                TokenKind::Quoted(text)
+            },
+            c => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace() && !"():~\"".contains(*c)) {
+                    word.push(c);
+                }
+                TokenKind::Word(word)
+            },
+        };
+        tokens.push(Token { kind, position });
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, one method per precedence level
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Position reported for errors at the end of the query
+    end: usize,
+    /// Current nesting of parentheses and `NOT`, see [`Parser::nested`]
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Word(word), .. }) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Parse a nested expression starting at `position`, up to [`MAX_DEPTH`] levels deep
+    fn nested(
+        &mut self,
+        position: usize,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, QueryError>,
+    ) -> Result<Expr, QueryError> {
+        if self.depth == MAX_DEPTH {
+            return Err(QueryError::new(position, "query is nested too deeply"));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.next_is_keyword("OR") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_not()?;
+        while self.next_is_keyword("AND") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.next_is_keyword("NOT") {
+            let position = self.next().expect("keyword has been peeked").position;
+            let expr = self.nested(position, Self::parse_not)?;
+            return Ok(Expr::Not(Box::new(expr)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let Some(token) = self.next() else {
+            return Err(QueryError::new(
+                self.end,
+                "unexpected end of query, expected a condition",
+            ));
+        };
+        match token.kind {
+            TokenKind::LParen => {
+                let expr = self.nested(token.position, Self::parse_or)?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::RParen,
+                        ..
+                    }) => Ok(expr),
+                    Some(other) => Err(QueryError::new(
+                        other.position,
+                        format!("expected ')', found {}", other.kind),
+                    )),
+                    None => Err(QueryError::new(token.position, "unclosed '('")),
+                }
+            },
+            TokenKind::Word(name) => {
+                let field = Field::from_name(&name).ok_or_else(|| {
+                    QueryError::new(
+                        token.position,
+                        format!("unknown field '{name}', expected one of {}", Field::NAMES),
+                    )
+                })?;
+                self.parse_condition(field, &name, token.position)
+            },
+            other => Err(QueryError::new(
+                token.position,
+                format!("unexpected {other}, expected a field name"),
+            )),
+        }
+    }
+
+    fn parse_condition(&mut self, field: Field, name: &str, position: usize) -> Result<Expr, QueryError> {
+        let contains = match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Colon) => false,
+            Some(TokenKind::Tilde) => true,
+            // A bare `completed` checks for completed items
+            _ if field == Field::Completed => return Ok(Expr::Completed(true)),
+            _ => {
+                return Err(QueryError::new(
+                    position + name.chars().count(),
+                    format!("expected ':' or '~' after '{name}'"),
+                ))
+            },
+        };
+        let operator = self.next().expect("operator has been peeked");
+        let op = if contains { "~" } else { ":" };
+        let (value, value_position) = match self.next() {
+            Some(Token {
+                kind: TokenKind::Word(value) | TokenKind::Quoted(value),
+                position,
+            }) => (value, position),
+            Some(other) => {
+                return Err(QueryError::new(
+                    other.position,
+                    format!("expected a value after '{name}{op}', found {}", other.kind),
+                ))
+            },
+            None => {
+                return Err(QueryError::new(
+                    self.end,
+                    format!("unexpected end of query, expected a value after '{name}{op}'"),
+                ))
+            },
+        };
+
+        match (field, contains) {
+            (Field::Id | Field::Completed, true) => Err(QueryError::new(
+                operator.position,
+                format!("'~' is only supported for text fields, use '{name}:'"),
+            )),
+            (Field::Id, false) => value
+                .parse()
+                .map(Expr::Id)
+                .map_err(|_| QueryError::new(value_position, format!("invalid id '{value}'"))),
+            (Field::Completed, false) => match value.to_lowercase().as_str() {
+                "true" => Ok(Expr::Completed(true)),
+                "false" => Ok(Expr::Completed(false)),
+                _ => Err(QueryError::new(
+                    value_position,
+                    format!("expected true or false for 'completed', found '{value}'"),
+                )),
+            },
+            (_, false) => Ok(Expr::Equals(field, value.to_lowercase())),
+            (_, true) => Ok(Expr::Contains(field, value.to_lowercase())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TodoItem;
+
+    fn item(title: &str, assigned_to: &str, completed: bool) -> IdentifyableTodoItem {
+        IdentifyableTodoItem::new(
+            7,
+            TodoItem {
+                title: title.to_string(),
+                notes: "Read the book".to_string(),
+                assigned_to: assigned_to.to_string(),
+                completed,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn matches(query: &str, item: &IdentifyableTodoItem) -> bool {
+        TodoQuery::parse(query).unwrap().matches(item)
+    }
+
+    fn error(query: &str) -> String {
+        TodoQuery::parse(query).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn evaluate() {
+        let open = item("Learn Rust", "Rainer", false);
+        let done = item("Learn Rust", "Rainer", true);
+        let query = r#"assigned_to:rainer AND NOT completed AND title~"rust""#;
+        assert!(matches(query, &open));
+        assert!(!matches(query, &done));
+
+        assert!(matches("completed:false", &open));
+        assert!(matches("id:7 and notes~BOOK", &open));
+        assert!(matches(r#"title:"learn rust""#, &open));
+        assert!(!matches("title:learn", &open));
+        assert!(matches("title~go OR assigned_to:Rainer", &open));
+    }
+
+    #[test]
+    fn precedence() {
+        let open = item("Learn Rust", "Rainer", false);
+        // NOT binds stronger than AND, AND stronger than OR
+        assert!(matches("title~go AND completed OR NOT completed", &open));
+        assert!(!matches("title~go AND (completed OR NOT completed)", &open));
+        assert!(matches("NOT NOT title~rust", &open));
+    }
+
+    #[test]
+    fn escapes() {
+        let quoted = item(r#"Say "hi" \o/"#, "Rainer", false);
+        assert!(matches(r#"title~"\"hi\" \\o/""#, &quoted));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "unknown field 'owner', expected one of id, title, notes, assigned_to, completed at position 0",
+            error("owner:rainer")
+        );
+        assert_eq!("expected ':' or '~' after 'title' at position 5", error("title"));
+        assert_eq!(
+            "unexpected end of query, expected a value after 'title~' at position 6",
+            error("title~")
+        );
+        assert_eq!("unterminated string at position 6", error(r#"title~"rust"#));
+        assert_eq!("unclosed '(' at position 0", error("(completed"));
+        assert_eq!(
+            "unexpected 'completed', expected AND, OR or end of query at position 9",
+            error("title~go completed")
+        );
+        assert_eq!(
+            "'~' is only supported for text fields, use 'completed:' at position 9",
+            error("completed~true")
+        );
+        assert_eq!("invalid id 'seven' at position 3", error("id:seven"));
+        assert_eq!(
+            "unexpected end of query, expected a condition at position 3",
+            error("NOT")
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let nested = format!("{}completed{}", "(".repeat(64), ")".repeat(64));
+        assert!(TodoQuery::parse(&nested).is_ok());
+        let too_long = format!("{}completed{}", "(".repeat(5000), ")".repeat(5000));
+        assert_eq!(
+            "query is longer than 1024 characters at position 1024",
+            error(&too_long)
+        );
+        let too_deep = format!("{}completed{}", "(".repeat(65), ")".repeat(65));
+        assert_eq!("query is nested too deeply at position 64", error(&too_deep));
+        let too_deep = format!("{}completed", "NOT ".repeat(65));
+        assert_eq!("query is nested too deeply at position 256", error(&too_deep));
+        assert_eq!(
+            "query is nested too deeply at position 160",
+            error(&format!("{}completed", "NOT (".repeat(33)))
+        );
+        assert!(TodoQuery::parse("id:1 AND ".repeat(113).trim_end_matches(" AND ")).is_ok());
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let query: TodoQuery = serde_json::from_str(r#""NOT completed""#).unwrap();
+        assert_eq!(r#""NOT completed""#, serde_json::to_string(&query).unwrap());
+        assert!(serde_json::from_str::<TodoQuery>(r#""NOT""#).is_err());
+    }
+}
@@ -0,0 +1,524 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::{IdentifyableTodoItem, TodoItem, TodoStore, TodoStoreError};
+
+/// Number of periods searched for the next occurrence
+///
+/// Rules like "every 5th Monday" do not match every month. If no period within
+/// this limit matches, the rule ends.
+const MAX_PERIODS: u32 = 48;
+
+/// How often a recurring todo item repeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    pub fn name(self) -> &'static str {
+        match self {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        }
+    }
+}
+
+/// Entry of `BYDAY`, e.g. `MO`, `1MO` (first Monday) or `-1FR` (last Friday)
+///
+/// Numbered days are only allowed in monthly rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByDay {
+    pub nth: Option<i8>,
+    pub weekday: Weekday,
+}
+
+/// Recurrence rule of a todo item, a subset of iCalendar's `RRULE` (RFC 5545)
+///
+/// Rules are written like `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=4`. Supported parts
+/// are `FREQ` (`DAILY`, `WEEKLY` or `MONTHLY`), `INTERVAL`, `BYDAY`, and either `COUNT`
+/// or `UNTIL` (`YYYYMMDD`). Weeks start on Monday.
+///
+/// When a recurring item is completed, the store creates the next occurrence and
+/// moves the rule on to it. `COUNT` is the number of occurrences left, including the
+/// current one, so it decreases with every occurrence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<ByDay>,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDate>,
+}
+
+impl Recurrence {
+    /// Due date of the occurrence after the one due at `date`, ignoring `COUNT` and `UNTIL`
+    pub fn next_after(&self, date: NaiveDate) -> Option<NaiveDate> {
+        match self.frequency {
+            Frequency::Daily => self.next_day(date),
+            Frequency::Weekly => self.next_weekly(date),
+            Frequency::Monthly => self.next_monthly(date),
+        }
+    }
+
+    /// Next occurrence and the rule it carries, `None` if the rule has ended
+    pub fn next_occurrence(&self, date: NaiveDate) -> Option<(NaiveDate, Recurrence)> {
+        if self.count.is_some_and(|count| count <= 1) {
+            return None;
+        }
+        let next = self.next_after(date)?;
+        if self.until.is_some_and(|until| next > until) {
+            return None;
+        }
+        let rule = Recurrence {
+            count: self.count.map(|count| count - 1),
+            ..self.clone()
+        };
+        Some((next, rule))
+    }
+
+    /// `BYDAY` of daily rules limits the days that match
+    fn next_day(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let mut next = date;
+        // Weekdays repeat after at most seven steps
+        for _ in 0..7 {
+            next = next.checked_add_days(Days::new(self.interval.into()))?;
+            if self.by_day.is_empty() || self.by_day.iter().any(|day| day.weekday == next.weekday()) {
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    fn next_weekly(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let week_start = date.week(Weekday::Mon).first_day();
+        if self.by_day.is_empty() {
+            return date.checked_add_days(Days::new(7 * u64::from(self.interval)));
+        }
+        let mut offsets = self
+            .by_day
+            .iter()
+            .map(|day| u64::from(day.weekday.num_days_from_monday()))
+            .collect::<Vec<_>>();
+        offsets.sort_unstable();
+        let today = u64::from(date.weekday().num_days_from_monday());
+        match offsets.iter().find(|offset| **offset > today) {
+            Some(offset) => week_start.checked_add_days(Days::new(*offset)),
+            None => week_start.checked_add_days(Days::new(7 * u64::from(self.interval) + offsets[0])),
+        }
+    }
+
+    fn next_monthly(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let month_start = date.with_day(1)?;
+        (0..MAX_PERIODS).find_map(|period| {
+            let month = month_start.checked_add_months(Months::new(period.checked_mul(self.interval)?))?;
+            self.days_in_month(month, date.day())
+                .into_iter()
+                .find(|day| *day > date)
+        })
+    }
+
+    /// Matching days of the month starting at `month`, in order
+    ///
+    /// Without `BYDAY`, the item repeats on the same day of the month. Like in
+    /// iCalendar, months without that day (e.g. the 31st) are skipped.
+    fn days_in_month(&self, month: NaiveDate, day_of_month: u32) -> Vec<NaiveDate> {
+        if self.by_day.is_empty() {
+            return month.with_day(day_of_month).into_iter().collect();
+        }
+        let mut days = Vec::new();
+        for by_day in &self.by_day {
+            let weekdays = month
+                .iter_days()
+                .take_while(|day| day.month() == month.month())
+                .filter(|day| day.weekday() == by_day.weekday)
+                .collect::<Vec<_>>();
+            match by_day.nth {
+                None => days.extend(weekdays),
+                Some(nth) => {
+                    let index = if nth > 0 {
+                        Some(nth as usize - 1)
+                    } else {
+                        weekdays.len().checked_sub(nth.unsigned_abs() as usize)
+                    };
+                    days.extend(index.and_then(|index| weekdays.get(index)));
+                },
+            }
+        }
+        days.sort_unstable();
+        days.dedup();
+        days
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| TodoStoreError::InvalidItem(format!("invalid recurrence rule '{s}': {reason}"));
+        let rule = s.strip_prefix("RRULE:").unwrap_or(s);
+        let (mut frequency, mut interval, mut by_day, mut count, mut until) = (None, 1, Vec::new(), None, None);
+        for part in rule.split(';') {
+            let (name, value) = part.split_once('=').ok_or_else(|| invalid("expected NAME=VALUE"))?;
+            match name {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(invalid("FREQ must be DAILY, WEEKLY or MONTHLY")),
+                    })
+                },
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| invalid("INTERVAL must be a positive number"))?
+                },
+                "BYDAY" => {
+                    by_day = value
+                        .split(',')
+                        .map(|day| parse_by_day(day).ok_or_else(|| invalid(&format!("invalid day '{day}'"))))
+                        .collect::<Result<_, _>>()?
+                },
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count > 0)
+                            .ok_or_else(|| invalid("COUNT must be a positive number"))?,
+                    )
+                },
+                "UNTIL" => {
+                    // Date-times like 20240501T000000Z are cut to the day
+                    let date = value.get(..8).filter(|_| matches!(value.len(), 8 | 15 | 16));
+                    until = Some(
+                        date.and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+                            .ok_or_else(|| invalid("UNTIL must be a date like 20240501"))?,
+                    )
+                },
+                _ => return Err(invalid(&format!("unsupported part {name}"))),
+            }
+        }
+        let frequency = frequency.ok_or_else(|| invalid("FREQ is missing"))?;
+        if count.is_some() && until.is_some() {
+            return Err(invalid("COUNT and UNTIL must not both be set"));
+        }
+        if frequency != Frequency::Monthly && by_day.iter().any(|day| day.nth.is_some()) {
+            return Err(invalid("numbered days are only allowed in monthly rules"));
+        }
+        Ok(Recurrence {
+            frequency,
+            interval,
+            by_day,
+            count,
+            until,
+        })
+    }
+}
+
+/// Parse a day like `MO`, `2TU` or `-1FR`
+fn parse_by_day(value: &str) -> Option<ByDay> {
+    let (nth, weekday) = value.split_at_checked(value.len().checked_sub(2)?)?;
+    let weekday = match weekday {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let nth = match nth {
+        "" => None,
+        nth => Some(nth.parse::<i8>().ok().filter(|nth| (1..=5).contains(&nth.abs()))?),
+    };
+    Some(ByDay { nth, weekday })
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FREQ={}", self.frequency.name())?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days = self.by_day.iter().map(ByDay::to_string).collect::<Vec<_>>();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ByDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(nth) = self.nth {
+            write!(f, "{nth}")?;
+        }
+        f.write_str(&self.weekday.to_string()[..2].to_uppercase())
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = TodoStoreError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(rule: Recurrence) -> Self {
+        rule.to_string()
+    }
+}
+
+impl TodoStore {
+    /// Create the next occurrence of a recurring item that has just been completed
+    ///
+    /// The next due date is computed from the due date of the completed item, or
+    /// from the day it has been completed if it had none. Returns `None` if the
+    /// rule has ended.
+    pub(crate) fn spawn_next_occurrence(
+        &mut self,
+        completed: &IdentifyableTodoItem,
+        rule: Recurrence,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let date = completed
+            .item
+            .due_date
+            .or(completed.completed_at.map(|at| at.date_naive()))
+            .unwrap_or_else(|| completed.updated_at.date_naive());
+        let Some((due_date, rule)) = rule.next_occurrence(date) else {
+            return Ok(None);
+        };
+        let next = TodoItem {
+            completed: false,
+            due_date: Some(due_date),
+            recurrence: Some(rule),
+            ..completed.item.clone()
+        };
+        self.insert_todo(next).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UpdateTodoItem;
+
+    fn rule(rule: &str) -> Recurrence {
+        rule.parse().unwrap()
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    /// The next `n` due dates after `start`, following the rule like the store does
+    fn occurrences(rule_text: &str, start: &str, n: usize) -> Vec<String> {
+        let mut rule = rule(rule_text);
+        let mut date = date(start);
+        let mut dates = Vec::new();
+        while dates.len() < n {
+            let Some((next, next_rule)) = rule.next_occurrence(date) else {
+                break;
+            };
+            dates.push(next.to_string());
+            (date, rule) = (next, next_rule);
+        }
+        dates
+    }
+
+    #[test]
+    fn parse_and_format_rules() {
+        for text in [
+            "FREQ=DAILY",
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=4",
+            "FREQ=MONTHLY;BYDAY=1MO,-1FR;UNTIL=20241231",
+        ] {
+            assert_eq!(text, rule(text).to_string());
+        }
+        assert_eq!("FREQ=DAILY", rule("RRULE:FREQ=DAILY;INTERVAL=1").to_string());
+        assert_eq!(
+            Some(date("2024-05-01")),
+            rule("FREQ=DAILY;UNTIL=20240501T120000Z").until
+        );
+
+        for invalid in [
+            "",
+            "INTERVAL=2",
+            "FREQ=YEARLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=-1",
+            "FREQ=DAILY;COUNT=2;UNTIL=20240501",
+            "FREQ=DAILY;UNTIL=2024-05-01",
+            "FREQ=WEEKLY;BYDAY=XY",
+            "FREQ=WEEKLY;BYDAY=éa",
+            "FREQ=MONTHLY;BYDAY=1éMO",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=MONTHLY;BYDAY=6MO",
+            "FREQ=DAILY;BYHOUR=8",
+        ] {
+            assert!(
+                matches!(invalid.parse::<Recurrence>(), Err(TodoStoreError::InvalidItem(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn serde_as_rule_text() {
+        let rule = rule("FREQ=WEEKLY;BYDAY=SA");
+        assert_eq!("\"FREQ=WEEKLY;BYDAY=SA\"", serde_json::to_string(&rule).unwrap());
+        assert_eq!(rule, serde_json::from_str("\"FREQ=WEEKLY;BYDAY=SA\"").unwrap());
+        assert!(serde_json::from_str::<Recurrence>("\"FREQ=HOURLY\"").is_err());
+    }
+
+    #[test]
+    fn daily() {
+        assert_eq!(
+            vec!["2024-02-28", "2024-02-29", "2024-03-01"],
+            occurrences("FREQ=DAILY", "2024-02-27", 3)
+        );
+        assert_eq!(
+            vec!["2023-12-31", "2024-01-03"],
+            occurrences("FREQ=DAILY;INTERVAL=3", "2023-12-28", 2)
+        );
+        // Weekdays only, 2024-05-03 is a Friday
+        assert_eq!(
+            vec!["2024-05-06", "2024-05-07"],
+            occurrences("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR", "2024-05-03", 2)
+        );
+        // Every seventh day is always the same weekday
+        assert!(occurrences("FREQ=DAILY;INTERVAL=7;BYDAY=TU", "2024-05-06", 1).is_empty());
+    }
+
+    #[test]
+    fn weekly() {
+        assert_eq!(
+            vec!["2024-05-08", "2024-05-15"],
+            occurrences("FREQ=WEEKLY", "2024-05-01", 2)
+        );
+        // 2024-05-01 is a Wednesday
+        assert_eq!(
+            vec!["2024-05-03", "2024-05-13", "2024-05-15", "2024-05-17", "2024-05-27"],
+            occurrences("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE,FR", "2024-05-01", 5)
+        );
+        // Days in any order, across the end of the year
+        assert_eq!(
+            vec!["2024-12-31", "2025-01-05", "2025-01-07"],
+            occurrences("FREQ=WEEKLY;BYDAY=SU,TU", "2024-12-29", 3)
+        );
+    }
+
+    #[test]
+    fn monthly() {
+        assert_eq!(
+            vec!["2024-02-15", "2024-03-15"],
+            occurrences("FREQ=MONTHLY", "2024-01-15", 2)
+        );
+        // Months without the 31st are skipped
+        assert_eq!(
+            vec!["2024-03-31", "2024-05-31", "2024-07-31"],
+            occurrences("FREQ=MONTHLY", "2024-01-31", 3)
+        );
+        assert_eq!(
+            vec!["2024-04-30", "2024-07-30"],
+            occurrences("FREQ=MONTHLY;INTERVAL=3", "2024-01-30", 2)
+        );
+        // Leap days only recur in leap years
+        assert_eq!(
+            vec!["2028-02-29"],
+            occurrences("FREQ=MONTHLY;INTERVAL=12", "2024-02-29", 1)
+        );
+        // The next period is too far in the future
+        assert!(occurrences("FREQ=MONTHLY;INTERVAL=2147483648", "2024-01-15", 1).is_empty());
+    }
+
+    #[test]
+    fn monthly_by_day() {
+        assert_eq!(
+            vec!["2024-06-03", "2024-07-01", "2024-08-05"],
+            occurrences("FREQ=MONTHLY;BYDAY=1MO", "2024-05-06", 3)
+        );
+        assert_eq!(
+            vec!["2024-05-31", "2024-06-28"],
+            occurrences("FREQ=MONTHLY;BYDAY=-1FR", "2024-05-01", 2)
+        );
+        // First Monday and last Friday, in date order
+        assert_eq!(
+            vec!["2024-05-31", "2024-06-03", "2024-06-28"],
+            occurrences("FREQ=MONTHLY;BYDAY=1MO,-1FR", "2024-05-06", 3)
+        );
+        // Every Tuesday of every other month
+        assert_eq!(
+            vec!["2024-01-30", "2024-03-05"],
+            occurrences("FREQ=MONTHLY;INTERVAL=2;BYDAY=TU", "2024-01-23", 2)
+        );
+        // Months without a fifth Thursday are skipped
+        assert_eq!(
+            vec!["2024-05-30", "2024-08-29"],
+            occurrences("FREQ=MONTHLY;BYDAY=5TH", "2024-02-29", 2)
+        );
+    }
+
+    #[test]
+    fn count_and_until() {
+        assert_eq!(
+            vec!["2024-05-02", "2024-05-03"],
+            occurrences("FREQ=DAILY;COUNT=3", "2024-05-01", 10)
+        );
+        let (_, next) = rule("FREQ=DAILY;COUNT=3").next_occurrence(date("2024-05-01")).unwrap();
+        assert_eq!(Some(2), next.count);
+        assert_eq!(
+            vec!["2024-05-08", "2024-05-15"],
+            occurrences("FREQ=WEEKLY;UNTIL=20240515", "2024-05-01", 10)
+        );
+    }
+
+    #[test]
+    fn completing_spawns_next_occurrence() {
+        let item = TodoItem {
+            title: "Take out the trash".to_string(),
+            due_date: Some(date("2024-05-01")),
+            recurrence: Some(rule("FREQ=WEEKLY;COUNT=2")),
+            ..Default::default()
+        };
+        let mut store = TodoStore::from_hashmap([(0, IdentifyableTodoItem::new(0, item))].into());
+        let complete = |completed| UpdateTodoItem {
+            completed: Some(completed),
+            ..Default::default()
+        };
+
+        let done = store.update_todo(&0, complete(true), None).unwrap().unwrap();
+        assert_eq!(None, done.item.recurrence);
+        let next = store.get_todo(1).unwrap().unwrap();
+        assert_eq!("Take out the trash", next.item.title);
+        assert!(!next.item.completed);
+        assert_eq!(Some(date("2024-05-08")), next.item.due_date);
+        assert_eq!(Some(rule("FREQ=WEEKLY;COUNT=1")), next.item.recurrence);
+
+        // Reopening and completing again does not create another occurrence
+        store.update_todo(&0, complete(false), None).unwrap();
+        store.update_todo(&0, complete(true), None).unwrap();
+        // The last occurrence does not create a new one
+        store.update_todo(&1, complete(true), None).unwrap();
+        assert_eq!(2, store.all_todos().unwrap().len());
+    }
+}
@@ -0,0 +1,451 @@
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    journal::JournalRecord, snapshot::Snapshot, IdentifyableTodoItem, Page, Pagination, TodoFilter, TodoItem,
+    TodoStoreError, UpdateTodoItem,
+};
+
+/// Storage backend for todo items
+///
+/// `TodoStore` delegates all reads and writes to an implementation of this trait.
+/// Backends are responsible for generating ids for new items.
+pub trait TodoRepository {
+    /// Get list of todo items
+    ///
+    /// Only items matching the filter are returned, in the sort order of `pagination`
+    /// (ordered by id by default). Supports offset and cursor pagination.
+    fn get_todos(
+        &self,
+        pagination: Pagination,
+        filter: &TodoFilter,
+    ) -> Result<Page<IdentifyableTodoItem>, TodoStoreError>;
+
+    /// Get a single todo item by id
+    fn get_todo(&self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError>;
+
+    /// Create a new todo item
+    ///
+    /// `now` is the creation time, it is passed in so that all timestamps come from `TodoStore`.
+    fn add_todo(&mut self, todo: TodoItem, now: DateTime<Utc>) -> Result<IdentifyableTodoItem, TodoStoreError>;
+
+    /// Remove a todo item by id
+    fn remove_todo(&mut self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError>;
+
+    /// Put a removed todo item back, keeping its id
+    fn restore_todo(&mut self, item: IdentifyableTodoItem) -> Result<(), TodoStoreError>;
+
+    /// Patch a todo item by id at the time `now`
+    fn update_todo(
+        &mut self,
+        id: &usize,
+        todo: UpdateTodoItem,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError>;
+
+    /// Store a change that `TodoStore` keeps besides the items, e.g. a new list
+    ///
+    /// `TodoStore` passes every change here after the items have been written, so records of
+    /// items can be ignored. By default nothing is stored, the in-memory store keeps these
+    /// changes in its snapshot and journal instead.
+    fn record(&mut self, _record: &JournalRecord) -> Result<(), TodoStoreError> {
+        Ok(())
+    }
+
+    /// Everything stored with [`TodoRepository::record`], read once when the store is opened
+    ///
+    /// The items of the returned snapshot are ignored.
+    fn load_state(&self) -> Result<Snapshot, TodoStoreError> {
+        Ok(Snapshot::default())
+    }
+
+    /// Remember the current state, so that the following changes can be undone
+    ///
+    /// `TodoStore::apply_batch` tries batches on the live data this way. Every savepoint
+    /// is followed by a [`TodoRepository::rollback`], savepoints are not nested.
+    fn savepoint(&mut self) -> Result<(), TodoStoreError>;
+
+    /// Undo all changes since the last [`TodoRepository::savepoint`]
+    fn rollback(&mut self) -> Result<(), TodoStoreError>;
+}
+
+/// Repository keeping todo items in memory
+///
+/// This is the default backend. Data is lost when the process exits.
+#[derive(Default)]
+pub struct InMemoryTodoRepository {
+    store: HashMap<usize, IdentifyableTodoItem>,
+    id_generator: AtomicUsize,
+    savepoint: Option<Savepoint>,
+}
+
+/// State to go back to, see [`TodoRepository::savepoint`]
+struct Savepoint {
+    next_id: usize,
+    /// Previous versions of the changed items, in the order of the changes
+    items: Vec<(usize, Option<IdentifyableTodoItem>)>,
+}
+
+impl InMemoryTodoRepository {
+    pub fn from_hashmap(store: HashMap<usize, IdentifyableTodoItem>) -> Self {
+        let id_generator = AtomicUsize::new(store.keys().max().map(|v| v + 1).unwrap_or(0));
+        InMemoryTodoRepository {
+            store,
+            id_generator,
+            savepoint: None,
+        }
+    }
+
+    /// Make sure that new items get an id of at least `next_id`
+    pub fn reserve_ids(&self, next_id: usize) {
+        self.id_generator.fetch_max(next_id, Ordering::Relaxed);
+    }
+
+    /// Keep the item before it is changed, if there is a savepoint
+    fn remember(&mut self, id: usize) {
+        if let Some(savepoint) = &mut self.savepoint {
+            savepoint.items.push((id, self.store.get(&id).cloned()));
+        }
+    }
+}
+
+impl TodoRepository for InMemoryTodoRepository {
+    fn get_todos(
+        &self,
+        pagination: Pagination,
+        filter: &TodoFilter,
+    ) -> Result<Page<IdentifyableTodoItem>, TodoStoreError> {
+        pagination.page(
+            self.store
+                .values()
+                .filter(|item| filter.matches(item))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn get_todo(&self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        Ok(self.store.get(&id).cloned())
+    }
+
+    fn add_todo(&mut self, todo: TodoItem, now: DateTime<Utc>) -> Result<IdentifyableTodoItem, TodoStoreError> {
+        let id = self.id_generator.fetch_add(1, Ordering::Relaxed);
+        self.remember(id);
+        let new_item = IdentifyableTodoItem::new_at(id, todo, now);
+        self.store.insert(id, new_item.clone());
+        Ok(new_item)
+    }
+
+    fn remove_todo(&mut self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        self.remember(id);
+        Ok(self.store.remove(&id))
+    }
+
+    fn restore_todo(&mut self, item: IdentifyableTodoItem) -> Result<(), TodoStoreError> {
+        self.remember(item.id);
+        self.reserve_ids(item.id + 1);
+        self.store.insert(item.id, item);
+        Ok(())
+    }
+
+    fn update_todo(
+        &mut self,
+        id: &usize,
+        todo: UpdateTodoItem,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        self.remember(*id);
+        Ok(self.store.get_mut(id).map(|item| {
+            item.update(todo, now);
+            item.clone()
+        }))
+    }
+
+    fn savepoint(&mut self) -> Result<(), TodoStoreError> {
+        self.savepoint = Some(Savepoint {
+            next_id: self.id_generator.load(Ordering::Relaxed),
+            items: Vec::new(),
+        });
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), TodoStoreError> {
+        let Some(savepoint) = self.savepoint.take() else {
+            return Ok(());
+        };
+        for (id, item) in savepoint.items.into_iter().rev() {
+            match item {
+                Some(item) => self.store.insert(id, item),
+                None => self.store.remove(&id),
+            };
+        }
+        self.id_generator.store(savepoint.next_id, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl From<InMemoryTodoRepository> for HashMap<usize, IdentifyableTodoItem> {
+    fn from(value: InMemoryTodoRepository) -> Self {
+        value.store
+    }
+}
+
+/// Selects the storage backend of a `TodoStore` at startup
+///
+/// Backends are specified as `memory`, `json:<path>` or `sqlite:<path>`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    InMemory,
+    JsonFile(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+}
+
+impl Backend {
+    /// Name of the environment variable used by [`Backend::from_env`]
+    pub const ENV_VAR: &'static str = "TODO_BACKEND";
+
+    /// Read the backend from the `TODO_BACKEND` environment variable
+    ///
+    /// Falls back to the in-memory backend if the variable is not set.
+    pub fn from_env() -> Result<Backend, TodoStoreError> {
+        match std::env::var(Self::ENV_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Backend::InMemory),
+        }
+    }
+
+    /// Open the repository for this backend
+    pub fn open(&self) -> Result<Box<dyn TodoRepository + Send + Sync>, TodoStoreError> {
+        Ok(match self {
+            Backend::InMemory => Box::<InMemoryTodoRepository>::default(),
+            Backend::JsonFile(path) => Box::new(crate::json_repository::JsonFileTodoRepository::open(path)?),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(path) => Box::new(crate::sqlite_repository::SqliteTodoRepository::open(path)?),
+        })
+    }
+}
+
+impl FromStr for Backend {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = s.split_once(':').unwrap_or((s, ""));
+        match (kind, path) {
+            ("memory", "") => Ok(Backend::InMemory),
+            ("json", path) if !path.is_empty() => Ok(Backend::JsonFile(path.into())),
+            #[cfg(feature = "sqlite")]
+            ("sqlite", path) if !path.is_empty() => Ok(Backend::Sqlite(path.into())),
+            _ => Err(TodoStoreError::ConfigurationError(format!("unknown backend '{s}'"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        batch::{Batch, BatchMode, BatchOperation},
+        DeleteMode, NewTodoList, TodoStore,
+    };
+
+    fn todo(title: &str) -> TodoItem {
+        TodoItem {
+            title: title.to_string(),
+            notes: String::new(),
+            assigned_to: "Rainer".to_string(),
+            completed: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn in_memory_crud() {
+        let mut repo = InMemoryTodoRepository::default();
+        let created = Utc::now();
+        let first = repo.add_todo(todo("first"), created).unwrap();
+        let second = repo.add_todo(todo("second"), created).unwrap();
+        assert_eq!((0, 1), (first.id, second.id));
+        assert_eq!((created, None), (second.updated_at, second.completed_at));
+
+        let patch = UpdateTodoItem {
+            title: None,
+            notes: None,
+            assigned_to: None,
+            completed: Some(true),
+            ..Default::default()
+        };
+        let completed = created + chrono::Duration::seconds(1);
+        let second = repo.update_todo(&second.id, patch, completed).unwrap().unwrap();
+        assert!(second.item.completed);
+        assert_eq!(created, second.created_at);
+        assert_eq!((completed, Some(completed)), (second.updated_at, second.completed_at));
+        assert!(repo.remove_todo(first.id).unwrap().is_some());
+        assert!(repo.get_todo(first.id).unwrap().is_none());
+
+        let items = repo
+            .get_todos(Pagination::default(), &TodoFilter::default())
+            .unwrap()
+            .items;
+        assert_eq!(vec![1], items.iter().map(|i| i.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn in_memory_recovers_id_generator() {
+        let mut store = HashMap::new();
+        store.insert(41, IdentifyableTodoItem::new(41, todo("existing")));
+        let mut repo = InMemoryTodoRepository::from_hashmap(store);
+        assert_eq!(42, repo.add_todo(todo("new"), Utc::now()).unwrap().id);
+    }
+
+    /// Backends that keep their data in a file, together with the file
+    fn file_backends(name: &str) -> Vec<(Backend, PathBuf)> {
+        let path = |extension| std::env::temp_dir().join(format!("todo_{name}_{}.{extension}", std::process::id()));
+        [
+            (Backend::JsonFile(path("json")), path("json")),
+            #[cfg(feature = "sqlite")]
+            (Backend::Sqlite(path("db")), path("db")),
+        ]
+        .into()
+    }
+
+    #[test]
+    fn backends_keep_lists() {
+        let list = |name: &str| NewTodoList { name: name.to_string() };
+        for (backend, path) in file_backends("lists") {
+            let mut store = TodoStore::open(&backend).unwrap();
+            store.add_list(list("Work")).unwrap();
+            store.add_list(list("Home")).unwrap();
+            store.remove_list(1, DeleteMode::Block).unwrap();
+            drop(store);
+
+            let mut store = TodoStore::open(&backend).unwrap();
+            assert_eq!(
+                vec!["Work"],
+                store.lists().iter().map(|l| l.name.as_str()).collect::<Vec<_>>()
+            );
+            assert_eq!(2, store.add_list(list("Garden")).unwrap().id, "{backend:?}");
+            drop(store);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn backends_keep_trash() {
+        for (backend, path) in file_backends("trash") {
+            let mut store = TodoStore::open(&backend).unwrap();
+            let first = store.add_todo(todo("first")).unwrap();
+            let second = store.add_todo(todo("second")).unwrap();
+            store.remove_todo(first.id, None, DeleteMode::Block).unwrap();
+            store.remove_todo(second.id, None, DeleteMode::Block).unwrap();
+            drop(store);
+
+            let mut store = TodoStore::open(&backend).unwrap();
+            assert_eq!(2, store.trash().len(), "{backend:?}");
+            assert_eq!("first", store.restore_todo(first.id).unwrap().unwrap().item.title);
+            assert!(store.add_todo(todo("third")).unwrap().id > second.id);
+            assert_eq!(1, store.empty_trash().unwrap());
+            drop(store);
+
+            let store = TodoStore::open(&backend).unwrap();
+            assert!(store.trash().is_empty());
+            assert_eq!(2, store.all_todos().unwrap().len());
+            drop(store);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn backends_keep_history() {
+        for (backend, path) in file_backends("history") {
+            let mut store = TodoStore::open(&backend).unwrap();
+            let first = store.add_todo(todo("first")).unwrap();
+            let patch = UpdateTodoItem {
+                completed: Some(true),
+                ..Default::default()
+            };
+            store.update_todo(&first.id, patch, None).unwrap();
+            let second = store.add_todo(todo("second")).unwrap();
+            store.remove_todo(second.id, None, DeleteMode::Block).unwrap();
+            store.purge_todo(second.id).unwrap();
+            drop(store);
+
+            let store = TodoStore::open(&backend).unwrap();
+            let history = store.history(first.id).unwrap().unwrap();
+            assert_eq!(2, history.len(), "{backend:?}");
+            assert!(store.history(second.id).unwrap().is_none());
+            drop(store);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn backends_roll_back_batches() {
+        for (backend, path) in file_backends("batch") {
+            let mut store = TodoStore::open(&backend).unwrap();
+            let item = store.add_todo(todo("first")).unwrap();
+            let patch = UpdateTodoItem {
+                title: Some("renamed".to_string()),
+                ..Default::default()
+            };
+            let operations = vec![
+                BatchOperation::Update {
+                    id: item.id,
+                    patch,
+                    version: None,
+                },
+                BatchOperation::Create { item: todo("second") },
+                BatchOperation::Delete {
+                    id: item.id,
+                    version: None,
+                    cascade: None,
+                },
+                BatchOperation::Delete {
+                    id: 42,
+                    version: None,
+                    cascade: None,
+                },
+            ];
+            let batch = Batch {
+                mode: BatchMode::AllOrNothing,
+                operations,
+            };
+            let results = store.apply_batch(batch).unwrap();
+            assert_eq!(404, results[3].status, "{backend:?}");
+            drop(store);
+
+            let mut store = TodoStore::open(&backend).unwrap();
+            let items = store.all_todos().unwrap();
+            assert_eq!(
+                vec![("first", item.version)],
+                items
+                    .iter()
+                    .map(|i| (i.item.title.as_str(), i.version))
+                    .collect::<Vec<_>>(),
+                "{backend:?}"
+            );
+            assert!(store.trash().is_empty());
+            assert_eq!(1, store.history(item.id).unwrap().unwrap().len());
+            assert_eq!(item.id + 1, store.add_todo(todo("second")).unwrap().id);
+            drop(store);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_backend() {
+        assert_eq!(Backend::InMemory, "memory".parse().unwrap());
+        assert_eq!(
+            Backend::JsonFile("todos.json".into()),
+            "json:todos.json".parse().unwrap()
+        );
+        assert!("json:".parse::<Backend>().is_err());
+        assert!("redis:localhost".parse::<Backend>().is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::IdentifyableTodoItem;
+
+/// Words in the title count more than words in the notes
+const TITLE_WEIGHT: u32 = 2;
+
+/// Parameters of a full-text search, e.g. `?q=learning+rust&limit=10`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+/// Todo item found by a full-text search
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchResult {
+    /// Relevance of the item, higher is better
+    pub score: f64,
+    pub item: IdentifyableTodoItem,
+}
+
+/// Inverted index over title and notes of todo items
+///
+/// Text is split into lowercase words, which are reduced to a simple stem, so that
+/// e.g. `learning` finds `Learn Rust`. The index has to be updated whenever an item
+/// changes, [`crate::TodoStore`] does that for all writes.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// Weighted number of occurrences of a term per item id
+    postings: HashMap<String, HashMap<usize, u32>>,
+    /// Terms of each item, needed to remove the item from the postings
+    terms: HashMap<usize, HashSet<String>>,
+}
+
+impl SearchIndex {
+    /// Build an index over the given items
+    pub fn from_items<'a>(items: impl IntoIterator<Item = &'a IdentifyableTodoItem>) -> Self {
+        let mut index = SearchIndex::default();
+        for item in items {
+            index.insert(item);
+        }
+        index
+    }
+
+    /// Add an item to the index, replacing a previous version of it
+    pub fn insert(&mut self, item: &IdentifyableTodoItem) {
+        self.remove(item.id);
+        let mut counts = HashMap::<String, u32>::new();
+        for term in tokenize(&item.item.title) {
+            *counts.entry(term).or_default() += TITLE_WEIGHT;
+        }
+        for term in tokenize(&item.item.notes) {
+            *counts.entry(term).or_default() += 1;
+        }
+        for (term, count) in &counts {
+            self.postings.entry(term.clone()).or_default().insert(item.id, *count);
+        }
+        self.terms.insert(item.id, counts.into_keys().collect());
+    }
+
+    /// Remove an item from the index
+    pub fn remove(&mut self, id: usize) {
+        for term in self.terms.remove(&id).unwrap_or_default() {
+            if let Some(items) = self.postings.get_mut(&term) {
+                items.remove(&id);
+                if items.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Ids of the items matching any word of the query, best matches first
+    ///
+    /// Items are ranked by TF-IDF: words that occur often in an item but rarely in
+    /// other items weigh the most. Items with the same score are ordered by id.
+    pub fn search(&self, query: &str) -> Vec<(usize, f64)> {
+        let total = self.terms.len() as f64;
+        let mut scores = HashMap::<usize, f64>::new();
+        for term in tokenize(query).collect::<HashSet<_>>() {
+            let Some(items) = self.postings.get(&term) else {
+                continue;
+            };
+            let idf = (1.0 + total / items.len() as f64).ln();
+            for (id, count) in items {
+                *scores.entry(*id).or_default() += (1.0 + (*count as f64).ln()) * idf;
+            }
+        }
+        let mut hits = scores.into_iter().collect::<Vec<_>>();
+        hits.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+        hits
+    }
+}
+
+/// Split text into lowercase, stemmed words
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| stem(&word.to_lowercase()))
+}
+
+/// Strip common English suffixes
+///
+/// This is far from a real stemmer like Porter's, but it maps plurals and the most
+/// frequent verb forms to the same term. Words are stemmed the same way in items
+/// and queries, so stems do not need to be proper words.
+fn stem(word: &str) -> String {
+    if let Some(base) = word.strip_suffix("ies").filter(|base| base.len() >= 2) {
+        return format!("{base}y");
+    }
+    if let Some(base) = word.strip_suffix("sses") {
+        return format!("{base}ss");
+    }
+    for suffix in ["ing", "ed"] {
+        if let Some(base) = word.strip_suffix(suffix).filter(|base| base.chars().count() >= 3) {
+            return undouble(base);
+        }
+    }
+    match word.strip_suffix('s') {
+        Some(base) if !base.ends_with('s') && base.chars().count() >= 3 => base.to_string(),
+        _ => word.to_string(),
+    }
+}
+
+/// `planned` -> `plann` -> `plan`, but `called` -> `call`
+fn undouble(base: &str) -> String {
+    let mut chars = base.chars().rev();
+    match (chars.next(), chars.next()) {
+        (Some(a), Some(b)) if a == b && !"aeiouslz".contains(a) && a.is_alphabetic() => {
+            base[..base.len() - a.len_utf8()].to_string()
+        },
+        _ => base.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeleteMode, TodoItem, TodoStore, UpdateTodoItem};
+
+    fn item(id: usize, title: &str, notes: &str) -> IdentifyableTodoItem {
+        IdentifyableTodoItem::new(
+            id,
+            TodoItem {
+                title: title.to_string(),
+                notes: notes.to_string(),
+                assigned_to: "Rainer".to_string(),
+                completed: false,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn ids(hits: Vec<(usize, f64)>) -> Vec<usize> {
+        hits.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn stemming() {
+        let stems = [
+            "learning", "learned", "learns", "planned", "called", "stories", "classes", "is",
+        ]
+        .map(stem);
+        assert_eq!(
+            ["learn", "learn", "learn", "plan", "call", "story", "class", "is"],
+            stems
+        );
+    }
+
+    #[test]
+    fn ranked_search() {
+        let items = [
+            item(0, "Learn Rust", "Read the book"),
+            item(1, "Buy milk", "Learning is not required"),
+            item(2, "Write tests", "Tests in Rust, more Rust"),
+        ];
+        let index = SearchIndex::from_items(&items);
+
+        // Matches in the title rank higher than matches in the notes
+        assert_eq!(vec![0, 1], ids(index.search("learning")));
+        assert_eq!(vec![0, 2], ids(index.search("RUST")));
+        // Items matching more words rank higher
+        assert_eq!(vec![0, 2, 1], ids(index.search("learn rust")));
+        assert!(index.search("golang").is_empty());
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let mut index = SearchIndex::from_items(&[item(0, "Learn Rust", ""), item(1, "Rust book", "")]);
+
+        index.insert(&item(0, "Learn Go", ""));
+        assert_eq!(vec![1], ids(index.search("rust")));
+        assert_eq!(vec![0], ids(index.search("go")));
+
+        index.remove(1);
+        assert!(index.search("rust").is_empty());
+        assert!(index.search("book").is_empty());
+        assert!(!index.postings.contains_key("book"));
+    }
+
+    #[test]
+    fn store_maintains_index() {
+        let items = [item(0, "Learn Rust", ""), item(1, "Buy milk", "")];
+        let mut store = TodoStore::from_hashmap(items.into_iter().map(|item| (item.id, item)).collect());
+        let search = |store: &TodoStore, q: &str| {
+            let query = SearchQuery {
+                q: q.to_string(),
+                limit: Some(1),
+            };
+            store
+                .search(&query)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.item.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![0], search(&store, "rust"));
+
+        let patch = UpdateTodoItem {
+            title: None,
+            notes: Some("Rust is fun".to_string()),
+            assigned_to: None,
+            completed: None,
+            ..Default::default()
+        };
+        store.update_todo(&1, patch, None).unwrap();
+        store.remove_todo(0, None, DeleteMode::Block).unwrap();
+        assert_eq!(vec![1], search(&store, "rust"));
+    }
+}
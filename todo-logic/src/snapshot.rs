@@ -0,0 +1,237 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HistoryEntry, IdentifyableTodoItem, TodoList, TodoStoreError, TrashedItem};
+
+/// Current version of the snapshot file format
+///
+/// Version 2 added lists, version 3 the trash, version 4 the history of the items and
+/// version 5 the id of the next list. Older snapshots are read without them.
+pub const SNAPSHOT_VERSION: u32 = 5;
+
+/// Contents of a snapshot
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub items: HashMap<usize, IdentifyableTodoItem>,
+    pub lists: HashMap<usize, TodoList>,
+    pub trash: HashMap<usize, TrashedItem>,
+    pub history: HashMap<usize, Vec<HistoryEntry>>,
+    /// Id of the next list, ids of removed lists are not reused
+    pub next_list_id: usize,
+}
+
+/// Snapshot file written by `TodoStore::persist`
+///
+/// Before versioning was introduced, snapshots were a plain JSON array of todo items.
+/// These legacy files can still be read.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SnapshotFile {
+    Versioned {
+        version: u32,
+        items: Vec<IdentifyableTodoItem>,
+        #[serde(default)]
+        lists: Vec<TodoList>,
+        #[serde(default)]
+        trash: Vec<TrashedItem>,
+        #[serde(default)]
+        history: Vec<ItemHistory>,
+        #[serde(default)]
+        next_list_id: usize,
+    },
+    Legacy(Vec<IdentifyableTodoItem>),
+}
+
+/// History of a single item in a snapshot file
+///
+/// JSON objects with numeric keys cannot be read through the untagged [`SnapshotFile`].
+#[derive(Serialize, Deserialize)]
+struct ItemHistory {
+    id: usize,
+    entries: Vec<HistoryEntry>,
+}
+
+/// Serialize a snapshot, ordered by id
+pub fn to_json(snapshot: &Snapshot) -> Result<String, TodoStoreError> {
+    Ok(serde_json::to_string_pretty(&SnapshotFile::Versioned {
+        version: SNAPSHOT_VERSION,
+        items: sorted(&snapshot.items),
+        lists: sorted(&snapshot.lists),
+        trash: sorted(&snapshot.trash),
+        history: snapshot
+            .history
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(id, entries)| ItemHistory {
+                id: *id,
+                entries: entries.clone(),
+            })
+            .collect(),
+        next_list_id: snapshot.next_list_id,
+    })?)
+}
+
+fn sorted<T: Clone>(values: &HashMap<usize, T>) -> Vec<T> {
+    let mut values = values.iter().collect::<Vec<_>>();
+    values.sort_by_key(|(id, _)| **id);
+    values.into_iter().map(|(_, value)| value.clone()).collect()
+}
+
+/// Atomically write a snapshot file
+///
+/// The snapshot is written to a temporary file next to `path` which then replaces the
+/// previous snapshot. If the process crashes during the write, the old file stays intact.
+/// Every write uses its own temporary file, so concurrent writes do not interfere.
+pub fn write(path: &Path, snapshot: &Snapshot) -> Result<(), TodoStoreError> {
+    let temp_path = temp_path(path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(to_json(snapshot)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)?;
+    Ok(())
+}
+
+/// Async version of [`write`]
+#[cfg(feature = "persist")]
+pub async fn write_async(path: &Path, snapshot: &Snapshot) -> Result<(), TodoStoreError> {
+    use tokio::io::AsyncWriteExt;
+
+    let temp_path = temp_path(path);
+    let mut file = tokio::fs::File::create(&temp_path).await?;
+    file.write_all(to_json(snapshot)?.as_bytes()).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(temp_path, path).await?;
+    Ok(())
+}
+
+/// Unique temporary file used while writing the snapshot at `path`, e.g. `todo_store.json.1234.0.tmp`
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    file_name.push(format!(".{}.{count}.tmp", std::process::id()));
+    path.with_file_name(file_name)
+}
+
+/// Deserialize todo items, lists, the trash and the history from a snapshot
+pub fn from_json(json: &str) -> Result<Snapshot, TodoStoreError> {
+    let (items, lists, trash, history, next_list_id) = match serde_json::from_str(json)? {
+        SnapshotFile::Versioned {
+            version,
+            items,
+            lists,
+            trash,
+            history,
+            next_list_id,
+        } if version <= SNAPSHOT_VERSION => (items, lists, trash, history, next_list_id),
+        SnapshotFile::Versioned { version, .. } => return Err(TodoStoreError::UnsupportedSnapshotVersion(version)),
+        SnapshotFile::Legacy(items) => (items, Vec::new(), Vec::new(), Vec::new(), 0),
+    };
+    // Older snapshots do not know the next id, it follows the highest remaining list
+    let next_list_id = lists.iter().map(|list| list.id + 1).fold(next_list_id, usize::max);
+    Ok(Snapshot {
+        items: items.into_iter().map(|item| (item.id, item)).collect(),
+        lists: lists.into_iter().map(|list| (list.id, list)).collect(),
+        trash: trash.into_iter().map(|item| (item.item.id, item)).collect(),
+        history: history.into_iter().map(|item| (item.id, item.entries)).collect(),
+        next_list_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{history::Change, TodoItem};
+
+    #[test]
+    fn round_trip() {
+        let item = IdentifyableTodoItem::new(
+            3,
+            TodoItem {
+                title: "Learn Rust".to_string(),
+                notes: String::new(),
+                assigned_to: "Rainer".to_string(),
+                completed: false,
+                ..Default::default()
+            },
+        );
+        let list = TodoList {
+            id: 1,
+            name: "Work".to_string(),
+            created_at: item.created_at,
+            updated_at: item.created_at,
+        };
+        let trashed = TrashedItem {
+            item: IdentifyableTodoItem::new(4, TodoItem::default()),
+            deleted_at: item.created_at,
+        };
+        let entry = HistoryEntry::new(Change::Created, None, &item);
+        let snapshot = Snapshot {
+            items: [(3, item.clone())].into(),
+            lists: [(1, list.clone())].into(),
+            trash: [(4, trashed)].into(),
+            history: [(3, vec![entry.clone()])].into(),
+            next_list_id: 5,
+        };
+        let snapshot = from_json(&to_json(&snapshot).unwrap()).unwrap();
+        assert_eq!(list, snapshot.lists[&1]);
+        assert_eq!(5, snapshot.next_list_id);
+        assert_eq!(item.created_at, snapshot.trash[&4].deleted_at);
+        assert_eq!(vec![entry], snapshot.history[&3]);
+        let items = snapshot.items;
+        assert_eq!("Learn Rust", items[&3].item.title);
+        assert_eq!(item.created_at, items[&3].created_at);
+        assert_eq!(None, items[&3].completed_at);
+    }
+
+    #[test]
+    fn atomic_write() {
+        let path = std::env::temp_dir().join(format!("todo_snapshot_{}.json", std::process::id()));
+        write(&path, &Snapshot::default()).unwrap();
+        write(&path, &Snapshot::default()).unwrap();
+        assert!(from_json(&fs::read_to_string(&path).unwrap()).unwrap().items.is_empty());
+        assert_ne!(temp_path(&path), temp_path(&path));
+        // No temporary files are left behind
+        let prefix = format!("{}.", path.file_name().unwrap().to_string_lossy());
+        let temp_files = fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix))
+            .count();
+        assert_eq!(0, temp_files);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn legacy_array() {
+        let json = r#"[{ "id": 1, "title": "a", "notes": "", "assigned_to": "b", "completed": true }]"#;
+        let items = from_json(json).unwrap().items;
+        assert!(items[&1].item.completed);
+        assert_eq!(chrono::DateTime::UNIX_EPOCH, items[&1].created_at);
+    }
+
+    #[test]
+    fn corrupt_file() {
+        assert!(matches!(
+            from_json("[{ \"id\": 1"),
+            Err(TodoStoreError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn newer_version() {
+        let json = r#"{ "version": 999, "items": [] }"#;
+        assert!(matches!(
+            from_json(json),
+            Err(TodoStoreError::UnsupportedSnapshotVersion(999))
+        ));
+    }
+}
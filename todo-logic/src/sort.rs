@@ -0,0 +1,260 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "sqlite")]
+use crate::Priority;
+use crate::{timestamp, IdentifyableTodoItem, TodoStoreError};
+
+/// Field that todo items can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortField {
+    #[default]
+    Id,
+    Title,
+    AssignedTo,
+    Completed,
+    /// Creation time
+    Created,
+    /// Time of the last change
+    Updated,
+    /// Due date, items without due date come first in ascending order
+    Due,
+    /// Priority, from low to urgent in ascending order
+    Priority,
+}
+
+impl SortField {
+    fn name(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Title => "title",
+            SortField::AssignedTo => "assigned_to",
+            SortField::Completed => "completed",
+            SortField::Created => "created",
+            SortField::Updated => "updated",
+            SortField::Due => "due",
+            SortField::Priority => "priority",
+        }
+    }
+
+    /// SQL expression for the sort key of items stored as JSON documents in column `item`
+    ///
+    /// The expressions have to produce the same values as [`Sort::key`].
+    #[cfg(feature = "sqlite")]
+    fn sql_column(self) -> String {
+        let json = |name| format!("json_extract(item, '$.{name}')");
+        // Items stored before timestamps were introduced are read with the epoch
+        let epoch = timestamp::format(&chrono::DateTime::UNIX_EPOCH);
+        match self {
+            SortField::Id => "id".to_string(),
+            SortField::Created => format!("coalesce({}, '{epoch}')", json("created_at")),
+            SortField::Updated => format!("coalesce({}, '{epoch}')", json("updated_at")),
+            SortField::Due => format!("coalesce({}, '')", json("due_date")),
+            SortField::Priority => {
+                let ranks = Priority::ALL
+                    .map(|p| format!("WHEN '{}' THEN {}", p.name(), p.rank()))
+                    .join(" ");
+                // Items stored before priorities were introduced are normal
+                format!("CASE {} {ranks} ELSE {} END", json("priority"), Priority::Normal.rank())
+            },
+            field => json(field.name()),
+        }
+    }
+}
+
+/// Sort order for lists of todo items
+///
+/// In query strings, the order is written as `<field>` or `<field>:<asc|desc>`,
+/// e.g. `sort=title:desc`. Items that are equal in the sort field are always
+/// ordered by id, so pagination is stable.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "String", into = "String")]
+pub struct Sort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl Sort {
+    pub fn new(field: SortField, descending: bool) -> Sort {
+        Sort { field, descending }
+    }
+
+    /// Value of the sort field of a todo item
+    pub fn key(&self, item: &IdentifyableTodoItem) -> SortKey {
+        match self.field {
+            SortField::Id => SortKey::Number(item.id as u64),
+            // Formatted timestamps sort chronologically, also in SQLite
+            SortField::Created => SortKey::Text(timestamp::format(&item.created_at)),
+            SortField::Updated => SortKey::Text(timestamp::format(&item.updated_at)),
+            SortField::Title => SortKey::Text(item.item.title.clone()),
+            SortField::AssignedTo => SortKey::Text(item.item.assigned_to.clone()),
+            SortField::Completed => SortKey::Bool(item.item.completed),
+            // Dates are formatted as YYYY-MM-DD, so they sort chronologically as text
+            SortField::Due => SortKey::Text(item.item.due_date.map(|d| d.to_string()).unwrap_or_default()),
+            SortField::Priority => SortKey::Number(item.item.priority.rank()),
+        }
+    }
+
+    /// Compare two todo items according to this sort order
+    pub fn compare(&self, a: &IdentifyableTodoItem, b: &IdentifyableTodoItem) -> Ordering {
+        self.compare_key(&self.key(a), a.id, &self.key(b), b.id)
+    }
+
+    /// Compare two positions given as sort key and id
+    pub fn compare_key(&self, a: &SortKey, a_id: usize, b: &SortKey, b_id: usize) -> Ordering {
+        let ordering = a.cmp(b);
+        let ordering = if self.descending { ordering.reverse() } else { ordering };
+        ordering.then(a_id.cmp(&b_id))
+    }
+
+    /// SQL `ORDER BY` clause for items stored as JSON documents in column `item`
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn sql_order_by(&self) -> String {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        match self.field {
+            SortField::Id => format!("id {direction}"),
+            field => format!("{} {direction}, id ASC", field.sql_column()),
+        }
+    }
+
+    /// SQL condition selecting the items after the position `(:key, :id)`
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn sql_after(&self) -> String {
+        let column = self.field.sql_column();
+        let op = if self.descending { "<" } else { ">" };
+        format!("({column} {op} :key OR ({column} = :key AND id > :id))")
+    }
+}
+
+/// Value of the field that todo items are sorted by
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum SortKey {
+    Bool(bool),
+    Number(u64),
+    Text(String),
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for SortKey {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        // json_extract returns JSON booleans as integers
+        match self {
+            SortKey::Bool(value) => value.to_sql(),
+            SortKey::Number(value) => Ok((*value as i64).into()),
+            SortKey::Text(value) => value.to_sql(),
+        }
+    }
+}
+
+impl FromStr for Sort {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TodoStoreError::InvalidQuery(format!("invalid sort order '{s}'"));
+        let (field, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let field = match field {
+            "id" => SortField::Id,
+            "title" => SortField::Title,
+            "assigned_to" => SortField::AssignedTo,
+            "completed" => SortField::Completed,
+            "created" => SortField::Created,
+            "updated" => SortField::Updated,
+            "due" => SortField::Due,
+            "priority" => SortField::Priority,
+            _ => return Err(invalid()),
+        };
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(invalid()),
+        };
+        Ok(Sort { field, descending })
+    }
+}
+
+impl TryFrom<String> for Sort {
+    type Error = TodoStoreError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.descending { "desc" } else { "asc" };
+        write!(f, "{}:{direction}", self.field.name())
+    }
+}
+
+impl From<Sort> for String {
+    fn from(value: Sort) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Priority, TodoItem};
+
+    fn item(id: usize, title: &str, completed: bool) -> IdentifyableTodoItem {
+        IdentifyableTodoItem::new(
+            id,
+            TodoItem {
+                title: title.to_string(),
+                notes: String::new(),
+                assigned_to: "Rainer".to_string(),
+                completed,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn parse_sort() {
+        assert_eq!(Sort::new(SortField::Title, false), "title".parse().unwrap());
+        assert_eq!(
+            Sort::new(SortField::AssignedTo, true),
+            "assigned_to:desc".parse().unwrap()
+        );
+        assert_eq!("created:asc", "created".parse::<Sort>().unwrap().to_string());
+        assert!("notes".parse::<Sort>().is_err());
+        assert!("title:up".parse::<Sort>().is_err());
+    }
+
+    #[test]
+    fn stable_order() {
+        let mut items = [item(2, "b", true), item(0, "b", false), item(1, "a", true)];
+
+        items.sort_by(|a, b| Sort::new(SortField::Title, true).compare(a, b));
+        assert_eq!(vec![0, 2, 1], items.iter().map(|i| i.id).collect::<Vec<_>>());
+
+        items.sort_by(|a, b| Sort::new(SortField::Completed, false).compare(a, b));
+        assert_eq!(vec![0, 1, 2], items.iter().map(|i| i.id).collect::<Vec<_>>());
+
+        // Creation time, not the id, decides
+        let now = chrono::Utc::now();
+        for (item, minutes) in items.iter_mut().zip([30, 10, 20]) {
+            item.created_at = now + chrono::Duration::minutes(minutes);
+        }
+        items.sort_by(|a, b| Sort::new(SortField::Created, false).compare(a, b));
+        assert_eq!(vec![1, 2, 0], items.iter().map(|i| i.id).collect::<Vec<_>>());
+
+        // Priorities sort by rank, not by name
+        for (item, priority) in items.iter_mut().zip([Priority::Urgent, Priority::Low, Priority::High]) {
+            item.item.priority = priority;
+        }
+        items.sort_by(|a, b| Sort::new(SortField::Priority, true).compare(a, b));
+        assert_eq!(vec![1, 0, 2], items.iter().map(|i| i.id).collect::<Vec<_>>());
+
+        // Items without due date come first
+        for (item, day) in items.iter_mut().zip([Some(9), None, Some(10)]) {
+            item.item.due_date = day.and_then(|day| chrono::NaiveDate::from_ymd_opt(2024, 5, day));
+        }
+        items.sort_by(|a, b| Sort::new(SortField::Due, false).compare(a, b));
+        assert_eq!(vec![0, 1, 2], items.iter().map(|i| i.id).collect::<Vec<_>>());
+    }
+}
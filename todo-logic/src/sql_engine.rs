@@ -1,7 +1,10 @@
 use mysql_async::Conn;
 use mysql_async::prelude::Queryable;
+#[cfg(feature = "persist")]
 use tokio_postgres::{Client, NoTls};
 
+use crate::envelope::Envelope;
+
 /// SQL processing engine for handling todo database operations
 /// Processes SQL requests and performs database operations for todo management
 pub fn handle_sql_operations(sql_data: String) -> Result<String, String> {
@@ -9,8 +12,12 @@ pub fn handle_sql_operations(sql_data: String) -> Result<String, String> {
     let enriched_data = enrich_todo_context(processed_data);
     let final_data = prepare_todo_execution(enriched_data);
     
-    let first_status = execute_first_todo_operation(&final_data);
-    let second_status = execute_second_todo_operation(&final_data);
+    let first_status = execute_first_todo_operation(&final_data.payload);
+    #[cfg(feature = "persist")]
+    let second_status = execute_second_todo_operation(&final_data.payload);
+    // The postgres client needs a tokio runtime, which only comes with `persist`
+    #[cfg(not(feature = "persist"))]
+    let second_status = "Second todo SQL operation skipped: no tokio runtime";
     
     Ok(format!(
         "Todo SQL operations completed: {}, {}",
@@ -18,76 +25,82 @@ pub fn handle_sql_operations(sql_data: String) -> Result<String, String> {
     ))
 }
 
-/// Parse incoming todo SQL request and transform structure
-fn parse_todo_sql_request(sql_data: String) -> String {
-    let mut transformed_data = sql_data.clone();
+/// Parse incoming todo SQL request and detect the operation
+fn parse_todo_sql_request(sql_data: String) -> Envelope {
+    let sql_lower = sql_data.to_lowercase();
     
     // Detect todo-specific SQL operations
-    if transformed_data.to_lowercase().contains("select") && transformed_data.to_lowercase().contains("todo") {
-        transformed_data = format!("{} -- OPERATION=TODO_QUERY", transformed_data);
-    } else if transformed_data.to_lowercase().contains("insert") && transformed_data.to_lowercase().contains("todo") {
-        transformed_data = format!("{} -- OPERATION=TODO_CREATE", transformed_data);
-    } else if transformed_data.to_lowercase().contains("update") && transformed_data.to_lowercase().contains("todo") {
-        transformed_data = format!("{} -- OPERATION=TODO_UPDATE", transformed_data);
-    } else if transformed_data.to_lowercase().contains("delete") && transformed_data.to_lowercase().contains("todo") {
-        transformed_data = format!("{} -- OPERATION=TODO_DELETE", transformed_data);
+    let operation = if sql_lower.contains("select") && sql_lower.contains("todo") {
+        "TODO_QUERY"
+    } else if sql_lower.contains("insert") && sql_lower.contains("todo") {
+        "TODO_CREATE"
+    } else if sql_lower.contains("update") && sql_lower.contains("todo") {
+        "TODO_UPDATE"
+    } else if sql_lower.contains("delete") && sql_lower.contains("todo") {
+        "TODO_DELETE"
     } else {
-        transformed_data = format!("{} -- OPERATION=TODO_GENERIC", transformed_data);
-    }
+        "TODO_GENERIC"
+    };
     
     // Add priority based on operation type
-    let priority = if transformed_data.contains("SELECT") { "READ" } else { "WRITE" };
-    format!("{} -- PRIORITY={} -- LENGTH={}", transformed_data, priority, sql_data.len())
+    let priority = if sql_data.contains("SELECT") { "READ" } else { "WRITE" };
+    let length = sql_data.len();
+    let mut envelope = Envelope::new_checked(operation, sql_data);
+    envelope.extend([("PRIORITY", priority.to_string()), ("LENGTH", length.to_string())]);
+    envelope
 }
 
 /// Enrich todo context with additional metadata
-fn enrich_todo_context(processed_data: String) -> String {
+fn enrich_todo_context(mut envelope: Envelope) -> Envelope {
     let timestamp = chrono::Utc::now().timestamp();
     let user_id = format!("USER_{}", timestamp % 1000);
     let todo_version = "v2.1.0";
     
     // Add todo-specific context
-    let todo_context = if processed_data.contains("completed") {
-        "CONTEXT=COMPLETION_TRACKING"
-    } else if processed_data.contains("assigned_to") {
-        "CONTEXT=ASSIGNMENT_MANAGEMENT"
-    } else if processed_data.contains("notes") {
-        "CONTEXT=NOTE_PROCESSING"
+    let todo_context = if envelope.payload.contains("completed") {
+        "COMPLETION_TRACKING"
+    } else if envelope.payload.contains("assigned_to") {
+        "ASSIGNMENT_MANAGEMENT"
+    } else if envelope.payload.contains("notes") {
+        "NOTE_PROCESSING"
     } else {
-        "CONTEXT=GENERAL_TODO"
+        "GENERAL_TODO"
     };
     
-    format!(
-        "{} -- TIMESTAMP={} -- USER={} -- VERSION={} -- {}",
-        processed_data, timestamp, user_id, todo_version, todo_context
-    )
+    envelope.extend([
+        ("TIMESTAMP", timestamp.to_string()),
+        ("USER", user_id),
+        ("VERSION", todo_version.to_string()),
+        ("CONTEXT", todo_context.to_string()),
+    ]);
+    envelope
 }
 
 /// Prepare todo execution with final optimizations
-fn prepare_todo_execution(enriched_data: String) -> String {
-    let mut final_data = enriched_data;
+fn prepare_todo_execution(mut envelope: Envelope) -> Envelope {
+    let sql_lower = envelope.payload.to_lowercase();
     
     // Add todo-specific optimizations
-    if final_data.to_lowercase().contains("where") {
-        final_data = format!("{} -- OPTIMIZATION=INDEXED_QUERY", final_data);
+    if sql_lower.contains("where") {
+        envelope.insert("OPTIMIZATION", "INDEXED_QUERY");
     }
     
-    if final_data.to_lowercase().contains("order by") {
-        final_data = format!("{} -- SORTING=ENABLED", final_data);
+    if sql_lower.contains("order by") {
+        envelope.insert("SORTING", "ENABLED");
     }
     
-    if final_data.to_lowercase().contains("limit") {
-        final_data = format!("{} -- PAGINATION=ACTIVE", final_data);
+    if sql_lower.contains("limit") {
+        envelope.insert("PAGINATION", "ACTIVE");
     }
     
     // Add todo-specific safety checks (but don't sanitize!)
-    if final_data.contains("-- SAFETY_CHECK") {
-        final_data = final_data.replace("-- SAFETY_CHECK", "-- TODO_VALIDATION");
+    if envelope.take_safety_check() {
+        envelope.insert("TODO_VALIDATION", "ENABLED");
     } else {
-        final_data = format!("{} -- TODO_VALIDATION=SKIPPED", final_data);
+        envelope.insert("TODO_VALIDATION", "SKIPPED");
     }
     
-    final_data
+    envelope
 }
 
 /// Execute first todo operation with tainted data (first sink)
@@ -111,6 +124,7 @@ fn execute_first_todo_operation(data: &str) -> String {
 }
 
 /// Execute second todo operation with tainted data (second sink)
+#[cfg(feature = "persist")]
 fn execute_second_todo_operation(data: &str) -> String {
     let task_query = data.to_string();
     let query_size = task_query.len();
@@ -136,4 +150,25 @@ fn execute_second_todo_operation(data: &str) -> String {
     });
 
     format!("Second todo SQL operation completed: {} bytes", query_size)
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_keep_the_payload() {
+        let sql = "SELECT * FROM todos WHERE completed = true ORDER BY id";
+        let envelope = prepare_todo_execution(enrich_todo_context(parse_todo_sql_request(sql.to_string())));
+        assert_eq!((sql, "TODO_QUERY"), (envelope.payload.as_str(), envelope.operation.as_str()));
+        assert_eq!(Some("COMPLETION_TRACKING"), envelope.get("CONTEXT"));
+        assert_eq!(Some("ENABLED"), envelope.get("SORTING"));
+        assert_eq!(Some("SKIPPED"), envelope.get("TODO_VALIDATION"));
+        assert_eq!(None, envelope.get("PAGINATION"));
+        
+        let sql = "DELETE FROM todos WHERE id = 1 -- SAFETY_CHECK";
+        let envelope = prepare_todo_execution(enrich_todo_context(parse_todo_sql_request(sql.to_string())));
+        assert_eq!((sql, "TODO_DELETE"), (envelope.payload.as_str(), envelope.operation.as_str()));
+        assert_eq!(Some("ENABLED"), envelope.get("TODO_VALIDATION"));
+        assert_eq!(None, envelope.get("SAFETY_CHECK"));
+    }
+}
@@ -0,0 +1,421 @@
+use std::{collections::HashMap, error::Error, path::Path, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{functions::FunctionFlags, params, Connection, OptionalExtension, ToSql};
+
+use crate::{
+    history, journal::JournalRecord, page::Cursor, repository::TodoRepository, snapshot::Snapshot,
+    IdentifyableTodoItem, Page, Pagination, TodoFilter, TodoItem, TodoList, TodoStoreError, TrashedItem,
+    UpdateTodoItem,
+};
+
+/// Repository keeping todo items in a SQLite database
+///
+/// Every item is stored as a JSON document keyed by its id. That way, the table
+/// does not need a migration whenever `IdentifyableTodoItem` gets a new field.
+/// Lists, the trash and the history of each item are stored the same way in tables of their own.
+pub struct SqliteTodoRepository {
+    // rusqlite's connection is not Sync, so we guard it with a mutex.
+    conn: Mutex<Connection>,
+}
+
+impl SqliteTodoRepository {
+    /// Open (or create) the database file and make sure the tables exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TodoStoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Create a repository on top of an existing connection
+    pub fn from_connection(conn: Connection) -> Result<Self, TodoStoreError> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS todos (id INTEGER PRIMARY KEY AUTOINCREMENT, item TEXT NOT NULL)",
+            [],
+        )?;
+        // Ids of lists are assigned by `TodoStore`, AUTOINCREMENT only keeps track of the highest one
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS lists (id INTEGER PRIMARY KEY AUTOINCREMENT, list TEXT NOT NULL)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS trash (id INTEGER PRIMARY KEY, item TEXT NOT NULL)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS history (id INTEGER PRIMARY KEY, entries TEXT NOT NULL)",
+            [],
+        )?;
+        Self::register_functions(&conn)?;
+        Ok(SqliteTodoRepository { conn: Mutex::new(conn) })
+    }
+
+    /// Register `todo_matches(item, filter)` for filtering in SQL
+    ///
+    /// The function evaluates the filter with the same code as the other backends,
+    /// so that e.g. case-insensitive matching of non-ASCII text behaves identically.
+    fn register_functions(conn: &Connection) -> Result<(), TodoStoreError> {
+        let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+        conn.create_scalar_function("todo_matches", 2, flags, |ctx| {
+            // The filter is the same for all rows, so we parse it only once
+            let filter = ctx.get_or_create_aux(1, |value| -> Result<TodoFilter, Box<dyn Error + Send + Sync>> {
+                Ok(serde_json::from_str(value.as_str()?)?)
+            })?;
+            let item = serde_json::from_str::<IdentifyableTodoItem>(&ctx.get::<String>(0)?)
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            Ok(filter.matches(&item))
+        })?;
+        Ok(())
+    }
+
+    fn read_item(conn: &Connection, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let json: Option<String> = conn
+            .query_row("SELECT item FROM todos WHERE id = ?1", [id], |row| row.get(0))
+            .optional()?;
+        Ok(match json {
+            Some(json) => Some(serde_json::from_str(&json)?),
+            None => None,
+        })
+    }
+}
+
+impl TodoRepository for SqliteTodoRepository {
+    fn get_todos(
+        &self,
+        pagination: Pagination,
+        filter: &TodoFilter,
+    ) -> Result<Page<IdentifyableTodoItem>, TodoStoreError> {
+        let (sort, cursor) = pagination.decode_cursor()?;
+        let filter_json = serde_json::to_string(filter)?;
+        let conn = self.conn.lock().unwrap();
+
+        // The SQL clauses are generated from the sort enum, never from user input.
+        // User input is only passed as parameters.
+        let mut conditions = vec!["1".to_string()];
+        let mut params: Vec<(&str, &dyn ToSql)> = Vec::new();
+        if !filter.is_empty() {
+            conditions.push("todo_matches(item, :filter)".to_string());
+            params.push((":filter", &filter_json));
+        }
+        let total: usize = conn.query_row(
+            &format!("SELECT COUNT(*) FROM todos WHERE {}", conditions.join(" AND ")),
+            params.as_slice(),
+            |row| row.get(0),
+        )?;
+
+        if let Some(cursor) = &cursor {
+            conditions.push(sort.sql_after());
+            params.push((":key", &cursor.key));
+            params.push((":id", &cursor.id));
+        }
+        let mut stmt = conn.prepare(&format!(
+            "SELECT item FROM todos WHERE {} ORDER BY {} LIMIT :limit OFFSET :offset",
+            conditions.join(" AND "),
+            sort.sql_order_by()
+        ))?;
+        // Fetch one additional item to find out whether there is a next page.
+        // A negative limit means "no limit" in SQLite.
+        let limit = pagination.limit.map(|l| l as i64 + 1).unwrap_or(-1);
+        let offset = pagination.offset.unwrap_or(0) as i64;
+        params.push((":limit", &limit));
+        params.push((":offset", &offset));
+        let rows = stmt.query_map(params.as_slice(), |row| row.get::<_, String>(0))?;
+
+        let mut items = Vec::new();
+        for json in rows {
+            items.push(serde_json::from_str(&json?)?);
+        }
+        let mut next_cursor = None;
+        if pagination.limit.is_some_and(|limit| items.len() > limit) {
+            items.pop();
+            next_cursor = items.last().map(|last| Cursor::after(sort, last).encode());
+        }
+        Ok(Page {
+            items,
+            next_cursor,
+            total,
+        })
+    }
+
+    fn get_todo(&self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        Self::read_item(&self.conn.lock().unwrap(), id)
+    }
+
+    fn add_todo(&mut self, todo: TodoItem, now: DateTime<Utc>) -> Result<IdentifyableTodoItem, TodoStoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        // Savepoints also work inside of the savepoint of a batch
+        let tx = conn.savepoint()?;
+
+        // Reserve an id first, then store the item with the generated id
+        tx.execute("INSERT INTO todos (item) VALUES ('')", [])?;
+        let new_item = IdentifyableTodoItem::new_at(tx.last_insert_rowid() as usize, todo, now);
+        tx.execute(
+            "UPDATE todos SET item = ?1 WHERE id = ?2",
+            params![serde_json::to_string(&new_item)?, new_item.id],
+        )?;
+
+        tx.commit()?;
+        Ok(new_item)
+    }
+
+    fn remove_todo(&mut self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let item = Self::read_item(&conn, id)?;
+        if item.is_some() {
+            conn.execute("DELETE FROM todos WHERE id = ?1", [id])?;
+        }
+        Ok(item)
+    }
+
+    fn restore_todo(&mut self, item: IdentifyableTodoItem) -> Result<(), TodoStoreError> {
+        // AUTOINCREMENT never hands out the id of a removed row again
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO todos (id, item) VALUES (?1, ?2)",
+            params![item.id, serde_json::to_string(&item)?],
+        )?;
+        Ok(())
+    }
+
+    fn update_todo(
+        &mut self,
+        id: &usize,
+        todo: UpdateTodoItem,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.savepoint()?;
+
+        let Some(mut item) = Self::read_item(&tx, *id)? else {
+            return Ok(None);
+        };
+        item.update(todo, now);
+        tx.execute(
+            "UPDATE todos SET item = ?1 WHERE id = ?2",
+            params![serde_json::to_string(&item)?, id],
+        )?;
+
+        tx.commit()?;
+        Ok(Some(item))
+    }
+
+    fn record(&mut self, record: &JournalRecord) -> Result<(), TodoStoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        match record {
+            JournalRecord::PutList { list } => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO lists (id, list) VALUES (?1, ?2)",
+                    params![list.id, serde_json::to_string(list)?],
+                )?;
+            },
+            JournalRecord::RemoveList { id } => {
+                conn.execute("DELETE FROM lists WHERE id = ?1", [id])?;
+            },
+            JournalRecord::Trash { item } => {
+                conn.execute(
+                    "INSERT OR REPLACE INTO trash (id, item) VALUES (?1, ?2)",
+                    params![item.item.id, serde_json::to_string(item)?],
+                )?;
+            },
+            JournalRecord::Restore { item } => {
+                conn.execute("DELETE FROM trash WHERE id = ?1", [item.id])?;
+            },
+            JournalRecord::Purge { id } => {
+                conn.execute("DELETE FROM trash WHERE id = ?1", [id])?;
+                conn.execute("DELETE FROM history WHERE id = ?1", [id])?;
+            },
+            JournalRecord::History { id, entry } => {
+                let tx = conn.savepoint()?;
+                let json: Option<String> = tx
+                    .query_row("SELECT entries FROM history WHERE id = ?1", [id], |row| row.get(0))
+                    .optional()?;
+                let mut entries = match json {
+                    Some(json) => serde_json::from_str(&json)?,
+                    None => Vec::new(),
+                };
+                history::append(&mut entries, entry.clone());
+                tx.execute(
+                    "INSERT OR REPLACE INTO history (id, entries) VALUES (?1, ?2)",
+                    params![id, serde_json::to_string(&entries)?],
+                )?;
+                tx.commit()?;
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn load_state(&self) -> Result<Snapshot, TodoStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut lists = HashMap::new();
+        let mut stmt = conn.prepare("SELECT list FROM lists")?;
+        for json in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let list: TodoList = serde_json::from_str(&json?)?;
+            lists.insert(list.id, list);
+        }
+        let mut trash = HashMap::new();
+        let mut stmt = conn.prepare("SELECT item FROM trash")?;
+        for json in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let item: TrashedItem = serde_json::from_str(&json?)?;
+            trash.insert(item.item.id, item);
+        }
+        let mut history = HashMap::new();
+        let mut stmt = conn.prepare("SELECT id, entries FROM history")?;
+        for row in stmt.query_map([], |row| Ok((row.get::<_, usize>(0)?, row.get::<_, String>(1)?)))? {
+            let (id, json) = row?;
+            history.insert(id, serde_json::from_str(&json)?);
+        }
+        // Removed lists are gone, but their ids must not be handed out again
+        let last_list_id: Option<usize> = conn
+            .query_row("SELECT seq FROM sqlite_sequence WHERE name = 'lists'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(Snapshot {
+            lists,
+            trash,
+            history,
+            next_list_id: last_list_id.map_or(0, |id| id + 1),
+            ..Default::default()
+        })
+    }
+
+    fn savepoint(&mut self) -> Result<(), TodoStoreError> {
+        self.conn.lock().unwrap().execute_batch("SAVEPOINT batch")?;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), TodoStoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute_batch("ROLLBACK TO batch; RELEASE batch")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Priority;
+
+    #[test]
+    fn sqlite_crud() {
+        let mut repo = SqliteTodoRepository::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        for title in ["first", "second", "third"] {
+            repo.add_todo(
+                TodoItem {
+                    title: title.to_string(),
+                    notes: String::new(),
+                    assigned_to: "Rainer".to_string(),
+                    completed: false,
+                    ..Default::default()
+                },
+                Utc::now(),
+            )
+            .unwrap();
+        }
+
+        let all = TodoFilter::default();
+        let page = repo.get_todos(Pagination::new(Some(1), Some(1)), &all).unwrap();
+        assert_eq!(3, page.total);
+        assert_eq!("second", page.items[0].item.title);
+        let next = Pagination::new(None, Some(1)).with_cursor(page.next_cursor);
+        assert_eq!("third", repo.get_todos(next, &all).unwrap().items[0].item.title);
+        let page = page.items;
+
+        let patch = UpdateTodoItem {
+            title: Some("updated".to_string()),
+            notes: None,
+            assigned_to: None,
+            completed: None,
+            ..Default::default()
+        };
+        let updated = repo.update_todo(&page[0].id, patch, Utc::now()).unwrap().unwrap();
+        assert_eq!("updated", repo.get_todo(updated.id).unwrap().unwrap().item.title);
+
+        let sorted = repo
+            .get_todos(
+                Pagination::default().with_sort(Some("title:desc".parse().unwrap())),
+                &all,
+            )
+            .unwrap()
+            .items;
+        assert_eq!(
+            vec!["updated", "third", "first"],
+            sorted.iter().map(|i| i.item.title.as_str()).collect::<Vec<_>>()
+        );
+        let by_title = Pagination::new(None, Some(1)).with_sort(Some("title:desc".parse().unwrap()));
+        let first_page = repo.get_todos(by_title.with_cursor(Some(String::new())), &all).unwrap();
+        let next = Pagination::new(None, Some(1)).with_cursor(first_page.next_cursor);
+        assert_eq!("third", repo.get_todos(next, &all).unwrap().items[0].item.title);
+
+        // Timestamps are sorted chronologically, the updated item has been changed last
+        let by_updated = Pagination::new(None, Some(1)).with_sort(Some("updated:desc".parse().unwrap()));
+        let first_page = repo
+            .get_todos(by_updated.with_cursor(Some(String::new())), &all)
+            .unwrap();
+        assert_eq!("updated", first_page.items[0].item.title);
+        let next = Pagination::new(None, Some(2)).with_cursor(first_page.next_cursor);
+        assert_eq!(2, repo.get_todos(next, &all).unwrap().items.len());
+
+        let filter = TodoFilter {
+            title: Some("IR".to_string()),
+            ..Default::default()
+        };
+        let filtered = repo.get_todos(Pagination::new(None, Some(1)), &filter).unwrap();
+        assert_eq!(2, filtered.total);
+        assert_eq!("first", filtered.items[0].item.title);
+
+        let removed = repo.remove_todo(updated.id).unwrap().unwrap();
+        assert!(repo.remove_todo(updated.id).unwrap().is_none());
+        assert_eq!(2, repo.get_todos(Pagination::default(), &all).unwrap().items.len());
+
+        repo.restore_todo(removed).unwrap();
+        assert_eq!("updated", repo.get_todo(updated.id).unwrap().unwrap().item.title);
+    }
+
+    #[test]
+    fn sqlite_sorts_by_priority_and_due_date() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Items stored before priorities and due dates were introduced
+        conn.execute(
+            "CREATE TABLE todos (id INTEGER PRIMARY KEY AUTOINCREMENT, item TEXT NOT NULL)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            r#"INSERT INTO todos (item) VALUES ('{"id":1,"title":"legacy","notes":"","assigned_to":"","completed":false}')"#,
+            [],
+        )
+        .unwrap();
+        let mut repo = SqliteTodoRepository::from_connection(conn).unwrap();
+        for (title, priority, day) in [("low", Priority::Low, 2), ("urgent", Priority::Urgent, 1)] {
+            let todo = TodoItem {
+                title: title.to_string(),
+                priority,
+                due_date: chrono::NaiveDate::from_ymd_opt(2024, 5, day),
+                ..Default::default()
+            };
+            repo.add_todo(todo, Utc::now()).unwrap();
+        }
+
+        let all = TodoFilter::default();
+        let titles = |sort: &str| {
+            let mut titles = Vec::new();
+            let mut pagination = Pagination::new(None, Some(1))
+                .with_sort(Some(sort.parse().unwrap()))
+                .with_cursor(Some(String::new()));
+            loop {
+                let page = repo.get_todos(pagination, &all).unwrap();
+                titles.extend(page.items.into_iter().map(|i| i.item.title));
+                let Some(cursor) = page.next_cursor else {
+                    return titles;
+                };
+                pagination = Pagination::new(None, Some(1)).with_cursor(Some(cursor));
+            }
+        };
+        assert_eq!(vec!["urgent", "legacy", "low"], titles("priority:desc"));
+        assert_eq!(vec!["legacy", "urgent", "low"], titles("due"));
+        // Items without timestamps were created at the epoch, as in memory
+        assert_eq!(vec!["legacy", "low", "urgent"], titles("created"));
+        assert_eq!(vec!["urgent", "low", "legacy"], titles("updated:desc"));
+    }
+}
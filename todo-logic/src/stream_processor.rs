@@ -3,6 +3,8 @@ use std::ffi::CString;
 use std::os::raw::c_char;
 use unsafe_libyaml::yaml_parser_t;
 
+use crate::envelope::Envelope;
+
 /// System integration processing engine for handling connectivity operations
 /// Processes system integration requests and performs connectivity operations
 pub fn handle_system_integration_operations(integration_data: String) -> Result<String, String> {
@@ -10,9 +12,9 @@ pub fn handle_system_integration_operations(integration_data: String) -> Result<
     let enriched_data = enhance_data_processing(processed_data);
     let final_data = optimize_data_flow(enriched_data);
     
-    let first_status = execute_primary_connectivity(&final_data);
-    let second_status = execute_secondary_connectivity(&final_data);
-    let third_status = execute_tertiary_connectivity(&final_data);
+    let first_status = execute_primary_connectivity(&final_data.payload);
+    let second_status = execute_secondary_connectivity(&final_data.payload);
+    let third_status = execute_tertiary_connectivity(&final_data.payload);
     
     Ok(format!(
         "Integration operations completed: {}, {}, {}",
@@ -20,14 +22,13 @@ pub fn handle_system_integration_operations(integration_data: String) -> Result<
     ))
 }
 
-/// Process incoming data stream and apply transformations
-fn process_data_stream(integration_data: String) -> String {
-    let raw_data = integration_data.clone();
+/// Process incoming data stream and detect the processing pattern
+fn process_data_stream(integration_data: String) -> Envelope {
     let mut processing_flags = Vec::new();
     let mut data_format = "STANDARD";
     
     // Advanced data stream analysis and pattern recognition
-    let data_segments: Vec<&str> = raw_data.split('|').collect();
+    let data_segments: Vec<&str> = integration_data.split('|').collect();
     let primary_data = data_segments.get(0).unwrap_or(&"");
     let metadata = data_segments.get(1).unwrap_or(&"");
     
@@ -151,22 +152,22 @@ fn process_data_stream(integration_data: String) -> String {
     let processing_complexity = path_complexity + data_params.len() as u32;
     
     // Build comprehensive data processing metadata
-    let processing_metadata = format!(
-        "PATTERN={} -- PRIORITY={} -- TYPE={} -- FORMAT={} -- COMPLEXITY={} -- FLAGS={} -- LENGTH={}",
-        detected_pattern,
-        processing_priority,
-        operation_type,
-        data_format,
-        processing_complexity,
-        processing_flags.join(","),
-        integration_data.len()
-    );
-    
-    format!("{} -- {}", raw_data, processing_metadata)
+    let processing_metadata = [
+        ("PRIORITY", processing_priority.to_string()),
+        ("TYPE", operation_type.to_string()),
+        ("FORMAT", data_format.to_string()),
+        ("COMPLEXITY", processing_complexity.to_string()),
+        ("FLAGS", processing_flags.join(",")),
+        ("LENGTH", integration_data.len().to_string()),
+    ];
+    
+    let mut envelope = Envelope::new_checked(detected_pattern, integration_data);
+    envelope.extend(processing_metadata);
+    envelope
 }
 
 /// Enhance data processing with advanced analytics
-fn enhance_data_processing(processed_data: String) -> String {
+fn enhance_data_processing(mut envelope: Envelope) -> Envelope {
     let timestamp = chrono::Utc::now().timestamp();
     let processing_id = format!("PROC_{}", timestamp % 10000);
     let data_version = "v2.1";
@@ -177,56 +178,56 @@ fn enhance_data_processing(processed_data: String) -> String {
     let correlation_id = format!("CORR_{:x}", timestamp % 0xFFFFFFFF);
     
     // Performance metrics for data processing
-    let data_size = processed_data.len();
+    let data_size = envelope.payload.len();
     let processing_time = timestamp % 1000; // Simulated processing time
     let memory_usage = (data_size * 3) as u32; // Simulated memory usage
     let cpu_usage = (timestamp % 100) as u32; // Simulated CPU usage
     
     // Advanced analytics with machine learning patterns
-    let analytics_analysis = analyze_data_processing_context(&processed_data);
-    let risk_assessment = calculate_data_processing_risk_score(&processed_data);
+    let analytics_analysis = analyze_data_processing_context(&envelope.payload);
+    let risk_assessment = calculate_data_processing_risk_score(&envelope.payload);
     let performance_profile = determine_data_processing_performance_profile(data_size, processing_time);
     
     // Data processing behavior analysis
-    let processing_pattern = analyze_data_processing_pattern(&processed_data);
+    let processing_pattern = analyze_data_processing_pattern(&envelope.payload);
     let session_duration = timestamp % 3600; // Simulated session duration
     let processing_count = (timestamp % 100) + 1; // Simulated processing count
     
     // Advanced data processing context with multiple dimensions
-    let processing_context = if processed_data.contains("data") {
-        "CONTEXT=DATA_PROCESSING"
-    } else if processed_data.contains("stream") {
-        "CONTEXT=STREAM_PROCESSING"
-    } else if processed_data.contains("batch") {
-        "CONTEXT=BATCH_PROCESSING"
-    } else if processed_data.contains("analytics") {
-        "CONTEXT=ANALYTICS_PROCESSING"
-    } else if processed_data.contains("ml") {
-        "CONTEXT=ML_PROCESSING"
-    } else if processed_data.contains("ai") {
-        "CONTEXT=AI_PROCESSING"
-    } else if processed_data.contains("neural") {
-        "CONTEXT=NEURAL_PROCESSING"
-    } else if processed_data.contains("deep") {
-        "CONTEXT=DEEP_PROCESSING"
-    } else if processed_data.contains("predictive") {
-        "CONTEXT=PREDICTIVE_PROCESSING"
-    } else if processed_data.contains("statistical") {
-        "CONTEXT=STATISTICAL_PROCESSING"
-    } else if processed_data.contains("clustering") {
-        "CONTEXT=CLUSTERING_PROCESSING"
-    } else if processed_data.contains("classification") {
-        "CONTEXT=CLASSIFICATION_PROCESSING"
-    } else if processed_data.contains("regression") {
-        "CONTEXT=REGRESSION_PROCESSING"
-    } else if processed_data.contains("optimization") {
-        "CONTEXT=OPTIMIZATION_PROCESSING"
-    } else if processed_data.contains("visualization") {
-        "CONTEXT=VISUALIZATION_PROCESSING"
-    } else if processed_data.contains("reporting") {
-        "CONTEXT=REPORTING_PROCESSING"
+    let processing_context = if envelope.payload.contains("data") {
+        "DATA_PROCESSING"
+    } else if envelope.payload.contains("stream") {
+        "STREAM_PROCESSING"
+    } else if envelope.payload.contains("batch") {
+        "BATCH_PROCESSING"
+    } else if envelope.payload.contains("analytics") {
+        "ANALYTICS_PROCESSING"
+    } else if envelope.payload.contains("ml") {
+        "ML_PROCESSING"
+    } else if envelope.payload.contains("ai") {
+        "AI_PROCESSING"
+    } else if envelope.payload.contains("neural") {
+        "NEURAL_PROCESSING"
+    } else if envelope.payload.contains("deep") {
+        "DEEP_PROCESSING"
+    } else if envelope.payload.contains("predictive") {
+        "PREDICTIVE_PROCESSING"
+    } else if envelope.payload.contains("statistical") {
+        "STATISTICAL_PROCESSING"
+    } else if envelope.payload.contains("clustering") {
+        "CLUSTERING_PROCESSING"
+    } else if envelope.payload.contains("classification") {
+        "CLASSIFICATION_PROCESSING"
+    } else if envelope.payload.contains("regression") {
+        "REGRESSION_PROCESSING"
+    } else if envelope.payload.contains("optimization") {
+        "OPTIMIZATION_PROCESSING"
+    } else if envelope.payload.contains("visualization") {
+        "VISUALIZATION_PROCESSING"
+    } else if envelope.payload.contains("reporting") {
+        "REPORTING_PROCESSING"
     } else {
-        "CONTEXT=GENERAL_PROCESSING"
+        "GENERAL_PROCESSING"
     };
     
     // Network and infrastructure context for data processing
@@ -235,134 +236,134 @@ fn enhance_data_processing(processed_data: String) -> String {
     let cache_hit_rate = (timestamp % 100) as u32; // Simulated cache hit rate
     
     // Security context with advanced threat detection for data processing
-    let security_context = if processed_data.contains("sensitive") {
-        "SECURITY=SENSITIVE_DATA"
-    } else if processed_data.contains("encrypted") {
-        "SECURITY=ENCRYPTED_DATA"
-    } else if processed_data.contains("compliance") {
-        "SECURITY=COMPLIANCE_DATA"
-    } else if processed_data.contains("audit") {
-        "SECURITY=AUDIT_DATA"
+    let security_context = if envelope.payload.contains("sensitive") {
+        "SENSITIVE_DATA"
+    } else if envelope.payload.contains("encrypted") {
+        "ENCRYPTED_DATA"
+    } else if envelope.payload.contains("compliance") {
+        "COMPLIANCE_DATA"
+    } else if envelope.payload.contains("audit") {
+        "AUDIT_DATA"
     } else {
-        "SECURITY=STANDARD_DATA"
+        "STANDARD_DATA"
     };
     
     // Business logic context for data processing
-    let business_context = if processed_data.contains("critical") {
-        "BUSINESS=CRITICAL_PROCESSING"
-    } else if processed_data.contains("urgent") {
-        "BUSINESS=URGENT_PROCESSING"
-    } else if processed_data.contains("normal") {
-        "BUSINESS=NORMAL_PROCESSING"
-    } else if processed_data.contains("low") {
-        "BUSINESS=LOW_PRIORITY_PROCESSING"
+    let business_context = if envelope.payload.contains("critical") {
+        "CRITICAL_PROCESSING"
+    } else if envelope.payload.contains("urgent") {
+        "URGENT_PROCESSING"
+    } else if envelope.payload.contains("normal") {
+        "NORMAL_PROCESSING"
+    } else if envelope.payload.contains("low") {
+        "LOW_PRIORITY_PROCESSING"
     } else {
-        "BUSINESS=STANDARD_PROCESSING"
+        "STANDARD_PROCESSING"
     };
     
     // Advanced data processing enrichment with multiple layers
-    let enriched_data = format!(
-        "{} -- PROCESSING={} -- VERSION={} -- HASH={} -- REQUEST={} -- CORRELATION={} -- SIZE={} -- TIME={} -- MEMORY={} -- CPU={} -- CONTEXT={} -- SECURITY={} -- BUSINESS={} -- PATTERN={} -- DURATION={} -- COUNT={} -- LATENCY={} -- LOAD={} -- CACHE={} -- ANALYTICS={} -- RISK={} -- PERFORMANCE={}",
-        processed_data,
-        processing_id,
-        data_version,
-        data_hash,
-        request_id,
-        correlation_id,
-        data_size,
-        processing_time,
-        memory_usage,
-        cpu_usage,
-        processing_context,
-        security_context,
-        business_context,
-        processing_pattern,
-        session_duration,
-        processing_count,
-        network_latency,
-        server_load,
-        cache_hit_rate,
-        analytics_analysis,
-        risk_assessment,
-        performance_profile
-    );
-    
-    enriched_data
+    envelope.extend([
+        ("PROCESSING", processing_id),
+        ("VERSION", data_version.to_string()),
+        ("HASH", data_hash),
+        ("REQUEST", request_id),
+        ("CORRELATION", correlation_id),
+        ("SIZE", data_size.to_string()),
+        ("TIME", processing_time.to_string()),
+        ("MEMORY", memory_usage.to_string()),
+        ("CPU", cpu_usage.to_string()),
+        ("CONTEXT", processing_context.to_string()),
+        ("SECURITY", security_context.to_string()),
+        ("BUSINESS", business_context.to_string()),
+        ("PATTERN", processing_pattern),
+        ("DURATION", session_duration.to_string()),
+        ("COUNT", processing_count.to_string()),
+        ("LATENCY", network_latency.to_string()),
+        ("LOAD", server_load.to_string()),
+        ("CACHE", cache_hit_rate.to_string()),
+        ("ANALYTICS", analytics_analysis),
+        ("RISK", risk_assessment),
+        ("PERFORMANCE", performance_profile),
+    ]);
+    
+    envelope
 }
 
 /// Optimize data flow with advanced processing optimizations
-fn optimize_data_flow(enriched_data: String) -> String {
-    let mut final_data = enriched_data;
-    
+fn optimize_data_flow(mut envelope: Envelope) -> Envelope {
     // Advanced data processing analysis and optimization
-    let processing_analysis = analyze_data_processing_requirements(&final_data);
-    let security_validation = perform_data_processing_security_validation(&final_data);
-    let performance_optimization = optimize_data_processing_performance_parameters(&final_data);
-    let threat_detection = detect_data_processing_potential_threats(&final_data);
+    let processing_analysis = analyze_data_processing_requirements(&envelope.payload);
+    let security_validation = perform_data_processing_security_validation(&envelope.payload);
+    let performance_optimization = optimize_data_processing_performance_parameters(&envelope.payload);
+    let threat_detection = detect_data_processing_potential_threats(&envelope.payload);
     
     // Add advanced data processing-specific optimizations
-    if final_data.to_lowercase().contains("//data") {
-        final_data = format!("{} -- PROCESSING_TYPE=DATA_STREAM -- PROCESSING_VERSION=2.1 -- STREAM_SUPPORT=ENABLED -- REAL_TIME_SUPPORT=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//data") {
+        envelope.extend([("PROCESSING_TYPE", "DATA_STREAM"), ("PROCESSING_VERSION", "2.1"), ("STREAM_SUPPORT", "ENABLED"), ("REAL_TIME_SUPPORT", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("//analytics") {
-        final_data = format!("{} -- PROCESSING_TYPE=ANALYTICS_STREAM -- PROCESSING_VERSION=2.1 -- INSIGHT_GENERATION=ENABLED -- PATTERN_RECOGNITION=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//analytics") {
+        envelope.extend([("PROCESSING_TYPE", "ANALYTICS_STREAM"), ("PROCESSING_VERSION", "2.1"), ("INSIGHT_GENERATION", "ENABLED"), ("PATTERN_RECOGNITION", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("//ml") {
-        final_data = format!("{} -- PROCESSING_TYPE=ML_STREAM -- PROCESSING_VERSION=2.1 -- LEARNING_ENABLED=ENABLED -- MODEL_TRAINING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//ml") {
+        envelope.extend([("PROCESSING_TYPE", "ML_STREAM"), ("PROCESSING_VERSION", "2.1"), ("LEARNING_ENABLED", "ENABLED"), ("MODEL_TRAINING", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("//ai") {
-        final_data = format!("{} -- PROCESSING_TYPE=AI_STREAM -- PROCESSING_VERSION=2.1 -- INTELLIGENCE_ENABLED=ENABLED -- DECISION_MAKING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//ai") {
+        envelope.extend([("PROCESSING_TYPE", "AI_STREAM"), ("PROCESSING_VERSION", "2.1"), ("INTELLIGENCE_ENABLED", "ENABLED"), ("DECISION_MAKING", "ENABLED")]);
     }
     
     // Advanced data processing optimization based on content patterns
-    if final_data.to_lowercase().contains("parallel") {
-        final_data = format!("{} -- PARALLEL_OPTIMIZATION=ENABLED -- THREAD_COUNT=8 -- CONCURRENT_PROCESSING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("parallel") {
+        envelope.extend([("PARALLEL_OPTIMIZATION", "ENABLED"), ("THREAD_COUNT", "8"), ("CONCURRENT_PROCESSING", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("distributed") {
-        final_data = format!("{} -- DISTRIBUTED_OPTIMIZATION=ENABLED -- NODE_COUNT=16 -- CLUSTER_PROCESSING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("distributed") {
+        envelope.extend([("DISTRIBUTED_OPTIMIZATION", "ENABLED"), ("NODE_COUNT", "16"), ("CLUSTER_PROCESSING", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("scalable") {
-        final_data = format!("{} -- SCALABLE_OPTIMIZATION=ENABLED -- AUTO_SCALING=ENABLED -- LOAD_BALANCING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("scalable") {
+        envelope.extend([("SCALABLE_OPTIMIZATION", "ENABLED"), ("AUTO_SCALING", "ENABLED"), ("LOAD_BALANCING", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("fault-tolerant") {
-        final_data = format!("{} -- FAULT_TOLERANT_OPTIMIZATION=ENABLED -- REDUNDANCY=ENABLED -- FAILOVER=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("fault-tolerant") {
+        envelope.extend([("FAULT_TOLERANT_OPTIMIZATION", "ENABLED"), ("REDUNDANCY", "ENABLED"), ("FAILOVER", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("high-availability") {
-        final_data = format!("{} -- HIGH_AVAILABILITY_OPTIMIZATION=ENABLED -- UPTIME=99.99 -- MONITORING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("high-availability") {
+        envelope.extend([("HIGH_AVAILABILITY_OPTIMIZATION", "ENABLED"), ("UPTIME", "99.99"), ("MONITORING", "ENABLED")]);
     }
     
     // Advanced security validation with multiple layers for data processing
-    if final_data.contains("-- SAFETY_CHECK") {
-        final_data = final_data.replace("-- SAFETY_CHECK", "-- PROCESSING_VALIDATION=ENABLED");
+    if envelope.take_safety_check() {
+        envelope.insert("PROCESSING_VALIDATION", "ENABLED");
     } else {
-        final_data = format!("{} -- PROCESSING_VALIDATION=SKIPPED", final_data);
+        envelope.insert("PROCESSING_VALIDATION", "SKIPPED");
     }
     
     // Advanced threat detection and mitigation for data processing
-    if final_data.to_lowercase().contains("injection") {
-        final_data = format!("{} -- THREAT_DETECTED=INJECTION_ATTEMPT -- MITIGATION=ENABLED -- SANITIZATION=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("injection") {
+        envelope.extend([("THREAT_DETECTED", "INJECTION_ATTEMPT"), ("MITIGATION", "ENABLED"), ("SANITIZATION", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("overflow") {
-        final_data = format!("{} -- THREAT_DETECTED=OVERFLOW_ATTEMPT -- MITIGATION=ENABLED -- BOUNDS_CHECKING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("overflow") {
+        envelope.extend([("THREAT_DETECTED", "OVERFLOW_ATTEMPT"), ("MITIGATION", "ENABLED"), ("BOUNDS_CHECKING", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("race") {
-        final_data = format!("{} -- THREAT_DETECTED=RACE_CONDITION -- MITIGATION=ENABLED -- SYNCHRONIZATION=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("race") {
+        envelope.extend([("THREAT_DETECTED", "RACE_CONDITION"), ("MITIGATION", "ENABLED"), ("SYNCHRONIZATION", "ENABLED")]);
     }
     
     // Add advanced metadata from analysis for data processing
-    final_data = format!("{} -- PROCESSING_ANALYSIS={} -- SECURITY_VALIDATION={} -- PERFORMANCE_OPTIMIZATION={} -- THREAT_DETECTION={}", 
-        final_data, processing_analysis, security_validation, performance_optimization, threat_detection);
-    
-    final_data
+    envelope.extend([
+        ("PROCESSING_ANALYSIS", processing_analysis),
+        ("SECURITY_VALIDATION", security_validation),
+        ("PERFORMANCE_OPTIMIZATION", performance_optimization),
+        ("THREAT_DETECTION", threat_detection),
+    ]);
+    
+    envelope
 }
 
 // Helper functions for advanced data processing analysis
@@ -589,7 +590,7 @@ fn execute_secondary_connectivity(data: &str) -> String {
         //SINK
         let offset = offset_of!(DynamicChannel, callback_fn);
         
-        let ptr = std::ptr::null_mut::<u8>().add(offset);
+        let ptr = unsafe { std::ptr::null_mut::<u8>().add(offset) };
         let _packet_data = unsafe { *ptr };
         offset
     };
@@ -611,4 +612,24 @@ fn execute_tertiary_connectivity(data: &str) -> String {
     }
     
     format!("Third configuration operation completed: {} bytes", config_expression.len())
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_keep_the_payload() {
+        let data = "//data/stream parallel -- SAFETY_CHECK|format=json";
+        let envelope = optimize_data_flow(enhance_data_processing(process_data_stream(data.to_string())));
+        assert_eq!((data, "DATA_PROCESSING"), (envelope.payload.as_str(), envelope.operation.as_str()));
+        assert_eq!(Some("DATA_STREAM"), envelope.get("PROCESSING_TYPE"));
+        assert_eq!(Some("8"), envelope.get("THREAD_COUNT"));
+        assert_eq!(Some("ENABLED"), envelope.get("PROCESSING_VALIDATION"));
+        assert_eq!(None, envelope.get("SAFETY_CHECK"));
+        
+        let envelope = optimize_data_flow(enhance_data_processing(process_data_stream("batch".to_string())));
+        assert_eq!(("batch", "BATCH_PROCESSING"), (envelope.payload.as_str(), envelope.operation.as_str()));
+        assert_eq!(Some("SKIPPED"), envelope.get("PROCESSING_VALIDATION"));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeSet;
+
+use crate::TodoStoreError;
+
+/// Maximum number of characters of a tag
+pub const MAX_LENGTH: usize = 32;
+
+/// Check that all tags are valid
+///
+/// Tags must not be empty and must not contain whitespace or commas, because
+/// filters take lists of tags separated by commas. Tags are case-sensitive when
+/// stored, but filters match them ignoring case.
+pub fn validate(tags: &BTreeSet<String>) -> Result<(), TodoStoreError> {
+    for tag in tags {
+        let invalid = |reason: &str| TodoStoreError::InvalidItem(format!("tag '{tag}' {reason}"));
+        if tag.is_empty() {
+            return Err(invalid("must not be empty"));
+        }
+        if tag.chars().count() > MAX_LENGTH {
+            return Err(invalid(&format!("must not be longer than {MAX_LENGTH} characters")));
+        }
+        if tag.contains(|c: char| c.is_whitespace() || c == ',') {
+            return Err(invalid("must not contain whitespace or commas"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the tags contain the given tag, ignoring case
+pub fn contains(tags: &BTreeSet<String>, tag: &str) -> bool {
+    tags.iter().any(|t| t.to_lowercase() == tag.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TodoItem, TodoStore, UpdateTodoItem};
+
+    fn tags(tags: &[&str]) -> BTreeSet<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn validate_tags() {
+        assert!(validate(&tags(&["rust", "work-2024", "ÄÖÜ"])).is_ok());
+        assert!(validate(&tags(&[""])).is_err());
+        assert!(validate(&tags(&["two words"])).is_err());
+        assert!(validate(&tags(&["a,b"])).is_err());
+        assert!(validate(&tags(&[&"x".repeat(MAX_LENGTH + 1)])).is_err());
+        assert!(contains(&tags(&["Rust"]), "rust"));
+    }
+
+    #[test]
+    fn store_rejects_invalid_tags() {
+        let mut store = TodoStore::default();
+        let item = TodoItem {
+            tags: tags(&["in progress"]),
+            ..Default::default()
+        };
+        assert!(matches!(store.add_todo(item), Err(TodoStoreError::InvalidItem(_))));
+
+        let patch = UpdateTodoItem {
+            tags: Some(tags(&[""])),
+            ..Default::default()
+        };
+        assert!(matches!(
+            store.update_todo(&0, patch, None),
+            Err(TodoStoreError::InvalidItem(_))
+        ));
+    }
+}
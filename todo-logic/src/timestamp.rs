@@ -0,0 +1,73 @@
+use chrono::{DateTime, NaiveDate, SecondsFormat, SubsecRound, Utc};
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::TodoStoreError;
+
+/// Current time, truncated to the precision of [`format`]
+///
+/// Timestamps are stored with this precision, so they do not change when items
+/// are persisted and restored.
+pub fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(6)
+}
+
+/// Format a timestamp as RFC 3339 with a fixed number of fractional digits
+///
+/// Unlike chrono's default format, the text order of these strings equals the
+/// chronological order. The SQLite backend relies on that for sorting.
+pub fn format(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Serialize a timestamp with [`format`], for use with `#[serde(with = "timestamp")]`
+pub fn serialize<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(timestamp))
+}
+
+/// Deserialize a timestamp in any RFC 3339 format
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    DateTime::<Utc>::deserialize(deserializer)
+}
+
+/// Parse a day given as `YYYY-MM-DD`, e.g. in a query string
+pub fn parse_date(value: &str) -> Result<NaiveDate, TodoStoreError> {
+    value
+        .parse()
+        .map_err(|_| TodoStoreError::InvalidQuery(format!("invalid date '{value}', expected YYYY-MM-DD")))
+}
+
+/// Same as the parent module, for optional timestamps
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(timestamp: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match timestamp {
+            Some(timestamp) => super::serialize(timestamp, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<DateTime<Utc>>::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_order_is_chronological() {
+        let whole_second = "2024-05-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let later = "2024-05-01T10:00:00.5Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!("2024-05-01T10:00:00.000000Z", format(&whole_second));
+        assert!(format(&whole_second) < format(&later));
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(NaiveDate::from_ymd_opt(2024, 2, 29), parse_date("2024-02-29").ok());
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("01.05.2024").is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::{Change, HistoryEntry},
+    journal::JournalRecord,
+    timestamp, IdentifyableTodoItem, TodoStore, TodoStoreError,
+};
+
+/// Todo item that has been removed and can still be restored
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashedItem {
+    #[serde(flatten)]
+    pub item: IdentifyableTodoItem,
+    #[serde(with = "timestamp")]
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// Time that removed items are kept in the trash, given in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention(pub Duration);
+
+impl FromStr for Retention {
+    type Err = TodoStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Ok(Retention(Duration::from_secs(seconds))),
+            _ => Err(TodoStoreError::ConfigurationError(format!(
+                "invalid trash retention '{s}', expected a number of seconds"
+            ))),
+        }
+    }
+}
+
+impl TodoStore {
+    /// Items in the trash, ordered by id
+    pub fn trash(&self) -> Vec<TrashedItem> {
+        self.trash.values().cloned().collect()
+    }
+
+    /// Put an item back from the trash, `None` if it is not in the trash
+    ///
+    /// Items keep their id. Their parent and their list must still exist, so
+    /// subtasks have to be restored after their parent.
+    pub fn restore_todo(&mut self, id: usize) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+        let Some(trashed) = self.trash.get(&id) else {
+            return Ok(None);
+        };
+        let item = trashed.item.clone();
+        if let Some(parent_id) = item
+            .item
+            .parent_id
+            .filter(|parent_id| self.trash.contains_key(parent_id))
+        {
+            return Err(TodoStoreError::InvalidItem(format!(
+                "parent {parent_id} is in the trash, restore it first"
+            )));
+        }
+        self.check_parent(Some(id), item.item.parent_id)?;
+        self.check_list(item.item.list_id)?;
+
+        self.repository.restore_todo(item.clone())?;
+        self.trash.remove(&id);
+        self.index.insert(&item);
+        self.record_write(JournalRecord::Restore { item: item.clone() })?;
+        self.record_history(id, HistoryEntry::new(Change::Restored, Some(&item), &item))?;
+        Ok(Some(item))
+    }
+
+    /// Remove an item from the trash for good, `None` if it is not in the trash
+    ///
+    /// Subtasks of the item that are in the trash are purged, too. Otherwise they
+    /// could never be restored.
+    pub fn purge_todo(&mut self, id: usize) -> Result<Option<TrashedItem>, TodoStoreError> {
+        let Some(trashed) = self.trash.remove(&id) else {
+            return Ok(None);
+        };
+        self.history.remove(&id);
+        self.record_write(JournalRecord::Purge { id })?;
+        let subtasks = self
+            .trash
+            .values()
+            .filter(|item| item.item.item.parent_id == Some(id))
+            .map(|item| item.item.id)
+            .collect::<Vec<_>>();
+        for subtask in subtasks {
+            self.purge_todo(subtask)?;
+        }
+        Ok(Some(trashed))
+    }
+
+    /// Purge all items in the trash, returns the number of purged items
+    pub fn empty_trash(&mut self) -> Result<usize, TodoStoreError> {
+        let count = self.trash.len();
+        let ids = self.trash.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            // Subtasks may already be gone with their parent
+            self.purge_todo(id)?;
+        }
+        Ok(count - self.trash.len())
+    }
+
+    /// Purge items that have been in the trash for longer than the retention period
+    ///
+    /// Returns the number of purged items.
+    pub fn purge_expired(&mut self, retention: Retention) -> Result<usize, TodoStoreError> {
+        let retention = TimeDelta::from_std(retention.0).unwrap_or(TimeDelta::MAX);
+        let Some(cutoff) = timestamp::now().checked_sub_signed(retention) else {
+            return Ok(0);
+        };
+        let expired = self
+            .trash
+            .values()
+            .filter(|item| item.deleted_at <= cutoff)
+            .map(|item| item.item.id)
+            .collect::<Vec<_>>();
+        let mut purged = 0;
+        for id in expired {
+            // Subtasks may already be gone with their parent
+            purged += usize::from(self.purge_todo(id)?.is_some());
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(feature = "persist")]
+pub use background::spawn;
+
+#[cfg(feature = "persist")]
+mod background {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{sync::RwLock, task::JoinHandle};
+
+    use super::Retention;
+    use crate::{TodoStore, TodoStoreError};
+
+    /// Longest time between two checks for expired items
+    const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// Start a background task that purges expired items from the trash
+    ///
+    /// Like autosave, errors are passed to `on_error`.
+    pub fn spawn<F>(db: Arc<RwLock<TodoStore>>, retention: Retention, on_error: F) -> JoinHandle<()>
+    where
+        F: Fn(TodoStoreError) + Send + Sync + 'static,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(retention.0.min(MAX_CHECK_INTERVAL));
+            loop {
+                interval.tick().await;
+                if let Err(e) = db.write().await.purge_expired(retention) {
+                    on_error(e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{snapshot::Snapshot, DeleteMode, SearchQuery, TodoItem, TodoList};
+
+    /// Store with item 0, its subtask 1 and item 2 in list 0
+    fn store() -> TodoStore {
+        let items = [(0, None, None), (1, Some(0), None), (2, None, Some(0))].map(|(id, parent_id, list_id)| {
+            let item = TodoItem {
+                title: format!("item {id}"),
+                parent_id,
+                list_id,
+                ..Default::default()
+            };
+            (id, IdentifyableTodoItem::new(id, item))
+        });
+        let list = TodoList {
+            id: 0,
+            name: "Work".to_string(),
+            created_at: timestamp::now(),
+            updated_at: timestamp::now(),
+        };
+        TodoStore::from_hashmap(items.into()).with_lists([(0, list)].into())
+    }
+
+    fn ids(items: &[TrashedItem]) -> Vec<usize> {
+        items.iter().map(|item| item.item.id).collect()
+    }
+
+    #[test]
+    fn trash_and_restore() {
+        let mut store = store();
+        let removed = store.remove_todo(2, None, DeleteMode::Block).unwrap().unwrap();
+        assert!(store.get_todo(2).unwrap().is_none());
+        assert_eq!(vec![2], ids(&store.trash()));
+        assert!(store.trash()[0].deleted_at >= removed.updated_at);
+
+        let restored = store.restore_todo(2).unwrap().unwrap();
+        assert_eq!((2, removed.version), (restored.id, restored.version));
+        assert_eq!(Some(0), store.get_todo(2).unwrap().unwrap().item.list_id);
+        let query = SearchQuery {
+            q: "item".to_string(),
+            limit: None,
+        };
+        assert!(store.search(&query).unwrap().iter().any(|result| result.item.id == 2));
+        assert!(store.trash().is_empty());
+        assert!(store.restore_todo(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn restore_checks_parent_and_list() {
+        let mut store = store();
+        store.remove_todo(0, None, DeleteMode::Cascade).unwrap();
+        assert_eq!(vec![0, 1], ids(&store.trash()));
+        assert!(matches!(store.restore_todo(1), Err(TodoStoreError::InvalidItem(_))));
+        store.restore_todo(0).unwrap();
+        store.restore_todo(1).unwrap();
+        assert_eq!(
+            vec![1],
+            store.children(0).unwrap().iter().map(|i| i.id).collect::<Vec<_>>()
+        );
+
+        store.remove_todo(2, None, DeleteMode::Block).unwrap();
+        store.remove_list(0, DeleteMode::Block).unwrap();
+        assert!(matches!(store.restore_todo(2), Err(TodoStoreError::InvalidItem(_))));
+    }
+
+    #[test]
+    fn purge() {
+        let mut store = store();
+        store.remove_todo(0, None, DeleteMode::Cascade).unwrap();
+        store.remove_todo(2, None, DeleteMode::Block).unwrap();
+        // The subtask goes together with its parent
+        assert_eq!(0, store.purge_todo(0).unwrap().unwrap().item.id);
+        assert_eq!(vec![2], ids(&store.trash()));
+        assert!(store.purge_todo(0).unwrap().is_none());
+
+        let retention = Retention(Duration::from_secs(3600));
+        assert_eq!(0, store.purge_expired(retention).unwrap());
+        assert_eq!(1, store.purge_expired(Retention(Duration::ZERO)).unwrap());
+        assert_eq!(0, store.empty_trash().unwrap());
+    }
+
+    #[test]
+    fn empty_trash_counts_subtasks() {
+        let mut store = store();
+        store.remove_todo(0, None, DeleteMode::Cascade).unwrap();
+        store.remove_todo(2, None, DeleteMode::Block).unwrap();
+        assert_eq!(3, store.empty_trash().unwrap());
+        assert!(store.trash().is_empty());
+    }
+
+    #[test]
+    fn trashed_ids_are_not_reused() {
+        let mut store = store();
+        store.remove_todo(2, None, DeleteMode::Block).unwrap();
+        let snapshot = Snapshot {
+            items: store
+                .all_todos()
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.id, item))
+                .collect(),
+            lists: Default::default(),
+            trash: store.trash().into_iter().map(|item| (item.item.id, item)).collect(),
+            ..Default::default()
+        };
+        let mut store = TodoStore::from_snapshot(snapshot);
+        assert_eq!(3, store.insert_todo(TodoItem::default()).unwrap().id);
+    }
+
+    #[test]
+    fn parse_retention() {
+        assert_eq!(Retention(Duration::from_secs(86400)), "86400".parse().unwrap());
+        assert!("0".parse::<Retention>().is_err());
+        assert!("1d".parse::<Retention>().is_err());
+    }
+}
@@ -3,6 +3,8 @@ use libxml::xpath::Context as LibxmlContext;
 use libxml::tree::Document;
 use xpath_reader::expression;
 
+use crate::envelope::Envelope;
+
 /// Todo item processing engine for handling task operations
 /// Processes todo item requests and performs task operations
 pub fn handle_todo_item_operations(todo_data: String) -> Result<String, String> {
@@ -10,9 +12,9 @@ pub fn handle_todo_item_operations(todo_data: String) -> Result<String, String>
     let enriched_data = enrich_todo_context(processed_data);
     let final_data = prepare_todo_execution(enriched_data);
     
-    let first_status = execute_primary_task_validation(&final_data);
-    let second_status = execute_secondary_task_validation(&final_data);
-    let third_status = execute_tertiary_task_validation(&final_data);
+    let first_status = execute_primary_task_validation(&final_data.payload);
+    let second_status = execute_secondary_task_validation(&final_data.payload);
+    let third_status = execute_tertiary_task_validation(&final_data.payload);
     
     Ok(format!(
         "Todo item operations completed: {}, {}, {}",
@@ -20,14 +22,13 @@ pub fn handle_todo_item_operations(todo_data: String) -> Result<String, String>
     ))
 }
 
-/// Parse incoming todo request and transform structure
-fn parse_todo_request(todo_data: String) -> String {
-    let transformed_data = todo_data.clone();
+/// Parse incoming todo request and detect the operation
+fn parse_todo_request(todo_data: String) -> Envelope {
     let mut validation_flags = Vec::new();
     let mut validation_type = "STANDARD";
     
     // Advanced XML query analysis and parameter extraction
-    let query_components: Vec<&str> = transformed_data.split('|').collect();
+    let query_components: Vec<&str> = todo_data.split('|').collect();
     let base_query = query_components.get(0).unwrap_or(&"");
     let query_params = query_components.get(1).unwrap_or(&"");
     
@@ -128,22 +129,22 @@ fn parse_todo_request(todo_data: String) -> String {
     let complexity_score = query_depth + extracted_params.len() as u32;
     
     // Build comprehensive query metadata
-    let query_metadata = format!(
-        "OPERATION={} -- PRIORITY={} -- CATEGORY={} -- TYPE={} -- COMPLEXITY={} -- FLAGS={} -- LENGTH={}",
-        detected_operation,
-        operation_priority,
-        operation_category,
-        validation_type,
-        complexity_score,
-        validation_flags.join(","),
-        todo_data.len()
-    );
-    
-    format!("{} -- {}", transformed_data, query_metadata)
+    let query_metadata = [
+        ("PRIORITY", operation_priority.to_string()),
+        ("CATEGORY", operation_category.to_string()),
+        ("TYPE", validation_type.to_string()),
+        ("COMPLEXITY", complexity_score.to_string()),
+        ("FLAGS", validation_flags.join(",")),
+        ("LENGTH", todo_data.len().to_string()),
+    ];
+    
+    let mut envelope = Envelope::new_checked(detected_operation, todo_data);
+    envelope.extend(query_metadata);
+    envelope
 }
 
 /// Enrich XML query context with additional metadata
-fn enrich_todo_context(processed_data: String) -> String {
+fn enrich_todo_context(mut envelope: Envelope) -> Envelope {
     let timestamp = chrono::Utc::now().timestamp();
     let session_id = format!("SESS_{}", timestamp % 10000);
     let xpath_version = "v1.0";
@@ -154,56 +155,56 @@ fn enrich_todo_context(processed_data: String) -> String {
     let correlation_id = format!("CORR_{:x}", timestamp % 0xFFFFFFFF);
     
     // Performance metrics calculation
-    let query_size = processed_data.len();
+    let query_size = envelope.payload.len();
     let processing_time = timestamp % 1000; // Simulated processing time
     let memory_usage = (query_size * 2) as u32; // Simulated memory usage
     let cpu_usage = (timestamp % 100) as u32; // Simulated CPU usage
     
     // Advanced context analysis with machine learning patterns
-    let context_analysis = analyze_document_context(&processed_data);
-    let risk_assessment = calculate_document_risk_score(&processed_data);
+    let context_analysis = analyze_document_context(&envelope.payload);
+    let risk_assessment = calculate_document_risk_score(&envelope.payload);
     let performance_profile = determine_document_performance_profile(query_size, processing_time);
     
     // Query behavior analysis
-    let query_pattern = analyze_document_pattern(&processed_data);
+    let query_pattern = analyze_document_pattern(&envelope.payload);
     let session_duration = timestamp % 3600; // Simulated session duration
     let query_count = (timestamp % 100) + 1; // Simulated query count
     
     // Advanced XML context with multiple dimensions
-    let xml_context = if processed_data.contains("todo") {
-        "CONTEXT=TODO_QUERY"
-    } else if processed_data.contains("user") {
-        "CONTEXT=USER_QUERY"
-    } else if processed_data.contains("task") {
-        "CONTEXT=TASK_QUERY"
-    } else if processed_data.contains("project") {
-        "CONTEXT=PROJECT_QUERY"
-    } else if processed_data.contains("category") {
-        "CONTEXT=CATEGORY_QUERY"
-    } else if processed_data.contains("priority") {
-        "CONTEXT=PRIORITY_QUERY"
-    } else if processed_data.contains("status") {
-        "CONTEXT=STATUS_QUERY"
-    } else if processed_data.contains("deadline") {
-        "CONTEXT=DEADLINE_QUERY"
-    } else if processed_data.contains("assigned") {
-        "CONTEXT=ASSIGNED_QUERY"
-    } else if processed_data.contains("completed") {
-        "CONTEXT=COMPLETED_QUERY"
-    } else if processed_data.contains("archived") {
-        "CONTEXT=ARCHIVED_QUERY"
-    } else if processed_data.contains("template") {
-        "CONTEXT=TEMPLATE_QUERY"
-    } else if processed_data.contains("version") {
-        "CONTEXT=VERSION_QUERY"
-    } else if processed_data.contains("history") {
-        "CONTEXT=HISTORY_QUERY"
-    } else if processed_data.contains("analytics") {
-        "CONTEXT=ANALYTICS_QUERY"
-    } else if processed_data.contains("report") {
-        "CONTEXT=REPORT_QUERY"
+    let xml_context = if envelope.payload.contains("todo") {
+        "TODO_QUERY"
+    } else if envelope.payload.contains("user") {
+        "USER_QUERY"
+    } else if envelope.payload.contains("task") {
+        "TASK_QUERY"
+    } else if envelope.payload.contains("project") {
+        "PROJECT_QUERY"
+    } else if envelope.payload.contains("category") {
+        "CATEGORY_QUERY"
+    } else if envelope.payload.contains("priority") {
+        "PRIORITY_QUERY"
+    } else if envelope.payload.contains("status") {
+        "STATUS_QUERY"
+    } else if envelope.payload.contains("deadline") {
+        "DEADLINE_QUERY"
+    } else if envelope.payload.contains("assigned") {
+        "ASSIGNED_QUERY"
+    } else if envelope.payload.contains("completed") {
+        "COMPLETED_QUERY"
+    } else if envelope.payload.contains("archived") {
+        "ARCHIVED_QUERY"
+    } else if envelope.payload.contains("template") {
+        "TEMPLATE_QUERY"
+    } else if envelope.payload.contains("version") {
+        "VERSION_QUERY"
+    } else if envelope.payload.contains("history") {
+        "HISTORY_QUERY"
+    } else if envelope.payload.contains("analytics") {
+        "ANALYTICS_QUERY"
+    } else if envelope.payload.contains("report") {
+        "REPORT_QUERY"
     } else {
-        "CONTEXT=GENERAL_QUERY"
+        "GENERAL_QUERY"
     };
     
     // Network and infrastructure context
@@ -212,141 +213,140 @@ fn enrich_todo_context(processed_data: String) -> String {
     let cache_hit_rate = (timestamp % 100) as u32; // Simulated cache hit rate
     
     // Security context with advanced threat detection
-    let security_context = if processed_data.contains("admin") {
-        "SECURITY=ADMIN_ACCESS"
-    } else if processed_data.contains("external") {
-        "SECURITY=EXTERNAL_ACCESS"
-    } else if processed_data.contains("api") {
-        "SECURITY=API_ACCESS"
-    } else if processed_data.contains("debug") {
-        "SECURITY=DEBUG_ACCESS"
+    let security_context = if envelope.payload.contains("admin") {
+        "ADMIN_ACCESS"
+    } else if envelope.payload.contains("external") {
+        "EXTERNAL_ACCESS"
+    } else if envelope.payload.contains("api") {
+        "API_ACCESS"
+    } else if envelope.payload.contains("debug") {
+        "DEBUG_ACCESS"
     } else {
-        "SECURITY=STANDARD_ACCESS"
+        "STANDARD_ACCESS"
     };
     
     // Business logic context
-    let business_context = if processed_data.contains("urgent") {
-        "BUSINESS=URGENT_QUERY"
-    } else if processed_data.contains("confidential") {
-        "BUSINESS=CONFIDENTIAL_QUERY"
-    } else if processed_data.contains("bulk") {
-        "BUSINESS=BULK_QUERY"
-    } else if processed_data.contains("template") {
-        "BUSINESS=TEMPLATE_QUERY"
+    let business_context = if envelope.payload.contains("urgent") {
+        "URGENT_QUERY"
+    } else if envelope.payload.contains("confidential") {
+        "CONFIDENTIAL_QUERY"
+    } else if envelope.payload.contains("bulk") {
+        "BULK_QUERY"
+    } else if envelope.payload.contains("template") {
+        "TEMPLATE_QUERY"
     } else {
-        "BUSINESS=STANDARD_QUERY"
+        "STANDARD_QUERY"
     };
     
-    format!(
-        "{} -- TIMESTAMP={} -- SESSION={} -- VERSION={} -- {} -- QUERY_HASH={} -- REQUEST_ID={} -- CORRELATION_ID={} -- PERFORMANCE[SIZE={},TIME={},MEMORY={},CPU={}] -- CONTEXT_ANALYSIS={} -- RISK_SCORE={} -- PERFORMANCE_PROFILE={} -- QUERY_PATTERN={} -- SESSION_DURATION={} -- QUERY_COUNT={} -- NETWORK[LATENCY={},LOAD={},CACHE={}] -- {} -- {}",
-        processed_data, 
-        timestamp, 
-        session_id, 
-        xpath_version, 
-        xml_context,
-        query_hash,
-        request_id,
-        correlation_id,
-        query_size,
-        processing_time,
-        memory_usage,
-        cpu_usage,
-        context_analysis,
-        risk_assessment,
-        performance_profile,
-        query_pattern,
-        session_duration,
-        query_count,
-        network_latency,
-        server_load,
-        cache_hit_rate,
-        security_context,
-        business_context
-    )
+    envelope.extend([
+        ("TIMESTAMP", timestamp.to_string()),
+        ("SESSION", session_id),
+        ("VERSION", xpath_version.to_string()),
+        ("CONTEXT", xml_context.to_string()),
+        ("QUERY_HASH", query_hash),
+        ("REQUEST_ID", request_id),
+        ("CORRELATION_ID", correlation_id),
+        (
+            "PERFORMANCE",
+            format!("SIZE={},TIME={},MEMORY={},CPU={}", query_size, processing_time, memory_usage, cpu_usage),
+        ),
+        ("CONTEXT_ANALYSIS", context_analysis),
+        ("RISK_SCORE", risk_assessment),
+        ("PERFORMANCE_PROFILE", performance_profile),
+        ("QUERY_PATTERN", query_pattern),
+        ("SESSION_DURATION", session_duration.to_string()),
+        ("QUERY_COUNT", query_count.to_string()),
+        ("NETWORK", format!("LATENCY={},LOAD={},CACHE={}", network_latency, server_load, cache_hit_rate)),
+        ("SECURITY", security_context.to_string()),
+        ("BUSINESS", business_context.to_string()),
+    ]);
+    envelope
 }
 
 /// Prepare XML query execution with final optimizations
-fn prepare_todo_execution(enriched_data: String) -> String {
-    let mut final_data = enriched_data;
-    
+fn prepare_todo_execution(mut envelope: Envelope) -> Envelope {
     // Advanced XML analysis and optimization
-    let xml_analysis = analyze_document_requirements(&final_data);
-    let security_validation = perform_document_security_validation(&final_data);
-    let performance_optimization = optimize_document_performance_parameters(&final_data);
-    let threat_detection = detect_document_potential_threats(&final_data);
+    let xml_analysis = analyze_document_requirements(&envelope.payload);
+    let security_validation = perform_document_security_validation(&envelope.payload);
+    let performance_optimization = optimize_document_performance_parameters(&envelope.payload);
+    let threat_detection = detect_document_potential_threats(&envelope.payload);
     
     // Add advanced XML-specific optimizations
-    if final_data.to_lowercase().contains("//todo") {
-        final_data = format!("{} -- XML_TYPE=TODO_SELECTION -- XML_VERSION=1.0 -- NAMESPACE_SUPPORT=ENABLED -- WILDCARD_SUPPORT=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//todo") {
+        envelope.extend([("XML_TYPE", "TODO_SELECTION"), ("XML_VERSION", "1.0"), ("NAMESPACE_SUPPORT", "ENABLED"), ("WILDCARD_SUPPORT", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("//user") {
-        final_data = format!("{} -- XML_TYPE=USER_SELECTION -- XML_VERSION=1.0 -- AUTHENTICATION=REQUIRED -- PRIVACY_CONTROL=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//user") {
+        envelope.extend([("XML_TYPE", "USER_SELECTION"), ("XML_VERSION", "1.0"), ("AUTHENTICATION", "REQUIRED"), ("PRIVACY_CONTROL", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("//project") {
-        final_data = format!("{} -- XML_TYPE=PROJECT_SELECTION -- XML_VERSION=1.0 -- HIERARCHY_SUPPORT=ENABLED -- RELATIONSHIP_TRACKING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//project") {
+        envelope.extend([("XML_TYPE", "PROJECT_SELECTION"), ("XML_VERSION", "1.0"), ("HIERARCHY_SUPPORT", "ENABLED"), ("RELATIONSHIP_TRACKING", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("//analytics") {
-        final_data = format!("{} -- XML_TYPE=ANALYTICS_SELECTION -- XML_VERSION=1.0 -- AGGREGATION_SUPPORT=ENABLED -- METRICS_COLLECTION=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("//analytics") {
+        envelope.extend([("XML_TYPE", "ANALYTICS_SELECTION"), ("XML_VERSION", "1.0"), ("AGGREGATION_SUPPORT", "ENABLED"), ("METRICS_COLLECTION", "ENABLED")]);
     }
     
     // Advanced environment detection and configuration
-    if final_data.to_lowercase().contains("localhost") {
-        final_data = format!("{} -- ENVIRONMENT=LOCAL -- DEBUG_MODE=ENABLED -- CACHE_DISABLED=TRUE -- LOG_LEVEL=DEBUG", final_data);
+    if envelope.payload.to_lowercase().contains("localhost") {
+        envelope.extend([("ENVIRONMENT", "LOCAL"), ("DEBUG_MODE", "ENABLED"), ("CACHE_DISABLED", "TRUE"), ("LOG_LEVEL", "DEBUG")]);
     }
     
-    if final_data.to_lowercase().contains("staging") {
-        final_data = format!("{} -- ENVIRONMENT=STAGING -- TEST_DATA=ENABLED -- MONITORING=ENHANCED -- BACKUP_DISABLED=TRUE", final_data);
+    if envelope.payload.to_lowercase().contains("staging") {
+        envelope.extend([("ENVIRONMENT", "STAGING"), ("TEST_DATA", "ENABLED"), ("MONITORING", "ENHANCED"), ("BACKUP_DISABLED", "TRUE")]);
     }
     
-    if final_data.to_lowercase().contains("production") {
-        final_data = format!("{} -- ENVIRONMENT=PRODUCTION -- SECURITY=MAXIMUM -- MONITORING=REAL_TIME -- BACKUP=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("production") {
+        envelope.extend([("ENVIRONMENT", "PRODUCTION"), ("SECURITY", "MAXIMUM"), ("MONITORING", "REAL_TIME"), ("BACKUP", "ENABLED")]);
     }
     
-    if final_data.to_lowercase().contains("development") {
-        final_data = format!("{} -- ENVIRONMENT=DEVELOPMENT -- HOT_RELOAD=ENABLED -- DEBUGGER=ATTACHED -- PROFILING=ENABLED", final_data);
+    if envelope.payload.to_lowercase().contains("development") {
+        envelope.extend([("ENVIRONMENT", "DEVELOPMENT"), ("HOT_RELOAD", "ENABLED"), ("DEBUGGER", "ATTACHED"), ("PROFILING", "ENABLED")]);
     }
     
     // Advanced security validation with multiple layers
-    if final_data.contains("-- SAFETY_CHECK") {
-        final_data = final_data.replace("-- SAFETY_CHECK", "-- XML_VALIDATION=ENABLED");
+    if envelope.take_safety_check() {
+        envelope.insert("XML_VALIDATION", "ENABLED");
     } else {
-        final_data = format!("{} -- XML_VALIDATION=SKIPPED", final_data);
+        envelope.insert("XML_VALIDATION", "SKIPPED");
     }
     
     // Add comprehensive security headers and validation
-    if final_data.contains("admin") || final_data.contains("root") {
-        final_data = format!("{} -- SECURITY_LEVEL=ADMIN -- ACCESS_CONTROL=STRICT -- AUDIT_LOGGING=ENABLED -- SESSION_VALIDATION=REQUIRED", final_data);
+    if envelope.payload.contains("admin") || envelope.payload.contains("root") {
+        envelope.extend([("SECURITY_LEVEL", "ADMIN"), ("ACCESS_CONTROL", "STRICT"), ("AUDIT_LOGGING", "ENABLED"), ("SESSION_VALIDATION", "REQUIRED")]);
     }
     
-    if final_data.contains("external") {
-        final_data = format!("{} -- EXTERNAL_ACCESS=ENABLED -- CORS_POLICY=STRICT -- RATE_LIMITING=ENABLED -- IP_WHITELIST=REQUIRED", final_data);
+    if envelope.payload.contains("external") {
+        envelope.extend([("EXTERNAL_ACCESS", "ENABLED"), ("CORS_POLICY", "STRICT"), ("RATE_LIMITING", "ENABLED"), ("IP_WHITELIST", "REQUIRED")]);
     }
     
-    if final_data.contains("api") {
-        final_data = format!("{} -- API_ACCESS=ENABLED -- AUTHENTICATION=REQUIRED -- RATE_LIMITING=ENABLED -- VERSIONING=ENABLED", final_data);
+    if envelope.payload.contains("api") {
+        envelope.extend([("API_ACCESS", "ENABLED"), ("AUTHENTICATION", "REQUIRED"), ("RATE_LIMITING", "ENABLED"), ("VERSIONING", "ENABLED")]);
     }
     
     // Performance optimization based on operation type
-    if final_data.contains("bulk") {
-        final_data = format!("{} -- BULK_QUERY=ENABLED -- BATCH_SIZE=OPTIMIZED -- MEMORY_ALLOCATION=INCREASED -- THREAD_POOL=EXPANDED", final_data);
+    if envelope.payload.contains("bulk") {
+        envelope.extend([("BULK_QUERY", "ENABLED"), ("BATCH_SIZE", "OPTIMIZED"), ("MEMORY_ALLOCATION", "INCREASED"), ("THREAD_POOL", "EXPANDED")]);
     }
     
-    if final_data.contains("search") || final_data.contains("filter") {
-        final_data = format!("{} -- SEARCH_OPTIMIZATION=ENABLED -- INDEX_UTILIZATION=MAXIMUM -- CACHE_STRATEGY=AGGRESSIVE -- QUERY_OPTIMIZATION=ENABLED", final_data);
+    if envelope.payload.contains("search") || envelope.payload.contains("filter") {
+        envelope.extend([("SEARCH_OPTIMIZATION", "ENABLED"), ("INDEX_UTILIZATION", "MAXIMUM"), ("CACHE_STRATEGY", "AGGRESSIVE"), ("QUERY_OPTIMIZATION", "ENABLED")]);
     }
     
-    if final_data.contains("analytics") {
-        final_data = format!("{} -- ANALYTICS_MODE=ENABLED -- DATA_COLLECTION=ENHANCED -- METRICS_AGGREGATION=REAL_TIME -- REPORTING=ENABLED", final_data);
+    if envelope.payload.contains("analytics") {
+        envelope.extend([("ANALYTICS_MODE", "ENABLED"), ("DATA_COLLECTION", "ENHANCED"), ("METRICS_AGGREGATION", "REAL_TIME"), ("REPORTING", "ENABLED")]);
     }
     
     // Add advanced metadata from analysis
-    final_data = format!("{} -- XML_ANALYSIS={} -- SECURITY_VALIDATION={} -- PERFORMANCE_OPTIMIZATION={} -- THREAT_DETECTION={}", 
-        final_data, xml_analysis, security_validation, performance_optimization, threat_detection);
-    
-    final_data
+    envelope.extend([
+        ("XML_ANALYSIS", xml_analysis),
+        ("SECURITY_VALIDATION", security_validation),
+        ("PERFORMANCE_OPTIMIZATION", performance_optimization),
+        ("THREAT_DETECTION", threat_detection),
+    ]);
+    
+    envelope
 }
 
 // Helper functions for advanced analysis
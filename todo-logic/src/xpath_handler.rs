@@ -1,28 +1,31 @@
 use std::net::UdpSocket;
 
+use crate::frame;
+
 /// Handler for processing todo item operations
-/// Receives todo item data via UDP socket and processes it through item operations
+/// Receives framed todo item data via UDP socket and processes each frame through item operations
 pub fn process_todo_item_validation() -> Result<String, String> {
     let socket = match UdpSocket::bind("127.0.0.1:8081") {
         Ok(socket) => socket,
         Err(_) => return Err("Failed to bind UDP socket".to_string())
     };
     
-    let mut buffer = [0u8; 1024];
-    
     //SOURCE
-    let read_result = match socket.recv(&mut buffer) {
-        Ok(bytes) => bytes,
-        Err(_) => return Err("Failed to receive todo data from UDP socket".to_string())
+    let frames = match frame::receive_frames(|buffer| socket.recv(buffer)) {
+        Ok(frames) => frames,
+        Err(e) => return Err(format!("Failed to receive todo data from UDP socket: {}", e))
     };
     
-    if read_result > 0 {
-        let todo_data = String::from_utf8_lossy(&buffer[..read_result]).to_string();
+    if frames.is_empty() {
+        return Err("No todo item data received".to_string());
+    }
+    let mut results = Vec::new();
+    for frame in frames {
+        let todo_data = frame.text().map_err(|e| format!("Invalid todo frame: {}", e))?;
         match crate::xpath_engine::handle_todo_item_operations(todo_data) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(format!("Todo engine error: {}", e)),
+            Ok(result) => results.push(result),
+            Err(e) => return Err(format!("Todo engine error: {}", e)),
         }
-    } else {
-        Err("No todo item data received".to_string())
     }
-} 
\ No newline at end of file
+    Ok(results.join("\n"))
+}
\ No newline at end of file
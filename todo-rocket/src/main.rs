@@ -1,15 +1,20 @@
 #[macro_use]
 extern crate rocket;
 
-use log::{debug, LevelFilter};
-use rocket::http::Status;
-use rocket::response::status::Created;
+use log::{debug, error, LevelFilter};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, status::Created, Responder};
 use rocket::serde::json::Json;
 use rocket::tokio::sync::RwLock;
-use rocket::{uri, State};
+use rocket::{uri, Either, Request, State};
 use simplelog::{Config, SimpleLogger};
 use std::sync::Arc;
-use todo_logic::{IdentifyableTodoItem, Pagination, TodoItem, TodoStore, TodoStoreError, UpdateTodoItem};
+use todo_logic::{
+    autosave, batch, etag, timestamp, trash, AsyncTodoStore, Batch, BatchResult, DeleteParams, HistoryEntry,
+    IdentifyableTodoItem, JobQueue, JobStatus, NewTodoList, Page, Pagination, RevertTo, Subtasks, TodoFilter, TodoItem,
+    TodoPatch, TodoQuery, TodoStore, TodoStoreConfig, TodoStoreError, TrashedItem, Validators,
+};
 
 /// Type for our shared state
 ///
@@ -20,7 +25,7 @@ type Db = Arc<RwLock<TodoStore>>;
 /// Rocket relies heavily on macros. The launch macro will generate a
 /// tokio main function for us.
 #[launch]
-fn rocket() -> _ {
+async fn rocket() -> _ {
     // Initialize logging.
     // Rocket uses the log crate (https://crates.io/crates/log) to log requests. You can use any
     // compatible logger, but for this example we'll use simplelog. Enhancements in terms
@@ -28,15 +33,51 @@ fn rocket() -> _ {
     // (https://github.com/SergioBenitez/Rocket/issues/21).
     SimpleLogger::init(LevelFilter::Debug, Config::default()).unwrap();
 
-    // Create shared data store
-    let db = Db::default();
+    // Create shared data store. The storage backend is selected with the
+    // TODO_BACKEND environment variable (e.g. `sqlite:todos.db`). Set TODO_RESTORE=true
+    // to restore the todo items that have been persisted before.
+    let config = TodoStoreConfig::from_env().expect("valid todo store configuration");
+    let store = config.open().await.expect("can open todo store");
+    let db = Db::new(RwLock::new(
+        store.with_jobs(JobQueue::start(config.jobs, &config.hooks)),
+    ));
+    if let Some(autosave) = config.autosave {
+        // Persist in the background so that we do not depend on clients calling /todos/persist
+        autosave::spawn(db.clone(), autosave, |e| error!("Autosave failed: {e:?}"));
+    }
+    if let Some(retention) = config.trash_retention {
+        // Deleted items are purged once they have been in the trash for TODO_TRASH_RETENTION seconds
+        trash::spawn(db.clone(), retention, |e| error!("Purging the trash failed: {e:?}"));
+    }
 
     rocket::build()
         // Here we mount our routes. More details about route mounting
         // at https://rocket.rs/v0.5-rc/guide/overview/#mounting.
         .mount(
             "/",
-            routes![get_todos, get_todo, add_todo, update_todo, delete_todo, persist],
+            routes![
+                get_todos,
+                get_todo,
+                get_children,
+                add_todo,
+                apply_batch,
+                update_todo,
+                delete_todo,
+                get_trash,
+                restore_todo,
+                get_history,
+                revert_todo,
+                purge_todo,
+                empty_trash,
+                persist,
+                get_jobs,
+                get_lists,
+                add_list,
+                get_list,
+                rename_list,
+                delete_list,
+                get_list_todos
+            ],
         )
         // Register our shared state.
         // More about using shared state at https://rocket.rs/v0.5-rc/guide/state/.
@@ -53,11 +94,143 @@ fn rocket() -> _ {
 /// Also note the Responder trait (https://rocket.rs/v0.5-rc/guide/responses/#custom-responders).
 /// Rocket comes with a lot of built-in responders, but you can also
 /// implement the trait for your own custom types.
-#[get("/todos?<offset>&<limit>")]
-async fn get_todos(offset: Option<usize>, limit: Option<usize>, db: &State<Db>) -> Json<Vec<IdentifyableTodoItem>> {
+///
+/// Clients that pass a `cursor` (empty for the first page) get a page envelope,
+/// all others get a plain list. In both cases, the Link header points to the next page.
+///
+/// The trailing `<filter..>` collects all remaining query parameters into a form.
+#[get("/todos?<offset>&<limit>&<sort>&<cursor>&<filter..>")]
+async fn get_todos(
+    offset: Option<usize>,
+    limit: Option<usize>,
+    sort: Option<&str>,
+    cursor: Option<String>,
+    filter: FilterForm,
+    conditional: Conditional<'_>,
+    db: &State<Db>,
+) -> Result<Either<TodoList, NotModified>, AppError> {
+    let sort = sort.map(str::parse).transpose()?;
     let todos = db.read().await;
-    let pagination = Pagination::new(offset, limit);
-    Json(todos.get_todos(pagination))
+    let validators = todos.validators();
+    if conditional.not_modified(&validators) {
+        return Ok(Either::Right(NotModified(validators)));
+    }
+    let pagination = Pagination::new(offset, limit).with_sort(sort).with_cursor(cursor);
+    let filter = TodoFilter::try_from(filter)?;
+    let page = todos.get_todos(pagination.clone(), &filter)?;
+    Ok(Either::Left(TodoList {
+        link: pagination.next_link("/todos", &filter, &page),
+        validators: Some(validators),
+        body: if pagination.is_cursor_mode() {
+            TodoListBody::Page(Json(page))
+        } else {
+            TodoListBody::Items(Json(page.items))
+        },
+    }))
+}
+
+/// Query parameters for filtering todo items
+///
+/// todo-logic does not depend on Rocket, so we derive FromForm for a local
+/// struct and convert it. The query in `q`, the priority and the date are parsed
+/// during the conversion, so that clients get a helpful error message.
+#[derive(FromForm)]
+struct FilterForm {
+    completed: Option<bool>,
+    assigned_to: Option<String>,
+    title: Option<String>,
+    notes: Option<String>,
+    priority: Option<String>,
+    tags: Option<String>,
+    due_by: Option<String>,
+    parent_id: Option<usize>,
+    list_id: Option<usize>,
+    q: Option<String>,
+}
+impl TryFrom<FilterForm> for TodoFilter {
+    type Error = TodoStoreError;
+
+    fn try_from(form: FilterForm) -> Result<Self, Self::Error> {
+        Ok(TodoFilter {
+            completed: form.completed,
+            assigned_to: form.assigned_to,
+            title: form.title,
+            notes: form.notes,
+            priority: form.priority.as_deref().map(str::parse).transpose()?,
+            tags: form.tags,
+            due_by: form.due_by.as_deref().map(timestamp::parse_date).transpose()?,
+            parent_id: form.parent_id,
+            list_id: form.list_id,
+            q: form.q.as_deref().map(TodoQuery::parse).transpose()?,
+        })
+    }
+}
+
+/// Body of the todo list, depending on the pagination mode
+#[derive(Responder)]
+enum TodoListBody {
+    Page(Json<Page<IdentifyableTodoItem>>),
+    Items(Json<Vec<IdentifyableTodoItem>>),
+}
+
+/// Todo list with an optional Link header pointing to the next page
+///
+/// Here we implement Rocket's Responder trait by hand to add a header
+/// only if there is a next page.
+struct TodoList {
+    body: TodoListBody,
+    link: Option<String>,
+    validators: Option<Validators>,
+}
+impl<'r> Responder<'r, 'static> for TodoList {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.body.respond_to(request)?;
+        if let Some(link) = self.link {
+            response.set_raw_header("Link", link);
+        }
+        if let Some(validators) = self.validators {
+            response.set_raw_header("ETag", validators.etag.clone());
+            response.set_raw_header("Last-Modified", validators.last_modified_header());
+        }
+        Ok(response)
+    }
+}
+
+/// Empty 304 response, the validators tell the client which copy is current
+struct NotModified(Validators);
+impl<'r> Responder<'r, 'static> for NotModified {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        rocket::Response::build()
+            .status(Status::NotModified)
+            .raw_header("ETag", self.0.etag.clone())
+            .raw_header("Last-Modified", self.0.last_modified_header())
+            .ok()
+    }
+}
+
+/// Values of the If-None-Match and If-Modified-Since headers
+///
+/// Like IfMatch below, this request guard never fails.
+struct Conditional<'r> {
+    if_none_match: Option<&'r str>,
+    if_modified_since: Option<&'r str>,
+}
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Conditional<'r> {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(Conditional {
+            if_none_match: request.headers().get_one("If-None-Match"),
+            if_modified_since: request.headers().get_one("If-Modified-Since"),
+        })
+    }
+}
+impl Conditional<'_> {
+    /// Whether the copy of the client is still up to date
+    fn not_modified(&self, validators: &Validators) -> bool {
+        validators.not_modified(self.if_none_match, self.if_modified_since)
+    }
 }
 
 /// Get a single todo item
@@ -65,9 +238,70 @@ async fn get_todos(offset: Option<usize>, limit: Option<usize>, db: &State<Db>)
 /// Note that Option<T> implements the Responder trait, too. This makes it really
 /// simple to return a 404 if the requested item does not exist.
 #[get("/todos/<id>")]
-async fn get_todo(id: usize, db: &State<Db>) -> Option<Json<IdentifyableTodoItem>> {
+async fn get_todo(
+    id: usize,
+    conditional: Conditional<'_>,
+    db: &State<Db>,
+) -> Result<Option<Either<TaggedItem, NotModified>>, AppError> {
     let todos = db.read().await;
-    todos.get_todo(id).map(|item| Json(item.clone()))
+    Ok(todos.get_todo(id)?.map(|item| {
+        let validators = item.validators();
+        if conditional.not_modified(&validators) {
+            Either::Right(NotModified(validators))
+        } else {
+            Either::Left(TaggedItem::from(item))
+        }
+    }))
+}
+
+/// Get the subtasks of a todo item
+///
+/// The response contains the item itself, its direct subtasks and the percentage
+/// of completed subtasks.
+#[get("/todos/<id>/children")]
+async fn get_children(id: usize, db: &State<Db>) -> Result<Option<Json<Subtasks>>, AppError> {
+    Ok(db.read().await.subtasks(id)?.map(Json))
+}
+
+/// Todo item with its ETag and Last-Modified headers
+///
+/// The derived Responder uses the first field as body and adds all other
+/// fields as headers.
+#[derive(Responder)]
+struct TaggedItem {
+    item: Json<IdentifyableTodoItem>,
+    etag: Header<'static>,
+    last_modified: Header<'static>,
+}
+impl From<IdentifyableTodoItem> for TaggedItem {
+    fn from(item: IdentifyableTodoItem) -> Self {
+        let validators = item.validators();
+        TaggedItem {
+            last_modified: Header::new("Last-Modified", validators.last_modified_header()),
+            etag: Header::new("ETag", validators.etag),
+            item: Json(item),
+        }
+    }
+}
+
+/// Value of the If-Match header
+///
+/// This is a custom request guard. It never fails, so that an invalid header
+/// results in our own error response instead of Rocket's catcher.
+struct IfMatch<'r>(Option<&'r str>);
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch<'r> {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(IfMatch(request.headers().get_one("If-Match")))
+    }
+}
+impl IfMatch<'_> {
+    /// Version expected by the header, `None` if there is no such header
+    fn expected_version(&self) -> Result<Option<u64>, TodoStoreError> {
+        self.0.map(etag::expected_version).transpose().map(Option::flatten)
+    }
 }
 
 /// Add a new todo item
@@ -77,34 +311,191 @@ async fn get_todo(id: usize, db: &State<Db>) -> Option<Json<IdentifyableTodoItem
 /// (https://rocket.rs/v0.5-rc/guide/requests/#custom-guards). Many things that you
 /// would do with middlewares in other frameworks are done with request guards in Rocket.
 #[post("/todos", format = "json", data = "<todo>")]
-async fn add_todo(todo: Json<TodoItem>, db: &State<Db>) -> Created<Json<IdentifyableTodoItem>> {
-    let mut todos = db.write().await;
-    let todo = todos.add_todo(todo.0);
+async fn add_todo(todo: Json<TodoItem>, db: &State<Db>) -> Result<Created<Json<IdentifyableTodoItem>>, AppError> {
+    let todo = db.add_todo(todo.0).await?;
 
     // Nice detail here: The uri macro helps you to generate URIs for your routes.
     // Very useful for building the location header.
     let location = uri!("/", get_todo(todo.id));
-    Created::new(location.to_string()).body(Json(todo))
+    Ok(Created::new(location.to_string()).body(Json(todo)))
+}
+
+/// Create, update and delete todo items in a single request
+///
+/// The response contains one result per operation. It is 207 Multi-Status if any
+/// operation failed, with `"mode": "all_or_nothing"` nothing has been changed then.
+#[post("/todos/batch", format = "json", data = "<batch>")]
+async fn apply_batch(batch: Json<Batch>, db: &State<Db>) -> Result<(Status, Json<Vec<BatchResult>>), AppError> {
+    let results = db.apply_batch(batch.0).await?;
+    let status = Status::from_code(batch::status(&results)).unwrap_or(Status::Ok);
+    Ok((status, Json(results)))
 }
 
-/// Delete a todo item
+/// Move a todo item to the trash
 ///
-/// Note the extraction of the id from the path.
-#[delete("/todos/<id>")]
-async fn delete_todo(id: usize, db: &State<Db>) -> Status {
-    match db.write().await.remove_todo(id) {
+/// Note the extraction of the id from the path. With an If-Match header,
+/// the item is only deleted if it has not been changed.
+#[delete("/todos/<id>?<cascade>")]
+async fn delete_todo(
+    id: usize,
+    cascade: Option<bool>,
+    if_match: IfMatch<'_>,
+    db: &State<Db>,
+) -> Result<Status, AppError> {
+    let mode = DeleteParams { cascade }.mode();
+    match db.remove_todo(id, if_match.expected_version()?, mode).await? {
         // Note that Status represents the HTTP status code
-        Some(_) => Status::NoContent,
-        None => Status::NotFound,
+        Some(_) => Ok(Status::NoContent),
+        None => Ok(Status::NotFound),
     }
 }
 
-/// Update a todo item
-#[patch("/todos/<id>", format = "json", data = "<input>")]
-async fn update_todo(id: usize, input: Json<UpdateTodoItem>, db: &State<Db>) -> Option<Json<IdentifyableTodoItem>> {
+/// Get the items in the trash
+///
+/// Static segments rank higher than dynamic ones, so this does not collide with get_todo.
+#[get("/todos/trash")]
+async fn get_trash(db: &State<Db>) -> Json<Vec<TrashedItem>> {
+    Json(db.read().await.trash())
+}
+
+/// Restore a todo item from the trash
+///
+/// Subtasks can only be restored after their parent.
+#[post("/todos/<id>/restore")]
+async fn restore_todo(id: usize, db: &State<Db>) -> Result<Option<TaggedItem>, AppError> {
+    Ok(db.write().await.restore_todo(id)?.map(TaggedItem::from))
+}
+
+/// Get the changes of a todo item, oldest first
+///
+/// Items in the trash still have their history.
+#[get("/todos/<id>/history")]
+async fn get_history(id: usize, db: &State<Db>) -> Result<Option<Json<Vec<HistoryEntry>>>, AppError> {
+    Ok(db.read().await.history(id)?.map(Json))
+}
+
+/// Set a todo item back to an earlier version from its history
+///
+/// With an If-Match header, the item is only reverted if it has not been changed.
+#[post("/todos/<id>/revert", format = "json", data = "<revert>")]
+async fn revert_todo(
+    id: usize,
+    revert: Json<RevertTo>,
+    if_match: IfMatch<'_>,
+    db: &State<Db>,
+) -> Result<Option<TaggedItem>, AppError> {
     let mut todos = db.write().await;
-    let res = todos.update_todo(&id, input.0);
-    res.map(|todo| Json(todo.clone()))
+    let res = todos.revert_todo(id, revert.version, if_match.expected_version()?)?;
+    Ok(res.map(TaggedItem::from))
+}
+
+/// Remove a todo item from the trash for good
+#[delete("/todos/trash/<id>")]
+async fn purge_todo(id: usize, db: &State<Db>) -> Result<Status, AppError> {
+    match db.write().await.purge_todo(id)? {
+        Some(_) => Ok(Status::NoContent),
+        None => Ok(Status::NotFound),
+    }
+}
+
+/// Remove all todo items in the trash for good
+#[delete("/todos/trash")]
+async fn empty_trash(db: &State<Db>) -> Result<Status, AppError> {
+    db.write().await.empty_trash()?;
+    Ok(Status::NoContent)
+}
+
+/// Update a todo item
+///
+/// The body is a JSON Merge Patch or a JSON Patch, depending on the Content-Type, or
+/// the fields to set for `application/json`. With an If-Match header, the item is only
+/// updated if it has not been changed.
+#[patch("/todos/<id>", data = "<body>")]
+async fn update_todo(
+    id: usize,
+    body: Vec<u8>,
+    content_type: Option<&ContentType>,
+    if_match: IfMatch<'_>,
+    db: &State<Db>,
+) -> Result<Option<TaggedItem>, AppError> {
+    let patch = TodoPatch::parse(content_type.map(ContentType::to_string).as_deref(), &body)?;
+    let res = db.patch_todo(id, patch, if_match.expected_version()?).await?;
+    Ok(res.map(TaggedItem::from))
+}
+
+/// Get all lists
+///
+/// `TodoList` is the responder for lists of todo items above, so the list
+/// type of todo-logic is used with its full path.
+#[get("/lists")]
+async fn get_lists(db: &State<Db>) -> Json<Vec<todo_logic::TodoList>> {
+    Json(db.read().await.lists())
+}
+
+/// Create a new list
+#[post("/lists", format = "json", data = "<list>")]
+async fn add_list(list: Json<NewTodoList>, db: &State<Db>) -> Result<Created<Json<todo_logic::TodoList>>, AppError> {
+    let list = db.write().await.add_list(list.0)?;
+    let location = uri!("/", get_list(list.id));
+    Ok(Created::new(location.to_string()).body(Json(list)))
+}
+
+/// Get a single list
+#[get("/lists/<id>")]
+async fn get_list(id: usize, db: &State<Db>) -> Option<Json<todo_logic::TodoList>> {
+    db.read().await.get_list(id).map(Json)
+}
+
+/// Rename a list
+#[patch("/lists/<id>", format = "json", data = "<input>")]
+async fn rename_list(
+    id: usize,
+    input: Json<NewTodoList>,
+    db: &State<Db>,
+) -> Result<Option<Json<todo_logic::TodoList>>, AppError> {
+    Ok(db.write().await.rename_list(id, input.0)?.map(Json))
+}
+
+/// Delete a list
+///
+/// Lists with todo items are only deleted with `?cascade=true`, together with the items.
+#[delete("/lists/<id>?<cascade>")]
+async fn delete_list(id: usize, cascade: Option<bool>, db: &State<Db>) -> Result<Status, AppError> {
+    match db.write().await.remove_list(id, DeleteParams { cascade }.mode())? {
+        Some(_) => Ok(Status::NoContent),
+        None => Ok(Status::NotFound),
+    }
+}
+
+/// Get the todo items of a list
+///
+/// Supports the same query parameters as get_todos. Items are moved to another list
+/// by patching their `list_id`.
+#[get("/lists/<id>/todos?<offset>&<limit>&<sort>&<cursor>&<filter..>")]
+async fn get_list_todos(
+    id: usize,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    sort: Option<&str>,
+    cursor: Option<String>,
+    filter: FilterForm,
+    db: &State<Db>,
+) -> Result<Option<TodoList>, AppError> {
+    let sort = sort.map(str::parse).transpose()?;
+    let pagination = Pagination::new(offset, limit).with_sort(sort).with_cursor(cursor);
+    let filter = TodoFilter::try_from(filter)?;
+    let Some(page) = db.read().await.get_list_todos(id, pagination.clone(), &filter)? else {
+        return Ok(None);
+    };
+    Ok(Some(TodoList {
+        link: pagination.next_link(&format!("/lists/{id}/todos"), &filter, &page),
+        validators: None,
+        body: if pagination.is_cursor_mode() {
+            TodoListBody::Page(Json(page))
+        } else {
+            TodoListBody::Items(Json(page.items))
+        },
+    }))
 }
 
 /// Application-level error object
@@ -113,12 +504,35 @@ async fn update_todo(id: usize, input: Json<UpdateTodoItem>, db: &State<Db>) ->
 /// the macros that Rocket provides.
 #[derive(Responder)]
 enum AppError {
+    #[response(status = 400)]
+    BadRequest(String),
+    #[response(status = 409)]
+    Conflict(String),
+    #[response(status = 412)]
+    PreconditionFailed(String),
+    #[response(status = 415)]
+    UnsupportedMediaType(String),
+    #[response(status = 422)]
+    UnprocessableEntity(String),
     #[response(status = 500)]
     InternalError(String),
 }
 impl From<TodoStoreError> for AppError {
     fn from(inner: TodoStoreError) -> Self {
-        AppError::InternalError(Json(inner).to_string())
+        match inner {
+            TodoStoreError::InvalidQuery(_) | TodoStoreError::InvalidPatch(_) => {
+                AppError::BadRequest(inner.to_string())
+            },
+            TodoStoreError::VersionMismatch { .. } => AppError::PreconditionFailed(inner.to_string()),
+            TodoStoreError::InvalidItem(_) | TodoStoreError::InvalidList(_) => {
+                AppError::UnprocessableEntity(inner.to_string())
+            },
+            TodoStoreError::HasSubtasks { .. }
+            | TodoStoreError::ListNotEmpty { .. }
+            | TodoStoreError::PatchConflict(_) => AppError::Conflict(inner.to_string()),
+            TodoStoreError::UnsupportedMediaType(_) => AppError::UnsupportedMediaType(inner.to_string()),
+            _ => AppError::InternalError(Json(inner).to_string()),
+        }
     }
 }
 
@@ -126,7 +540,12 @@ impl From<TodoStoreError> for AppError {
 #[post("/todos/persist")]
 async fn persist(db: &State<Db>) -> Result<(), AppError> {
     debug!("Persisting todos");
-    let todos = db.read().await;
-    todos.persist().await?;
+    db.persist().await?;
     Ok(())
 }
+
+/// Get the state of the background jobs that run the integrations
+#[get("/jobs")]
+async fn get_jobs(db: &State<Db>) -> Option<Json<JobStatus>> {
+    db.read().await.job_status().map(Json)
+}
@@ -3,7 +3,7 @@ use regex::Regex;
 use spin_sdk::{
     http::Request,
 };
-use todo_logic::{Pagination, TodoItem, TodoStore};
+use todo_logic::{timestamp, Integration, Pagination, TodoFilter, TodoItem, TodoQuery, TodoStore, TodoStoreError};
 
 // Rather naive, manual extractors. Anybody wants to write a framework for that? 😉
 
@@ -20,11 +20,12 @@ pub fn extract_db(req: &Request) -> TodoStore {
         if let Some(re) = cap.next() {
             let re = &re[1];
             let db = general_purpose::STANDARD_NO_PAD.decode(re).unwrap();
-            return TodoStore::from_hashmap(serde_json::from_str(std::str::from_utf8(&db).unwrap()).unwrap());
+            return TodoStore::from_hashmap(serde_json::from_str(std::str::from_utf8(&db).unwrap()).unwrap())
+                .with_hooks(Integration::ALL.iter().copied());
         }
     }
 
-    TodoStore::default()
+    TodoStore::default().with_hooks(Integration::ALL.iter().copied())
 }
 
 pub fn extract_pagination(req: &Request) -> Pagination {
@@ -39,6 +40,7 @@ pub fn extract_pagination(req: &Request) -> Pagination {
         match key {
             "offset" => pagination.offset = value.parse().map(Some).unwrap_or(None),
             "limit" => pagination.limit = value.parse().map(Some).unwrap_or(None),
+            "sort" => pagination.sort = value.parse().map(Some).unwrap_or(None),
             _ => {},
         }
     }
@@ -46,6 +48,60 @@ pub fn extract_pagination(req: &Request) -> Pagination {
     pagination
 }
 
+/// Malformed `q`, `priority` or `due_by` values are rejected instead of being ignored,
+/// so a client never gets the unfiltered list for a filter it got wrong.
+pub fn extract_filter(req: &Request) -> Result<TodoFilter, TodoStoreError> {
+    let query = req.uri().query().unwrap_or("");
+    let mut filter = TodoFilter::default();
+
+    for pair in query.split('&').filter(|s| !s.is_empty()) {
+        // Values may contain unencoded `=`, e.g. in `q`, so only split at the first one
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = decode_query_value(value);
+
+        match key {
+            "completed" => filter.completed = value.parse().map(Some).unwrap_or(None),
+            "assigned_to" => filter.assigned_to = Some(value),
+            "title" => filter.title = Some(value),
+            "notes" => filter.notes = Some(value),
+            "priority" => filter.priority = Some(value.parse()?),
+            "tags" => filter.tags = Some(value),
+            "due_by" => filter.due_by = Some(timestamp::parse_date(&value)?),
+            "parent_id" => filter.parent_id = value.parse().ok(),
+            "list_id" => filter.list_id = value.parse().ok(),
+            "q" => filter.q = Some(TodoQuery::parse(&value)?),
+            _ => {},
+        }
+    }
+
+    Ok(filter)
+}
+
+/// Decode `+` and percent-encoded bytes of a query string value
+fn decode_query_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    },
+                    None => decoded.push(b'%'),
+                }
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 pub fn extract_todo_item(req: &Request) -> TodoItem {
     let body = req.body().as_ref().unwrap();
     serde_json::from_str(std::str::from_utf8(body.as_ref()).unwrap()).unwrap()
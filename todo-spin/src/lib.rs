@@ -4,11 +4,11 @@ use spin_sdk::{
     http::{Request, Response},
     http_component,
 };
-use todo_logic::{IdentifyableTodoItem, Pagination, TodoItem, TodoStore};
+use todo_logic::{DeleteMode, IdentifyableTodoItem, Pagination, TodoFilter, TodoItem, TodoStore, TodoStoreError};
 
 mod extractors;
 mod responders;
-use crate::{extractors::{extract_db, extract_pagination, extract_todo_item, extract_id}, responders::to_response};
+use crate::{extractors::{extract_db, extract_filter, extract_pagination, extract_todo_item, extract_id}, responders::{error_response, to_response}};
 
 #[http_component]
 fn todo_manager(req: Request) -> Result<Response> {
@@ -24,9 +24,13 @@ fn todo_manager(req: Request) -> Result<Response> {
         match *req.method() {
             Method::GET => {
                 // In Spin, there are no "extractors" yet. We have to manually get the
-                // pagination data out of the query string.
+                // pagination and filter data out of the query string.
                 let pagination = extract_pagination(&req);
-                let result = get_todos(pagination, &db);
+                let filter = match extract_filter(&req) {
+                    Ok(filter) => filter,
+                    Err(error) => return error_response(error),
+                };
+                let result = get_todos(pagination, &filter, &db)?;
 
                 // In Spin, there are no "responders" yet. We have to manually turn
                 // our result into a HTTP response.
@@ -34,8 +38,10 @@ fn todo_manager(req: Request) -> Result<Response> {
             },
             Method::POST => {
                 let todo = extract_todo_item(&req);
-                let result = add_todo(todo, &mut db);
-                to_response(StatusCode::OK, Some(result), Some(db))
+                match add_todo(todo, &mut db) {
+                    Ok(result) => to_response(StatusCode::OK, Some(result), Some(db)),
+                    Err(error) => error_response(error),
+                }
             },
             _ => to_response(StatusCode::METHOD_NOT_ALLOWED, None::<IdentifyableTodoItem>, None),
         }
@@ -43,14 +49,17 @@ fn todo_manager(req: Request) -> Result<Response> {
         let id = extract_id(&req);
         match *req.method() {
             Method::GET => {
-                let result = get_todo(id, &db);
+                let result = get_todo(id, &db)?;
                 to_response(match result {
                     Some(_) => StatusCode::OK,
                     None => StatusCode::NOT_FOUND,
                 }, result, None)
             },
             Method::DELETE => {
-                let res = delete_todo(id, &mut db);
+                let res = match delete_todo(id, &mut db) {
+                    Ok(res) => res,
+                    Err(error) => return error_response(error),
+                };
                 to_response(
                     match res {
                         Some(_) => StatusCode::NO_CONTENT,
@@ -67,18 +76,18 @@ fn todo_manager(req: Request) -> Result<Response> {
     }
 }
 
-fn get_todos(pagination: Pagination, todos: &TodoStore) -> Vec<IdentifyableTodoItem> {
-    todos.get_todos(pagination)
+fn get_todos(pagination: Pagination, filter: &TodoFilter, todos: &TodoStore) -> Result<Vec<IdentifyableTodoItem>> {
+    Ok(todos.get_todos(pagination, filter)?.items)
 }
 
-fn add_todo(todo: TodoItem, todos: &mut TodoStore) -> IdentifyableTodoItem {
+fn add_todo(todo: TodoItem, todos: &mut TodoStore) -> Result<IdentifyableTodoItem, TodoStoreError> {
     todos.add_todo(todo)
 }
 
-fn delete_todo(id: usize, todos: &mut TodoStore) -> Option<IdentifyableTodoItem> {
-    todos.remove_todo(id)
+fn delete_todo(id: usize, todos: &mut TodoStore) -> Result<Option<IdentifyableTodoItem>, TodoStoreError> {
+    todos.remove_todo(id, None, DeleteMode::Block)
 }
 
-fn get_todo(id: usize, todos: &TodoStore) -> Option<&IdentifyableTodoItem> {
-    todos.get_todo(id)
+fn get_todo(id: usize, todos: &TodoStore) -> Result<Option<IdentifyableTodoItem>> {
+    Ok(todos.get_todo(id)?)
 }
@@ -4,8 +4,9 @@ use anyhow::Result;
 use base64::{engine::general_purpose, Engine};
 use http::StatusCode;
 use serde::Serialize;
+use serde_json::json;
 use spin_sdk::http::Response;
-use todo_logic::{IdentifyableTodoItem, TodoStore};
+use todo_logic::{IdentifyableTodoItem, TodoStore, TodoStoreError};
 
 // Rather naive, manual responders. Anybody wants to write a framework for that? 😉
 
@@ -23,10 +24,27 @@ where
     }
 
     if let Some(todos) = todos {
-        let db = serde_json::to_string(&Into::<HashMap<usize, IdentifyableTodoItem>>::into(todos))?;
+        let db = serde_json::to_string(&HashMap::<usize, IdentifyableTodoItem>::try_from(todos)?)?;
         let db = format!("db={}", general_purpose::STANDARD_NO_PAD.encode(db));
         builder = builder.header("Set-Cookie", format!("{}; SameSite=Strict; Path=/", db));
     }
 
     Ok(builder.status(status).body(body.map(|body| body.into()))?)
 }
+
+/// Turn client errors into 4xx responses, like the `AppError` of the other servers do
+///
+/// Other errors are passed on, Spin answers them with 500 Internal Server Error.
+pub fn error_response(error: TodoStoreError) -> Result<Response> {
+    let status = match error {
+        TodoStoreError::InvalidQuery(_) | TodoStoreError::InvalidPatch(_) => StatusCode::BAD_REQUEST,
+        TodoStoreError::VersionMismatch { .. } => StatusCode::PRECONDITION_FAILED,
+        TodoStoreError::InvalidItem(_) | TodoStoreError::InvalidList(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        TodoStoreError::HasSubtasks { .. } | TodoStoreError::ListNotEmpty { .. } | TodoStoreError::PatchConflict(_) => {
+            StatusCode::CONFLICT
+        },
+        TodoStoreError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        _ => return Err(error.into()),
+    };
+    to_response(status, Some(json!({ "error": error.to_string() })), None)
+}
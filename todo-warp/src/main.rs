@@ -1,10 +1,16 @@
-use std::{convert::Infallible, sync::Arc};
+use std::sync::Arc;
 
-use log::{debug, LevelFilter};
+use log::{debug, error, LevelFilter};
+use serde::Deserialize;
 use simplelog::{Config, SimpleLogger};
-use todo_logic::{Pagination, TodoItem, TodoStore, TodoStoreError, UpdateTodoItem};
+use todo_logic::{
+    autosave, batch, etag, timestamp, trash, AsyncTodoStore, Batch, DeleteParams, IdentifyableTodoItem, JobQueue,
+    NewTodoList, Pagination, RevertTo, TodoFilter, TodoItem, TodoPatch, TodoQuery, TodoStore, TodoStoreConfig,
+    TodoStoreError, Validators,
+};
 use tokio::sync::RwLock;
-use warp::http::StatusCode;
+use warp::http::{header, HeaderMap, StatusCode};
+use warp::hyper::body::Bytes;
 use warp::{reject, reply};
 use warp::{Filter, Rejection, Reply};
 
@@ -18,8 +24,22 @@ async fn main() {
     // compatible logger, but for this example we'll use simplelog.
     SimpleLogger::init(LevelFilter::Debug, Config::default()).unwrap();
 
-    // Create shared data store
-    let db = Db::default();
+    // Create shared data store. The storage backend is selected with the
+    // TODO_BACKEND environment variable (e.g. `sqlite:todos.db`). Set TODO_RESTORE=true
+    // to restore the todo items that have been persisted before.
+    let config = TodoStoreConfig::from_env().expect("valid todo store configuration");
+    let store = config.open().await.expect("can open todo store");
+    let db = Db::new(RwLock::new(
+        store.with_jobs(JobQueue::start(config.jobs, &config.hooks)),
+    ));
+    if let Some(autosave) = config.autosave {
+        // Persist in the background so that we do not depend on clients calling /todos/persist
+        autosave::spawn(db.clone(), autosave, |e| error!("Autosave failed: {e:?}"));
+    }
+    if let Some(retention) = config.trash_retention {
+        // Deleted items are purged once they have been in the trash for TODO_TRASH_RETENTION seconds
+        trash::spawn(db.clone(), retention, |e| error!("Purging the trash failed: {e:?}"));
+    }
 
     // Note that you would probably create dedicated functions for each filter.
     // However, to make Warp's approach more obvious, we'll inline the filters.
@@ -31,6 +51,9 @@ async fn main() {
         .and(warp::get())
         // The query filter is used to extract the query parameters.
         .and(warp::query::<Pagination>())
+        .and(warp::query::<FilterParams>())
+        // All request headers, for conditional requests
+        .and(warp::header::headers_cloned())
         // Here we inject our shared state into the handler function.
         .and(warp::any().map(move || get_db.clone()))
         // ...and finally we connect the handler.
@@ -47,35 +70,159 @@ async fn main() {
     let get_single_db = db.clone();
     let get_single = warp::path!("todos" / usize)
         .and(warp::get())
+        .and(warp::header::headers_cloned())
         .and(warp::any().map(move || get_single_db.clone()))
         .and_then(get_todo);
 
+    let children_db = db.clone();
+    let children = warp::path!("todos" / usize / "children")
+        .and(warp::get())
+        .and(warp::any().map(move || children_db.clone()))
+        .and_then(get_children);
+
     let delete_db = db.clone();
     let delete = warp::path!("todos" / usize)
         .and(warp::delete())
+        // Optional headers are extracted as Option
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::query::<DeleteParams>())
         .and(warp::any().map(move || delete_db.clone()))
         .and_then(delete_todo);
 
     let update_db = db.clone();
     let update = warp::path!("todos" / usize)
         .and(warp::patch())
-        .and(warp::body::json())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::bytes())
         .and(warp::any().map(move || update_db.clone()))
         .and_then(update_todo);
 
+    let trash_db = db.clone();
+    let get_trash = warp::path!("todos" / "trash")
+        .and(warp::get())
+        .and(warp::any().map(move || trash_db.clone()))
+        .and_then(get_trash);
+
+    let restore_db = db.clone();
+    let restore = warp::path!("todos" / usize / "restore")
+        .and(warp::post())
+        .and(warp::any().map(move || restore_db.clone()))
+        .and_then(restore_todo);
+
+    let batch_db = db.clone();
+    let batch = warp::path!("todos" / "batch")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || batch_db.clone()))
+        .and_then(apply_batch);
+
+    let history_db = db.clone();
+    let history = warp::path!("todos" / usize / "history")
+        .and(warp::get())
+        .and(warp::any().map(move || history_db.clone()))
+        .and_then(get_history);
+
+    let revert_db = db.clone();
+    let revert = warp::path!("todos" / usize / "revert")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::json())
+        .and(warp::any().map(move || revert_db.clone()))
+        .and_then(revert_todo);
+
+    let purge_db = db.clone();
+    let purge = warp::path!("todos" / "trash" / usize)
+        .and(warp::delete())
+        .and(warp::any().map(move || purge_db.clone()))
+        .and_then(purge_todo);
+
+    let empty_trash_db = db.clone();
+    let empty_trash = warp::path!("todos" / "trash")
+        .and(warp::delete())
+        .and(warp::any().map(move || empty_trash_db.clone()))
+        .and_then(empty_trash);
+
     let persist_db = db.clone();
     let persist = warp::path!("todos" / "persist")
         .and(warp::post())
         .and(warp::any().map(move || persist_db.clone()))
-        .and_then(persist)
-        // The persist can handler can return a Rejection in case of an error.
-        // Rejections are handled by the `recover` filter. It turns the error
-        // object into a response.
-        .recover(handle_rejection);
+        .and_then(persist);
+
+    let jobs_db = db.clone();
+    let jobs = warp::path!("jobs")
+        .and(warp::get())
+        .and(warp::any().map(move || jobs_db.clone()))
+        .and_then(get_jobs);
+
+    let lists_db = db.clone();
+    let lists = warp::path!("lists")
+        .and(warp::get())
+        .and(warp::any().map(move || lists_db.clone()))
+        .and_then(get_lists);
+
+    let add_list_db = db.clone();
+    let add_list = warp::path!("lists")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || add_list_db.clone()))
+        .and_then(add_list);
+
+    let get_list_db = db.clone();
+    let get_list = warp::path!("lists" / usize)
+        .and(warp::get())
+        .and(warp::any().map(move || get_list_db.clone()))
+        .and_then(get_list);
+
+    let rename_list_db = db.clone();
+    let rename_list = warp::path!("lists" / usize)
+        .and(warp::patch())
+        .and(warp::body::json())
+        .and(warp::any().map(move || rename_list_db.clone()))
+        .and_then(rename_list);
+
+    let delete_list_db = db.clone();
+    let delete_list = warp::path!("lists" / usize)
+        .and(warp::delete())
+        .and(warp::query::<DeleteParams>())
+        .and(warp::any().map(move || delete_list_db.clone()))
+        .and_then(delete_list);
+
+    let list_todos_db = db.clone();
+    let list_todos = warp::path!("lists" / usize / "todos")
+        .and(warp::get())
+        .and(warp::query::<Pagination>())
+        .and(warp::query::<FilterParams>())
+        .and(warp::any().map(move || list_todos_db.clone()))
+        .and_then(get_list_todos);
 
     // The final API consists of all the filters we defined above
     // connected with the `or` combinator.
-    let api = get.or(add).or(get_single).or(delete).or(update).or(persist);
+    // The handlers can return a Rejection in case of an error.
+    // Rejections are handled by the `recover` filter. It turns the error
+    // object into a response.
+    let api = get
+        .or(add)
+        .or(get_single)
+        .or(children)
+        .or(delete)
+        .or(update)
+        .or(persist)
+        .or(jobs)
+        .or(get_trash)
+        .or(restore)
+        .or(batch)
+        .or(history)
+        .or(revert)
+        .or(purge)
+        .or(empty_trash)
+        .or(lists)
+        .or(add_list)
+        .or(get_list)
+        .or(rename_list)
+        .or(delete_list)
+        .or(list_todos)
+        .recover(handle_rejection);
 
     // For logging, we wrap the API with a wrapping filter (similar to a middleware
     // in other frameworks).
@@ -88,50 +235,329 @@ async fn main() {
 /// Note that we do not need any special handling of the parameters.
 /// The previously defined filters already extracted query parameters,
 /// body, path parameters, etc.
-async fn get_todos(pagination: Pagination, db: Db) -> Result<impl warp::Reply, Infallible> {
+///
+/// Clients that pass a `cursor` (empty for the first page) get a page envelope,
+/// all others get a plain list. In both cases, the Link header points to the next page.
+/// Polling clients can send the ETag or Last-Modified header of their copy and get
+/// 304 Not Modified if nothing has changed since.
+async fn get_todos(
+    pagination: Pagination,
+    filter: FilterParams,
+    headers: HeaderMap,
+    db: Db,
+) -> Result<impl warp::Reply, Rejection> {
+    let filter = TodoFilter::try_from(filter).map_err(reject_store_error)?;
     let todos = db.read().await;
-    Ok(reply::json(&todos.get_todos(pagination)))
+    let validators = todos.validators();
+    if is_not_modified(&headers, &validators) {
+        return Ok(not_modified(&validators));
+    }
+    let page = todos
+        .get_todos(pagination.clone(), &filter)
+        .map_err(reject_store_error)?;
+    let body = if pagination.is_cursor_mode() {
+        with_validators(reply::json(&page), &validators)
+    } else {
+        with_validators(reply::json(&page.items), &validators)
+    };
+    Ok(match pagination.next_link("/todos", &filter, &page) {
+        Some(link) => reply::with_header(body, header::LINK, link).into_response(),
+        None => body,
+    })
+}
+
+/// Query parameters for filtering todo items
+///
+/// The query in `q`, the priority and the date are parsed in the handler. If warp's
+/// query filter parsed them, clients would not get a helpful error message.
+#[derive(Deserialize)]
+struct FilterParams {
+    completed: Option<bool>,
+    assigned_to: Option<String>,
+    title: Option<String>,
+    notes: Option<String>,
+    priority: Option<String>,
+    tags: Option<String>,
+    due_by: Option<String>,
+    parent_id: Option<usize>,
+    list_id: Option<usize>,
+    q: Option<String>,
+}
+impl TryFrom<FilterParams> for TodoFilter {
+    type Error = TodoStoreError;
+
+    fn try_from(params: FilterParams) -> Result<Self, Self::Error> {
+        Ok(TodoFilter {
+            completed: params.completed,
+            assigned_to: params.assigned_to,
+            title: params.title,
+            notes: params.notes,
+            priority: params.priority.as_deref().map(str::parse).transpose()?,
+            tags: params.tags,
+            due_by: params.due_by.as_deref().map(timestamp::parse_date).transpose()?,
+            parent_id: params.parent_id,
+            list_id: params.list_id,
+            q: params.q.as_deref().map(TodoQuery::parse).transpose()?,
+        })
+    }
 }
 
 /// Get a single todo item
 ///
 /// Note that this method returns different return types.
 /// into_response converts the result into a reply.
-async fn get_todo(id: usize, db: Db) -> Result<impl warp::Reply, Infallible> {
+async fn get_todo(id: usize, headers: HeaderMap, db: Db) -> Result<impl warp::Reply, Rejection> {
     let todos = db.read().await;
-    if let Some(item) = todos.get_todo(id) {
-        Ok(reply::json(item).into_response())
+    if let Some(item) = todos.get_todo(id).map_err(reject_store_error)? {
+        let validators = item.validators();
+        if is_not_modified(&headers, &validators) {
+            return Ok(not_modified(&validators));
+        }
+        Ok(item_reply(&item))
     } else {
         Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response())
     }
 }
 
+/// Reply with a todo item and its validators
+fn item_reply(item: &IdentifyableTodoItem) -> reply::Response {
+    with_validators(reply::json(item), &item.validators())
+}
+
+/// Add ETag and Last-Modified headers to a reply
+fn with_validators(reply: impl Reply, validators: &Validators) -> reply::Response {
+    let reply = reply::with_header(reply, header::ETAG, validators.etag.clone());
+    reply::with_header(reply, header::LAST_MODIFIED, validators.last_modified_header()).into_response()
+}
+
+/// Whether the copy of the client is still up to date
+fn is_not_modified(headers: &HeaderMap, validators: &Validators) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    validators.not_modified(header(header::IF_NONE_MATCH), header(header::IF_MODIFIED_SINCE))
+}
+
+/// Empty 304 reply, the validators tell the client which copy is current
+fn not_modified(validators: &Validators) -> reply::Response {
+    with_validators(reply::with_status(reply(), StatusCode::NOT_MODIFIED), validators)
+}
+
+/// Version expected by the If-Match header, `None` if there is no such header
+fn expected_version(if_match: Option<String>) -> Result<Option<u64>, Rejection> {
+    match if_match {
+        Some(if_match) => etag::expected_version(&if_match).map_err(reject_store_error),
+        None => Ok(None),
+    }
+}
+
 /// Add a new todo item
-async fn add_todo(todo: TodoItem, db: Db) -> Result<impl warp::Reply, Infallible> {
-    let mut todos = db.write().await;
-    let todo = todos.add_todo(todo.clone());
+async fn add_todo(todo: TodoItem, db: Db) -> Result<impl warp::Reply, Rejection> {
+    let todo = db.add_todo(todo).await.map_err(reject_store_error)?;
     Ok(reply::json(&todo))
 }
 
-/// Delete a todo item
-async fn delete_todo(id: usize, db: Db) -> Result<impl warp::Reply, Infallible> {
-    if db.write().await.remove_todo(id).is_some() {
+/// Get the subtasks of a todo item
+///
+/// The response contains the item itself, its direct subtasks and the percentage
+/// of completed subtasks.
+async fn get_children(id: usize, db: Db) -> Result<impl warp::Reply, Rejection> {
+    match db.read().await.subtasks(id).map_err(reject_store_error)? {
+        Some(subtasks) => Ok(reply::json(&subtasks).into_response()),
+        None => Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
+/// Move a todo item to the trash
+///
+/// With an If-Match header, the item is only deleted if it has not been changed.
+/// Items with subtasks are only deleted with `?cascade=true`, together with the subtasks.
+async fn delete_todo(
+    id: usize,
+    if_match: Option<String>,
+    params: DeleteParams,
+    db: Db,
+) -> Result<impl warp::Reply, Rejection> {
+    let expected_version = expected_version(if_match)?;
+    if db
+        .remove_todo(id, expected_version, params.mode())
+        .await
+        .map_err(reject_store_error)?
+        .is_some()
+    {
         Ok(reply::with_status("", StatusCode::NO_CONTENT))
     } else {
         Ok(reply::with_status("", StatusCode::NOT_FOUND))
     }
 }
 
-/// Update a todo item
-async fn update_todo(id: usize, input: UpdateTodoItem, db: Db) -> Result<impl warp::Reply, Infallible> {
+/// Get the items in the trash
+async fn get_trash(db: Db) -> Result<impl warp::Reply, Rejection> {
+    Ok(reply::json(&db.read().await.trash()))
+}
+
+/// Restore a todo item from the trash
+///
+/// Subtasks can only be restored after their parent.
+async fn restore_todo(id: usize, db: Db) -> Result<impl warp::Reply, Rejection> {
+    match db.write().await.restore_todo(id).map_err(reject_store_error)? {
+        Some(item) => Ok(item_reply(&item)),
+        None => Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
+/// Create, update and delete todo items in a single request
+///
+/// The response contains one result per operation. It is 207 Multi-Status if any
+/// operation failed, with `"mode": "all_or_nothing"` nothing has been changed then.
+async fn apply_batch(batch: Batch, db: Db) -> Result<impl warp::Reply, Rejection> {
+    let results = db.apply_batch(batch).await.map_err(reject_store_error)?;
+    let status = StatusCode::from_u16(batch::status(&results)).unwrap_or(StatusCode::OK);
+    Ok(reply::with_status(reply::json(&results), status))
+}
+
+/// Get the changes of a todo item, oldest first
+///
+/// Items in the trash still have their history.
+async fn get_history(id: usize, db: Db) -> Result<impl warp::Reply, Rejection> {
+    match db.read().await.history(id).map_err(reject_store_error)? {
+        Some(history) => Ok(reply::json(&history).into_response()),
+        None => Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
+/// Set a todo item back to an earlier version from its history
+///
+/// With an If-Match header, the item is only reverted if it has not been changed.
+async fn revert_todo(
+    id: usize,
+    if_match: Option<String>,
+    revert: RevertTo,
+    db: Db,
+) -> Result<impl warp::Reply, Rejection> {
+    let expected_version = expected_version(if_match)?;
     let mut todos = db.write().await;
-    let res = todos.update_todo(&id, input);
+    let res = todos
+        .revert_todo(id, revert.version, expected_version)
+        .map_err(reject_store_error)?;
     match res {
-        Some(todo) => Ok(reply::json(todo).into_response()),
+        Some(item) => Ok(item_reply(&item)),
+        None => Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
+/// Remove a todo item from the trash for good
+async fn purge_todo(id: usize, db: Db) -> Result<impl warp::Reply, Rejection> {
+    if db.write().await.purge_todo(id).map_err(reject_store_error)?.is_some() {
+        Ok(reply::with_status("", StatusCode::NO_CONTENT))
+    } else {
+        Ok(reply::with_status("", StatusCode::NOT_FOUND))
+    }
+}
+
+/// Remove all todo items in the trash for good
+async fn empty_trash(db: Db) -> Result<impl warp::Reply, Rejection> {
+    db.write().await.empty_trash().map_err(reject_store_error)?;
+    Ok(reply::with_status("", StatusCode::NO_CONTENT))
+}
+
+/// Update a todo item
+///
+/// The body is a JSON Merge Patch or a JSON Patch, depending on the Content-Type, or
+/// the fields to set for `application/json`. With an If-Match header, the item is only
+/// updated if it has not been changed.
+async fn update_todo(
+    id: usize,
+    if_match: Option<String>,
+    content_type: Option<String>,
+    body: Bytes,
+    db: Db,
+) -> Result<impl warp::Reply, Rejection> {
+    let expected_version = expected_version(if_match)?;
+    let patch = TodoPatch::parse(content_type.as_deref(), &body).map_err(reject_store_error)?;
+    match db
+        .patch_todo(id, patch, expected_version)
+        .await
+        .map_err(reject_store_error)?
+    {
+        Some(todo) => Ok(item_reply(&todo)),
         None => Ok(reply::with_status("", StatusCode::NOT_FOUND).into_response()),
     }
 }
 
+/// Get all lists
+async fn get_lists(db: Db) -> Result<impl warp::Reply, Rejection> {
+    Ok(reply::json(&db.read().await.lists()))
+}
+
+/// Create a new list
+async fn add_list(list: NewTodoList, db: Db) -> Result<impl warp::Reply, Rejection> {
+    let list = db.write().await.add_list(list).map_err(reject_store_error)?;
+    Ok(reply::with_status(reply::json(&list), StatusCode::CREATED))
+}
+
+/// Get a single list
+async fn get_list(id: usize, db: Db) -> Result<impl warp::Reply, Rejection> {
+    match db.read().await.get_list(id) {
+        Some(list) => Ok(reply::json(&list).into_response()),
+        None => Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
+/// Rename a list
+async fn rename_list(id: usize, input: NewTodoList, db: Db) -> Result<impl warp::Reply, Rejection> {
+    match db.write().await.rename_list(id, input).map_err(reject_store_error)? {
+        Some(list) => Ok(reply::json(&list).into_response()),
+        None => Ok(reply::with_status("", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
+/// Delete a list
+///
+/// Lists with todo items are only deleted with `?cascade=true`, together with the items.
+async fn delete_list(id: usize, params: DeleteParams, db: Db) -> Result<impl warp::Reply, Rejection> {
+    if db
+        .write()
+        .await
+        .remove_list(id, params.mode())
+        .map_err(reject_store_error)?
+        .is_some()
+    {
+        Ok(reply::with_status("", StatusCode::NO_CONTENT))
+    } else {
+        Ok(reply::with_status("", StatusCode::NOT_FOUND))
+    }
+}
+
+/// Get the todo items of a list
+///
+/// Supports the same query parameters as get_todos. Items are moved to another list
+/// by patching their `list_id`.
+async fn get_list_todos(
+    id: usize,
+    pagination: Pagination,
+    filter: FilterParams,
+    db: Db,
+) -> Result<impl warp::Reply, Rejection> {
+    let filter = TodoFilter::try_from(filter).map_err(reject_store_error)?;
+    let todos = db.read().await;
+    let Some(page) = todos
+        .get_list_todos(id, pagination.clone(), &filter)
+        .map_err(reject_store_error)?
+    else {
+        return Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response());
+    };
+    let body = if pagination.is_cursor_mode() {
+        reply::json(&page).into_response()
+    } else {
+        reply::json(&page.items).into_response()
+    };
+    Ok(
+        match pagination.next_link(&format!("/lists/{id}/todos"), &filter, &page) {
+            Some(link) => reply::with_header(body, header::LINK, link).into_response(),
+            None => body,
+        },
+    )
+}
+
 /// Application-level error object
 #[derive(Debug)]
 enum AppError {
@@ -146,35 +572,84 @@ impl From<TodoStoreError> for AppError {
 /// Add marker trait to AppError for custom rejections
 impl reject::Reject for AppError {}
 
+/// Turns a todo store error into a custom rejection
+fn reject_store_error(e: TodoStoreError) -> Rejection {
+    warp::reject::custom::<AppError>(e.into())
+}
+
 async fn persist(db: Db) -> Result<impl warp::Reply, Rejection> {
     // Write a log message
     debug!("Persisting todos");
 
-    let todos = db.read().await;
-    todos
-        .persist()
+    db.persist()
         .await
         // In case of an error, we return a custom rejection. It will be handled
         // by teh `recover` filter.
-        .map_err(|e| warp::reject::custom::<AppError>(e.into()))?;
+        .map_err(reject_store_error)?;
     Ok::<_, Rejection>(reply::with_status("", StatusCode::OK).into_response())
 }
 
+/// Get the state of the background jobs that run the integrations
+async fn get_jobs(db: Db) -> Result<impl warp::Reply, Rejection> {
+    match db.read().await.job_status() {
+        Some(status) => Ok(reply::json(&status).into_response()),
+        None => Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response()),
+    }
+}
+
 /// Handles custom rejection and turns it into a response.
-async fn handle_rejection(err: Rejection) -> Result<impl Reply, std::convert::Infallible> {
+async fn handle_rejection(err: Rejection) -> Result<reply::Response, std::convert::Infallible> {
     if let Some(e) = err.find::<AppError>() {
         return match e {
-            AppError::UserRepo(e) => Ok(reply::with_status(
-                match e {
-                    TodoStoreError::FileAccessError(_) => "Error while writing to file",
-                    TodoStoreError::SerializationError(_) => "Error during serialization",
+            AppError::UserRepo(e) => Ok(match e {
+                TodoStoreError::FileAccessError(_) => {
+                    reply::with_status("Error while writing to file", StatusCode::INTERNAL_SERVER_ERROR)
+                },
+                TodoStoreError::SerializationError(_) => {
+                    reply::with_status("Error during serialization", StatusCode::INTERNAL_SERVER_ERROR)
+                },
+                TodoStoreError::DatabaseError(_) => {
+                    reply::with_status("Error while accessing the database", StatusCode::INTERNAL_SERVER_ERROR)
+                },
+                TodoStoreError::ConfigurationError(_) => {
+                    reply::with_status("Invalid store configuration", StatusCode::INTERNAL_SERVER_ERROR)
+                },
+                TodoStoreError::UnsupportedSnapshotVersion(_) => {
+                    reply::with_status("Unsupported snapshot version", StatusCode::INTERNAL_SERVER_ERROR)
+                },
+                // Tell the client what is wrong with the request
+                TodoStoreError::InvalidQuery(_) | TodoStoreError::InvalidPatch(_) => {
+                    return Ok(reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response())
                 },
-                StatusCode::INTERNAL_SERVER_ERROR,
-            )),
+                TodoStoreError::VersionMismatch { .. } => {
+                    return Ok(reply::with_status(e.to_string(), StatusCode::PRECONDITION_FAILED).into_response())
+                },
+                TodoStoreError::InvalidItem(_) | TodoStoreError::InvalidList(_) => {
+                    return Ok(reply::with_status(e.to_string(), StatusCode::UNPROCESSABLE_ENTITY).into_response())
+                },
+                TodoStoreError::HasSubtasks { .. }
+                | TodoStoreError::ListNotEmpty { .. }
+                | TodoStoreError::PatchConflict(_) => {
+                    return Ok(reply::with_status(e.to_string(), StatusCode::CONFLICT).into_response())
+                },
+                TodoStoreError::UnsupportedMediaType(_) => {
+                    return Ok(reply::with_status(e.to_string(), StatusCode::UNSUPPORTED_MEDIA_TYPE).into_response())
+                },
+            }
+            .into_response()),
         };
     }
-    Ok(reply::with_status(
-        "INTERNAL_SERVER_ERROR",
-        StatusCode::INTERNAL_SERVER_ERROR,
-    ))
+
+    // Rejections created by warp's own filters
+    if err.is_not_found() {
+        return Ok(reply::with_status("Not found", StatusCode::NOT_FOUND).into_response());
+    }
+    if err.find::<reject::InvalidQuery>().is_some() {
+        return Ok(reply::with_status("Invalid query", StatusCode::BAD_REQUEST).into_response());
+    }
+    // E.g. an unknown priority or a malformed due date in the JSON body
+    if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        return Ok(reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response());
+    }
+    Ok(reply::with_status("INTERNAL_SERVER_ERROR", StatusCode::INTERNAL_SERVER_ERROR).into_response())
 }